@@ -1,3 +0,0 @@
-mkdir -p shaders
-glslc src/shader/shader.vert -o shaders/vert.spv
-glslc src/shader/shader.frag -o shaders/frag.spv
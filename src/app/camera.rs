@@ -0,0 +1,405 @@
+use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_2;
+
+use nalgebra_glm as glm;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+// the scene uses the z-axis as up direction
+const UP: glm::Vec3 = glm::Vec3::new(0.0, 0.0, 1.0);
+
+// keep the pitch slightly away from straight up/down, because the view matrix
+// is undefined, if the view direction is parallel to the up vector
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+const MIN_DISTANCE: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// rotates around a target point; dragging rotates, scrolling zooms and
+    /// the movement keys move the target
+    Orbit,
+    /// moves freely through the scene; dragging looks around, the movement keys
+    /// move along the view direction
+    Fly,
+}
+
+/// camera, which is controlled by keyboard and mouse input of the window
+///
+/// the orientation is stored as yaw (rotation around the up axis, starting at
+/// the x-axis) and pitch (angle above the xy-plane) in radians; in orbit mode,
+/// this is the direction from the target to the camera, in fly mode the direction,
+/// in which the camera is looking
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub mode: CameraMode,
+    pub position: glm::Vec3,
+    pub target: glm::Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+
+    // vertical field of view in radians
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+
+    // units per second
+    pub move_speed: f32,
+    // radians per pixel the mouse is dragged
+    pub rotate_speed: f32,
+    // fraction of the distance per scrolled line
+    pub zoom_speed: f32,
+
+    pressed_keys: HashSet<VirtualKeyCode>,
+    dragging: bool,
+    last_cursor: Option<PhysicalPosition<f64>>,
+}
+
+impl Default for Camera {
+    /// orbits around the origin, looking at it from (2, 2, 2)
+    fn default() -> Self {
+        Self::orbit(glm::vec3(2.0, 2.0, 2.0), glm::vec3(0.0, 0.0, 0.0))
+    }
+}
+
+impl Camera {
+    /// creates an orbit camera at `position`, which rotates around `target`
+    ///
+    /// like rotating, the pitch is clamped, so a position straight above or
+    /// below the target is moved slightly to the side
+    pub fn orbit(position: glm::Vec3, target: glm::Vec3) -> Self {
+        let offset = position - target;
+        let distance = offset.norm().max(MIN_DISTANCE);
+
+        let mut camera = Self {
+            mode: CameraMode::Orbit,
+            position,
+            target,
+            yaw: offset.y.atan2(offset.x),
+            pitch: (offset.z / distance)
+                .clamp(-1.0, 1.0)
+                .asin()
+                .clamp(-MAX_PITCH, MAX_PITCH),
+            fov: 45.0_f32.to_radians(),
+            near: 0.1,
+            far: 100.0,
+            move_speed: 2.0,
+            rotate_speed: 0.005,
+            zoom_speed: 0.1,
+            pressed_keys: HashSet::new(),
+            dragging: false,
+            last_cursor: None,
+        };
+        camera.position = target + camera.direction() * distance;
+        camera
+    }
+
+    /// switches between orbit and fly mode without moving the camera
+    pub fn toggle_mode(&mut self) {
+        match self.mode {
+            CameraMode::Orbit => {
+                // look from the current position at the target
+                self.yaw += std::f32::consts::PI;
+                self.pitch = -self.pitch;
+                self.mode = CameraMode::Fly;
+            }
+            CameraMode::Fly => {
+                // orbit around the point, which is currently looked at, at the
+                // same distance as the previous target
+                let distance = self.distance();
+                self.target = self.position + self.forward() * distance;
+                self.yaw += std::f32::consts::PI;
+                self.pitch = -self.pitch;
+                self.mode = CameraMode::Orbit;
+            }
+        }
+
+        log::info!("Camera mode: {:?}", self.mode);
+    }
+
+    /// updates the input state of the camera; should be called for every event
+    /// of the window
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    // only toggle once, even if the key is held and repeated
+                    if *key == VirtualKeyCode::C && !self.pressed_keys.contains(key) {
+                        self.toggle_mode();
+                    }
+                    self.pressed_keys.insert(*key);
+                }
+                ElementState::Released => {
+                    self.pressed_keys.remove(key);
+                }
+            },
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(last)) = (self.dragging, self.last_cursor) {
+                    self.rotate(
+                        (position.x - last.x) as f32 * self.rotate_speed,
+                        (position.y - last.y) as f32 * self.rotate_speed,
+                    );
+                }
+                self.last_cursor = Some(*position);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.last_cursor = None;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // roughly 20 pixels per line
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / 20.0,
+                };
+                self.zoom(lines);
+            }
+            // released keys are not reported, if the window loses focus
+            WindowEvent::Focused(false) => {
+                self.pressed_keys.clear();
+                self.dragging = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// moves the camera according to the currently pressed keys; `dt` is the
+    /// time since the last update in seconds
+    pub fn update(&mut self, dt: f32) {
+        let axis = |positive: VirtualKeyCode, negative: VirtualKeyCode| {
+            let pressed = |k| self.pressed_keys.contains(&k) as i32 as f32;
+            pressed(positive) - pressed(negative)
+        };
+
+        let forward = axis(VirtualKeyCode::W, VirtualKeyCode::S);
+        let right = axis(VirtualKeyCode::D, VirtualKeyCode::A);
+        let up = axis(VirtualKeyCode::E, VirtualKeyCode::Q);
+
+        if forward == 0.0 && right == 0.0 && up == 0.0 {
+            return;
+        }
+
+        let step = self.move_speed * dt;
+
+        match self.mode {
+            CameraMode::Orbit => {
+                // move the target in the ground plane, relative to the view
+                // direction, so "forward" always moves away from the camera
+                let view_forward = -self.direction();
+                let flat_forward = glm::normalize(&glm::vec3(view_forward.x, view_forward.y, 0.0));
+                let flat_right = glm::cross(&flat_forward, &UP);
+                let offset = (flat_forward * forward + flat_right * right + UP * up) * step;
+                self.target += offset;
+                self.position += offset;
+            }
+            CameraMode::Fly => {
+                let forward_direction = self.forward();
+                let right_direction = glm::normalize(&glm::cross(&forward_direction, &UP));
+                self.position +=
+                    (forward_direction * forward + right_direction * right + UP * up) * step;
+            }
+        }
+    }
+
+    /// rotates the camera by the given angles (in radians); dragging to the right
+    /// turns the view to the right, dragging down lowers the view
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        match self.mode {
+            // orbiting moves the camera around the target, which turns the view
+            // in the opposite direction
+            CameraMode::Orbit => {
+                let distance = self.distance();
+                self.yaw -= yaw;
+                self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+                self.position = self.target + self.direction() * distance;
+            }
+            CameraMode::Fly => {
+                self.yaw -= yaw;
+                self.pitch = (self.pitch - pitch).clamp(-MAX_PITCH, MAX_PITCH);
+            }
+        }
+    }
+
+    /// zooms in by the given number of scrolled lines (negative values zoom out)
+    pub fn zoom(&mut self, lines: f32) {
+        match self.mode {
+            CameraMode::Orbit => {
+                let distance = self.distance() * (1.0 - self.zoom_speed).powf(lines);
+                self.position = self.target + self.direction() * distance.max(MIN_DISTANCE);
+            }
+            CameraMode::Fly => {
+                self.position += self.forward() * lines * self.zoom_speed * self.move_speed;
+            }
+        }
+    }
+
+    /// view matrix for the uniform buffer object
+    pub fn view(&self) -> glm::Mat4 {
+        let center = match self.mode {
+            CameraMode::Orbit => self.target,
+            CameraMode::Fly => self.position + self.forward(),
+        };
+
+        glm::look_at(&self.position, &center, &UP)
+    }
+
+    /// projection matrix for the uniform buffer object; `aspect` is the ratio of
+    /// width to height of the render target
+    pub fn projection(&self, aspect: f32) -> glm::Mat4 {
+        // we want to use the Vulkan depth range of 0.0 to 1.0 (and not the OpenGL
+        // depth range of -1.0 to 1.0); zo = zero-to-one
+        let mut proj = glm::perspective_rh_zo(aspect, self.fov, self.near, self.far);
+
+        // GLM was originally designed for OpenGL, where the Y coord of the clip
+        // coordinated is inverted; easiest way to compensate, is to flip sign on
+        // the scaling factor of the Y axis in the projection matrix; if we don't
+        // do this, the image will be rendered upside down
+        proj[(1, 1)] *= -1.0;
+        proj
+    }
+
+    // unit vector given by yaw and pitch
+    fn direction(&self) -> glm::Vec3 {
+        glm::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        )
+    }
+
+    // direction, in which the camera is looking
+    fn forward(&self) -> glm::Vec3 {
+        match self.mode {
+            CameraMode::Orbit => -self.direction(),
+            CameraMode::Fly => self.direction(),
+        }
+    }
+
+    fn distance(&self) -> f32 {
+        (self.position - self.target).norm().max(MIN_DISTANCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: glm::Vec4, expected: glm::Vec4) {
+        assert!(
+            (actual - expected).norm() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn orbit_view_looks_at_target() {
+        let camera = Camera::orbit(glm::vec3(3.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0));
+        let view = camera.view();
+
+        // the camera looks along -z in view space, with the up axis pointing up
+        assert_close(
+            view * glm::vec4(1.0, 0.0, 0.0, 1.0),
+            glm::vec4(0.0, 0.0, -2.0, 1.0),
+        );
+        assert_close(
+            view * glm::vec4(0.0, 0.0, 1.0, 0.0),
+            glm::vec4(0.0, 1.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn fly_view_looks_along_yaw_and_pitch() {
+        let mut camera = Camera::orbit(glm::vec3(3.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 0.0));
+        let orbit_view = camera.view();
+
+        // switching the mode doesn't move the camera
+        camera.toggle_mode();
+        assert_eq!(camera.mode, CameraMode::Fly);
+        let view = camera.view();
+        for i in 0..4 {
+            assert_close(view.column(i).into(), orbit_view.column(i).into());
+        }
+
+        camera.position = glm::vec3(0.0, 0.0, 0.0);
+        camera.yaw = FRAC_PI_2;
+        camera.pitch = 0.0;
+        let view = camera.view();
+        assert_close(
+            view * glm::vec4(0.0, 2.0, 0.0, 1.0),
+            glm::vec4(0.0, 0.0, -2.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn pitch_is_clamped() {
+        for mode in [CameraMode::Orbit, CameraMode::Fly] {
+            let mut camera = Camera {
+                mode,
+                ..Camera::default()
+            };
+
+            camera.rotate(0.0, 10.0);
+            assert_eq!(camera.pitch.abs(), MAX_PITCH);
+            camera.rotate(0.0, -20.0);
+            assert_eq!(camera.pitch.abs(), MAX_PITCH);
+        }
+    }
+
+    #[test]
+    fn orbit_straight_above_target_is_clamped() {
+        let camera = Camera::orbit(glm::vec3(0.0, 0.0, 5.0), glm::vec3(0.0, 0.0, 0.0));
+
+        assert_eq!(camera.pitch, MAX_PITCH);
+        assert!((camera.position.norm() - 5.0).abs() < 1e-5);
+        assert!(camera.view().iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn zoom_keeps_minimum_distance() {
+        let mut camera = Camera::default();
+
+        camera.zoom(1000.0);
+        assert!((camera.distance() - MIN_DISTANCE).abs() < 1e-5);
+        assert!((camera.position - camera.target).norm() >= MIN_DISTANCE - 1e-5);
+
+        camera.zoom(-10.0);
+        assert!(camera.distance() > MIN_DISTANCE);
+    }
+
+    #[test]
+    fn projection_flips_y() {
+        let camera = Camera::default();
+        let proj = camera.projection(1.0);
+
+        assert!(proj[(1, 1)] < 0.0);
+
+        // a point above the view direction ends up in the upper half of the
+        // image, which has negative y in vulkan
+        let clip = proj * glm::vec4(0.0, 1.0, -5.0, 1.0);
+        assert!(clip.y / clip.w < 0.0);
+        // depth range is 0.0 to 1.0
+        let near = proj * glm::vec4(0.0, 0.0, -camera.near, 1.0);
+        let far = proj * glm::vec4(0.0, 0.0, -camera.far, 1.0);
+        assert!((near.z / near.w).abs() < 1e-5);
+        assert!((far.z / far.w - 1.0).abs() < 1e-5);
+    }
+}
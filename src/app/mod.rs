@@ -1,61 +1,103 @@
+mod camera;
+
 // logging
 #[allow(dead_code, unused_variables, unused_imports)]
 use anyhow::{anyhow, Result};
 
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::prelude::v1_0::*;
-use vulkanalia::vk::{ExtDebugUtilsExtension, KhrSurfaceExtension, KhrSwapchainExtension};
-use vulkanalia::window as vk_window;
+use vulkanalia::vk::KhrSwapchainExtension;
 
 use winit::window::Window;
 
+use std::path::Path;
+use std::sync::Arc;
+
 use nalgebra_glm as glm;
 
+use crate::config::Config;
+use crate::render::allocator::{self, SharedAllocator};
 use crate::render::framebuffer;
+use crate::render::gltf_model;
+use crate::render::handle::{
+    Buffer, CommandPool, DescriptorPool, DescriptorSetLayout, Fence, Framebuffer, Image, ImageView,
+    OwnedDevice, OwnedInstance, Pipeline, PipelineLayout, RenderPass, Sampler, Semaphore,
+    Swapchain,
+};
 use crate::render::instance;
+use crate::render::model;
+use crate::render::offscreen;
 use crate::render::pipeline;
+use crate::render::reflection::ShaderReflection;
 use crate::render::render_pass;
+use crate::render::shader::{self, ShaderWatcher};
+use crate::render::shadow::{self, ShadowMap};
+use crate::render::skybox::{self, Skybox};
 use crate::render::swapchain;
 use crate::render::synchronization;
-use crate::render::validation;
+use crate::render::tonemap::{self, ToneMapper};
+use crate::render::upload::{self, UploadManager};
 use crate::render::{command_buffer, descriptor_set};
 use crate::render::{command_pool, descriptor_pool};
 use crate::render::{device, image};
-use std::mem::size_of;
+use crate::scene::{Lighting, Material, Mesh, Scene};
 use std::ptr::copy_nonoverlapping as memcpy;
 
-use std::collections::VecDeque;
+pub use camera::Camera;
+
+use std::collections::{HashSet, VecDeque};
 use std::{thread, time};
 
-#[derive(Clone, Debug)]
+/// the renderer; all vulkan objects are destroyed, when the app is dropped
+#[derive(Debug)]
 pub struct App {
-    entry: Entry,
-    instance: Instance,
+    // the instance and the device are only destroyed, after all objects created
+    // from them were dropped, so the order of the fields does not matter
+    instance: Arc<OwnedInstance>,
     data: AppData,
-    pub device: Device,
+    device: Arc<OwnedDevice>,
     // current frame index for multiple frames in flight
     frame: usize,
     pub resized: bool,
+    // view and projection are controlled by the input events of the window
+    pub camera: Camera,
+    // node of the scene, which is spinning
+    root_node: usize,
+    // reports changes of the shader sources, so the pipeline can be rebuilt
+    // while the app is running; not used in headless mode
+    shader_watcher: Option<ShaderWatcher>,
+    last_camera_update: time::Instant,
     last_frame_end: time::Instant,
     samples: VecDeque<u128>,
     frame_counter: u32,
-    sleep_in_render: bool,
+    // minimum time between the start of two frames, if the frame rate is capped
+    min_frame_time: Option<time::Duration>,
+    last_frame_start: time::Instant,
     count_fps: bool,
     start: time::Instant,
 }
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
 pub const FRAME_SAMPLE_COUNT: usize = 20;
-//pub const SLEEP_IN_RENDER: bool = true;
-pub const SLEEP_TIME_IN_MS: u32 = 16;
 
-#[derive(Clone, Debug, Default)]
+// the vulkan objects are wrapped in owning handles (see render::handle), which
+// destroy them on drop; raw handles are either owned by another object (e.g. the
+// swapchain images or the descriptor sets) or only copies of an owned handle
+#[derive(Debug, Default)]
 pub struct AppData {
+    // both are owned by the instance
     pub surface: vk::SurfaceKHR,
     pub messenger: vk::DebugUtilsMessengerEXT,
-    // this will be implicitly destroyed, if the instance is destroyed,
-    // so no further handling of this in App::destroy() required
+    // this will be implicitly destroyed, if the instance is destroyed
     pub physical_device: vk::PhysicalDevice,
+    // all physical devices, which were considered, and why they were rejected
+    pub device_report: device::DeviceReport,
+    // required and optional device extensions, which were enabled for the
+    // logical device
+    pub enabled_extensions: HashSet<vk::ExtensionName>,
+
+    // hands out sub-ranges of large memory blocks to buffers and images; the
+    // memory is returned, when the buffer or image is dropped
+    pub allocator: SharedAllocator,
 
     // queues, which will be created along with logic device creation
     // queues are implicitly cleaned up, when the device is destroyed
@@ -65,140 +107,283 @@ pub struct AppData {
     // device
     pub present_queue: vk::Queue,
 
+    // queue for uploads; the graphics queue, if the device has no separate
+    // transfer queue family
+    pub transfer_queue: vk::Queue,
+    // records uploads and submits them to the transfer queue
+    pub uploads: UploadManager,
+
+    // set, if there is no window to present to; the scene is rendered into
+    // an offscreen image, which can be saved as png
+    pub headless: bool,
+
+    // settings from the configuration (see config::Config)
+    pub validation: bool,
+    pub present_mode: vk::PresentModeKHR,
+    pub frames_in_flight: usize,
+
     // swapchain related data
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
-    pub swapchain: vk::SwapchainKHR,
+    pub swapchain: Swapchain,
+    // owned by the swapchain (or the offscreen image)
     pub swapchain_images: Vec<vk::Image>,
 
     // image views
-    pub swapchain_image_views: Vec<vk::ImageView>,
-
-    pub render_pass: vk::RenderPass,
-    pub descriptor_set_layout: vk::DescriptorSetLayout,
-    pub pipeline_layout: vk::PipelineLayout,
-
-    pub pipeline: vk::Pipeline,
-
-    pub framebuffers: Vec<vk::Framebuffer>,
-
-    pub command_pool: vk::CommandPool,
+    pub swapchain_image_views: Vec<ImageView>,
+
+    // offscreen color image, which replaces the swapchain images in headless mode
+    pub offscreen_image: Image,
+
+    pub render_pass: RenderPass,
+    pub descriptor_set_layout: DescriptorSetLayout,
+    pub pipeline_layout: PipelineLayout,
+
+    pub pipeline: Pipeline,
+    // SPIR-V code of the last successfully compiled shaders
+    pub vertex_shader_code: Vec<u32>,
+    pub fragment_shader_code: Vec<u32>,
+    // descriptors and vertex inputs used by the shaders
+    pub shader_reflection: ShaderReflection,
+
+    // the main render pass draws into the HDR image, which is shared by all
+    // swapchain images
+    pub framebuffer: Framebuffer,
+    // maps the HDR image into the swapchain image
+    pub tone_mapper: ToneMapper,
+
+    // pool for short lived command buffers, e.g. for uploads
+    pub command_pool: CommandPool,
+    // one command pool and command buffer per frame in flight; the command buffer
+    // is reset and recorded again every frame (and freed along with its pool)
+    pub frame_command_pools: Vec<CommandPool>,
     pub command_buffers: Vec<vk::CommandBuffer>,
 
-    pub image_ready_semaphores: Vec<vk::Semaphore>,
-    pub render_finished_semaphores: Vec<vk::Semaphore>,
+    pub image_ready_semaphores: Vec<Semaphore>,
+    pub render_finished_semaphores: Vec<Semaphore>,
 
-    pub in_flight_fences: Vec<vk::Fence>,
+    pub in_flight_fences: Vec<Fence>,
 
     // used to keep track of which images are currently in flight
-    // acquire_next_image_khr may return images out of order or MAX_FRAMES_IN_FLIGHT
+    // acquire_next_image_khr may return images out of order or frames_in_flight
     // could be higher than the number of swapchain images, so we could end up
     // rendering to a swapchain image, that is already in flight
+    // (copies of the in_flight_fences)
     pub images_in_flight: Vec<vk::Fence>,
 
+    // geometry, which is uploaded into the vertex and index buffer
+    pub vertices: Vec<pipeline::Vertex>,
+    pub indices: Vec<u32>,
+
     // vertex input & buffer
-    pub vertex_buffer: vk::Buffer,
-    pub vertex_buffer_memory: vk::DeviceMemory,
+    pub vertex_buffer: Buffer,
 
-    pub index_buffer: vk::Buffer,
-    pub index_buffer_memory: vk::DeviceMemory,
+    pub index_buffer: Buffer,
 
-    pub uniform_buffers: Vec<vk::Buffer>,
-    pub uniform_buffers_memory: Vec<vk::DeviceMemory>,
-    pub descriptor_pool: vk::DescriptorPool,
+    pub uniform_buffers: Vec<Buffer>,
+    // lights uniform block, one per swapchain image like the uniform buffers
+    pub light_buffers: Vec<Buffer>,
+    pub descriptor_pool: DescriptorPool,
+    // freed along with the descriptor pool
     pub descriptor_sets: Vec<vk::DescriptorSet>,
 
-    pub texture_image: vk::Image,
-    pub texture_image_memory: vk::DeviceMemory,
-    pub texture_image_view: vk::ImageView,
-
-    pub texture_sampler: vk::Sampler,
+    // all textures share the same sampler
+    pub textures: Vec<image::Texture>,
+    pub texture_sampler: Sampler,
+
+    // the nodes, which are drawn into each frame
+    pub scene: Scene,
+    // rendered before the scene, so the fragment shader can look up shadows
+    pub shadow: ShadowMap,
+    // drawn behind the scene, if a cube map is configured
+    pub skybox: Option<Skybox>,
+    // color of the background (RGBA)
+    pub clear_color: [f32; 4],
+
+    // multisample anti-aliasing: number of samples per pixel and the
+    // multisampled color image, which is resolved into the HDR image
+    pub msaa_samples: vk::SampleCountFlags,
+    pub color_image_view: ImageView,
+    pub color_image: Image,
+
+    // the scene in linear colors, which may exceed 1.0
+    pub hdr_image_view: ImageView,
+    pub hdr_image: Image,
 
     // depth buffering is also image based
-    pub depth_image: vk::Image,
-    pub depth_image_memory: vk::DeviceMemory,
-    pub depth_image_view: vk::ImageView,
+    pub depth_image_view: ImageView,
+    pub depth_image: Image,
 }
 
-// TODO: expose own safe wrapper around vulkan calls, which asserts the calling
-// of the correct invariants of the vulkan API functions
+// the public functions are safe: the app owns every vulkan object it uses, so
+// they are valid as long as the app exists, and waits for the device to become
+// idle, before it destroys any of them
 impl App {
-    /// creates the app
-    pub unsafe fn create(window: &Window) -> Result<Self> {
-        // create library loader for the vulkan library (LIBRARY is a constant
-        // path pointing to the Vulkan library)
-        // this loads initial Vulkan commands from the library
-        let loader = LibloadingLoader::new(LIBRARY)?;
-        // load the entry point of the Vulkan library using the loader
-        let entry = Entry::new(loader).map_err(|e| anyhow!("{}", e))?;
+    /// creates the app, which renders to the given window
+    pub fn create(window: &Window, config: &Config) -> Result<Self> {
+        unsafe { Self::init(Some(window), config) }
+    }
+
+    /// creates the app without a window; the scene is rendered into an offscreen
+    /// image of the window size of the config, which can be saved with
+    /// `render_to_png`
+    ///
+    /// this does not need any window system, so it can be used with a software
+    /// vulkan driver (e.g. lavapipe) on build servers
+    pub fn create_headless(config: &Config) -> Result<Self> {
+        unsafe { Self::init(None, config) }
+    }
+
+    // the window size of the config is only used in headless mode, otherwise
+    // the extent is derived from the window
+    unsafe fn init(window: Option<&Window>, config: &Config) -> Result<Self> {
+        let entry = load_entry()?;
         // use the window and entry to create a vulkan instance
-        let mut data = AppData::default();
-        let instance = instance::create_instance(window, &entry, &mut data)?;
+        let mut data = AppData {
+            headless: window.is_none(),
+            validation: config.validation,
+            present_mode: config.present_mode.to_vk(),
+            frames_in_flight: config.frames_in_flight,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            ..Default::default()
+        };
+        // the instance also creates the window surface
+        let instance = instance::create_instance(window, entry, &mut data)?;
 
-        // setup window surface
-        data.surface = vk_window::create_surface(&instance, window)?;
+        device::pick_physical_device(&instance, &mut data, config.device.as_ref())?;
 
-        device::pick_physical_device(&instance, &mut data)?;
-        let device = device::create_logical_device(&instance, &mut data)?;
+        // number of samples for multisample anti-aliasing; 1 disables it
+        device::pick_msaa_samples(&instance, &mut data, config.msaa_samples)?;
 
-        swapchain::create_swapchain(window, &instance, &device, &mut data)?;
+        let device = device::create_logical_device(&instance, &mut data)?;
+        allocator::create_allocator(&instance, &device, &mut data)?;
+
+        match window {
+            Some(window) => swapchain::create_swapchain(window, &instance, &device, &mut data)?,
+            None => offscreen::create_offscreen_target(
+                &instance,
+                &device,
+                &mut data,
+                config.window.width,
+                config.window.height,
+            )?,
+        }
         render_pass::create_render_pass(&instance, &device, &mut data)?;
+        // the descriptor set layout is derived from the compiled shaders
+        pipeline::compile_shaders(&mut data)?;
         descriptor_set::create_descriptor_set_layout(&device, &mut data)?;
         pipeline::create_pipeline(&device, &mut data)?;
         swapchain::create_swapchain_image_views(&device, &mut data)?;
-        framebuffer::create_framebuffers(&device, &mut data)?;
         command_pool::create_command_pool(&instance, &device, &mut data)?;
+        upload::create_upload_manager(&instance, &device, &mut data)?;
+        command_pool::create_frame_command_pools(&instance, &device, &mut data)?;
+        // the color, depth and HDR image views are attached to the framebuffer, so they need to exist first
+        image::create_color_objects(&instance, &device, &mut data)?;
         image::create_depth_objects(&instance, &device, &mut data)?;
-        image::create_texture_image(&instance, &device, &mut data)?;
-        image::create_texture_image_view(&device, &mut data)?;
+        image::create_hdr_objects(&instance, &device, &mut data)?;
+        framebuffer::create_framebuffer(&device, &mut data)?;
+        tonemap::create_tone_mapper(&device, &mut data, &config.tone_mapping)?;
+        tonemap::create_tone_map_pass(&device, &mut data)?;
+        let mut texture =
+            image::create_texture_image(&instance, &device, &mut data, &config.texture)?;
+        image::create_texture_image_view(&device, &mut texture)?;
+        data.textures.push(texture);
+
+        // load the model given in the config or fall back to the built-in geometry
+        let root_node = match &config.model {
+            Some(path) if gltf_model::is_gltf(path) => {
+                let model = gltf_model::load_gltf(path)?;
+                let root_node = data
+                    .scene
+                    .add_node("model", None, glm::identity(), None, 0)?;
+                // glTF uses the y-axis as up direction, the scene the z-axis
+                let y_up = glm::rotate_x(&glm::identity(), glm::half_pi());
+                let y_up_node = data
+                    .scene
+                    .add_node("y-up", Some(root_node), y_up, None, 0)?;
+                gltf_model::create_gltf_scene(&instance, &device, &mut data, model, y_up_node)?;
+                root_node
+            }
+            _ => {
+                (data.vertices, data.indices) = match &config.model {
+                    Some(path) => model::load_model(path)?,
+                    None => (pipeline::VERTICES.clone(), pipeline::INDICES.to_vec()),
+                };
+
+                // the whole geometry is a single mesh, which is drawn by the root node
+                let mesh = data.scene.add_mesh(Mesh {
+                    first_index: 0,
+                    index_count: data.indices.len() as u32,
+                    vertex_offset: 0,
+                });
+                let material = data.scene.add_material(Material::textured(0));
+                data.scene
+                    .add_node("model", None, glm::identity(), Some(mesh), material)?
+            }
+        };
+        // the shared sampler has to cover the mip chains of all textures
         image::create_texture_sampler(&device, &mut data)?;
+        // the projection of the light is fitted to the scene
+        shadow::create_shadow_map(&instance, &device, &mut data, &config.shadow)?;
+        if let Some(path) = &config.skybox {
+            data.skybox = Some(skybox::create_skybox(&instance, &device, &mut data, path)?);
+            skybox::create_skybox_pipeline(&device, &mut data)?;
+        }
+
         pipeline::create_vertex_buffer(&instance, &device, &mut data)?;
         pipeline::create_index_buffer(&instance, &device, &mut data)?;
+        // the texture, vertex and index uploads are executed, while the rest of
+        // the app is set up; the first frame is ordered after them on the queue
+        data.uploads.flush()?;
         pipeline::create_uniform_buffers(&instance, &device, &mut data)?;
         descriptor_pool::create_descriptor_pool(&device, &mut data)?;
         descriptor_set::create_descriptor_sets(&device, &mut data)?;
         command_buffer::create_command_buffers(&device, &mut data)?;
         synchronization::create_sync_objects(&device, &mut data)?;
 
-        let sleep = dotenv::var("SLEEP_IN_RENDER").unwrap();
-        println!("Sleep: {0}", sleep);
-
-        let sleep_bool = match sleep.as_str() {
-            "0" => false,
-            _ => true,
-        };
-
-        let fps = dotenv::var("FPS_COUNTER").unwrap();
-        println!("fps counter: {0}", fps);
-
-        let fps_bool = match fps.as_str() {
-            "0" => false,
-            _ => true,
+        // hot reloading is a convenience, so the app also works without it
+        let shader_watcher = if window.is_some() {
+            ShaderWatcher::new(Path::new(shader::SHADER_DIR))
+                .map_err(|e| log::warn!("Shader hot reloading disabled: {}", e))
+                .ok()
+        } else {
+            None
         };
 
         Ok(Self {
-            entry,
             instance,
             data,
             device,
             frame: 0,
             resized: false,
+            camera: Camera::default(),
+            root_node,
+            shader_watcher,
+            last_camera_update: time::Instant::now(),
             last_frame_end: time::Instant::now(),
             start: time::Instant::now(),
             samples: VecDeque::with_capacity(FRAME_SAMPLE_COUNT),
             frame_counter: 0,
-            sleep_in_render: sleep_bool,
-            count_fps: fps_bool,
+            min_frame_time: config
+                .frame_cap
+                .map(|fps| time::Duration::from_secs_f64(1.0 / fps as f64)),
+            last_frame_start: time::Instant::now(),
+            count_fps: config.fps_counter,
         })
     }
 
     /// renders one frame
-    pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
-        self.device.wait_for_fences(
-            &[self.data.in_flight_fences[self.frame]],
-            true,
-            u64::max_value(),
-        )?;
+    pub fn render(&mut self, window: &Window) -> Result<()> {
+        unsafe { self.draw_frame(window) }
+    }
+
+    unsafe fn draw_frame(&mut self, window: &Window) -> Result<()> {
+        self.reload_changed_shaders()?;
+        // releases the staging buffers of completed uploads
+        self.data.uploads.poll()?;
+
+        self.device
+            .wait_for_fences(&[*self.data.in_flight_fences[self.frame]], true, u64::MAX)?;
 
         // Each of the actions required for rendering is executed by calling
         // a single function, which executes asynchronously -> requires synchronization
@@ -209,9 +394,9 @@ impl App {
         // - Semaphores: state can't be queried from program, used to synchronize
         //   rendering internally
         let result = self.device.acquire_next_image_khr(
-            self.data.swapchain,
-            u64::max_value(),
-            self.data.image_ready_semaphores[self.frame],
+            *self.data.swapchain,
+            u64::MAX,
+            *self.data.image_ready_semaphores[self.frame],
             vk::Fence::null(),
         );
 
@@ -224,26 +409,32 @@ impl App {
             Err(e) => return Err(anyhow!(e)),
         };
 
-        // TODO: verstehen
-        if !self.data.images_in_flight[image_index].is_null() {
-            self.device.wait_for_fences(
-                &[self.data.images_in_flight[image_index]],
-                true,
-                u64::max_value(),
-            )?;
-        }
-
-        self.data.images_in_flight[image_index] = self.data.in_flight_fences[self.frame];
+        self.wait_for_image(image_index)?;
 
         // it is important, that the uniform buffer is not updated, before
         // the fence is signaled; we need to be sure, that any previously
         // rendered frame to the acquired swapchain image is completed, before
         // savely updating the data in the uniform buffer
+        let now = time::Instant::now();
+        self.camera
+            .update((now - self.last_camera_update).as_secs_f32());
+        self.last_camera_update = now;
+
+        self.update_scene();
         self.update_uniform_buffer(image_index)?;
 
-        let wait_semaphores = &[self.data.image_ready_semaphores[self.frame]];
-        let command_buffers = &[self.data.command_buffers[image_index]];
-        let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
+        // the scene may have changed since the last frame, so the commands are
+        // recorded again; the fence of this frame was waited for above
+        command_buffer::record_command_buffer(
+            &self.device,
+            &mut self.data,
+            self.frame,
+            image_index,
+        )?;
+
+        let wait_semaphores = &[*self.data.image_ready_semaphores[self.frame]];
+        let command_buffers = &[self.data.command_buffers[self.frame]];
+        let signal_semaphores = &[*self.data.render_finished_semaphores[self.frame]];
         let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
 
         let submit_info = vk::SubmitInfo::builder()
@@ -255,15 +446,15 @@ impl App {
             .signal_semaphores(signal_semaphores); // which semaphores should be signaled on finish
 
         self.device
-            .reset_fences(&[self.data.in_flight_fences[self.frame]])?;
+            .reset_fences(&[*self.data.in_flight_fences[self.frame]])?;
 
         self.device.queue_submit(
             self.data.graphics_queue,
             &[submit_info],
-            self.data.in_flight_fences[self.frame], // TODO: explain
+            *self.data.in_flight_fences[self.frame], // TODO: explain
         )?;
 
-        let swapchains = &[self.data.swapchain];
+        let swapchains = &[*self.data.swapchain];
         let image_indices = &[image_index as u32];
         let present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(signal_semaphores) // what sem to wait for, before presentation happens
@@ -287,7 +478,9 @@ impl App {
             return Err(anyhow!(e));
         }
 
-        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.frame = (self.frame + 1) % self.data.frames_in_flight;
+
+        self.limit_frame_rate();
 
         //
         // --- fps counter ---
@@ -305,7 +498,7 @@ impl App {
                 self.samples.pop_back();
             }
 
-            self.frame_counter = self.frame_counter + 1;
+            self.frame_counter += 1;
             if self.frame_counter == FRAME_SAMPLE_COUNT as u32 {
                 let avg: u128 =
                     self.samples.iter().sum::<u128>() / self.samples.len() as u128 / 1000;
@@ -313,68 +506,234 @@ impl App {
                 log::info!("Avg frame time: {} us, fps: {}", avg, fps);
                 self.frame_counter = 0;
             }
+        }
+        Ok(())
+    }
 
-            if self.sleep_in_render {
-                thread::sleep(time::Duration::from_millis(SLEEP_TIME_IN_MS.into()));
+    // sleeps for the rest of the minimum frame time, if the frame rate is capped
+    fn limit_frame_rate(&mut self) {
+        if let Some(min_frame_time) = self.min_frame_time {
+            let elapsed = self.last_frame_start.elapsed();
+            if elapsed < min_frame_time {
+                thread::sleep(min_frame_time - elapsed);
             }
         }
+        self.last_frame_start = time::Instant::now();
+    }
+
+    /// the lights of the scene; changes are visible in the next frame
+    pub fn lighting_mut(&mut self) -> &mut Lighting {
+        &mut self.data.scene.lighting
+    }
+
+    /// the exposure and the operator of the tone mapping; changes are visible in
+    /// the next frame
+    pub fn tone_mapper_mut(&mut self) -> &mut ToneMapper {
+        &mut self.data.tone_mapper
+    }
+
+    /// the physical devices, which were considered, when the app was created
+    pub fn device_report(&self) -> &device::DeviceReport {
+        &self.data.device_report
+    }
+
+    /// lists the physical devices, whether they can be used and which one would
+    /// be selected; there is no window, so they are checked like for headless
+    /// rendering (i.e. without swapchain support)
+    pub fn physical_devices(config: &Config) -> Result<device::DeviceReport> {
+        unsafe {
+            let entry = load_entry()?;
+            let mut data = AppData {
+                headless: true,
+                validation: config.validation,
+                ..Default::default()
+            };
+            let instance = instance::create_instance(None, entry, &mut data)?;
+            let candidates = device::rate_physical_devices(&instance, &data)?;
+            // a device, which does not match or can't be used, is not an error here
+            let selected = device::select_physical_device(&candidates, config.device.as_ref()).ok();
+            Ok(device::DeviceReport {
+                candidates,
+                selected,
+            })
+        }
+    }
+
+    /// renders the given number of frames into the offscreen image and writes
+    /// the last one to a png file
+    ///
+    /// only available for apps created with `create_headless`
+    pub fn render_to_png(&mut self, path: &Path, frames: u32) -> Result<()> {
+        if !self.data.headless {
+            return Err(anyhow!("Rendering to png requires a headless app."));
+        }
+
+        for frame in 1..=frames {
+            let output = (frame == frames).then_some(path);
+            unsafe { self.draw_offscreen_frame(output)? };
+        }
         Ok(())
     }
 
-    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
+    // waits, until the last frame, which rendered into the image, is finished,
+    // and marks the image as used by the current frame; the resources of the
+    // image (like the uniform buffers) are safe to update afterwards
+    unsafe fn wait_for_image(&mut self, image_index: usize) -> Result<()> {
+        // TODO: verstehen
+        if !self.data.images_in_flight[image_index].is_null() {
+            self.device.wait_for_fences(
+                &[self.data.images_in_flight[image_index]],
+                true,
+                u64::MAX,
+            )?;
+        }
+
+        self.data.images_in_flight[image_index] = *self.data.in_flight_fences[self.frame];
+
+        Ok(())
+    }
+
+    // the image is only read back, if there is an output path
+    unsafe fn draw_offscreen_frame(&mut self, path: Option<&Path>) -> Result<()> {
+        self.data.uploads.poll()?;
+        self.device
+            .wait_for_fences(&[*self.data.in_flight_fences[self.frame]], true, u64::MAX)?;
+
+        // there is only a single offscreen image, so there is no image to acquire;
+        // its uniform buffers may still be read by the previous frame, which
+        // used another fence, if there is more than one frame in flight
+        let image_index = 0;
+        self.wait_for_image(image_index)?;
+
+        self.update_scene();
+        self.update_uniform_buffer(image_index)?;
+        command_buffer::record_command_buffer(
+            &self.device,
+            &mut self.data,
+            self.frame,
+            image_index,
+        )?;
+
+        let command_buffers = &[self.data.command_buffers[self.frame]];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+        self.device
+            .reset_fences(&[*self.data.in_flight_fences[self.frame]])?;
+
+        self.device.queue_submit(
+            self.data.graphics_queue,
+            &[submit_info],
+            *self.data.in_flight_fences[self.frame],
+        )?;
+
+        if let Some(path) = path {
+            // the image needs to be completely rendered, before it can be read back
+            self.device.wait_for_fences(
+                &[*self.data.in_flight_fences[self.frame]],
+                true,
+                u64::MAX,
+            )?;
+
+            offscreen::save_offscreen_image(&self.instance, &self.device, &mut self.data, path)?;
+        }
+
+        self.frame = (self.frame + 1) % self.data.frames_in_flight;
+
+        Ok(())
+    }
+
+    // rebuilds the pipelines, of which one of the shaders was modified
+    unsafe fn reload_changed_shaders(&mut self) -> Result<()> {
+        let changed = match &self.shader_watcher {
+            Some(watcher) => watcher.changed(),
+            None => return Ok(()),
+        };
+
+        let uses_changed_shader =
+            |uses_shader: fn(&Path) -> bool| changed.iter().any(|p| uses_shader(p));
+        let main = uses_changed_shader(pipeline::uses_shader);
+        let shadow = uses_changed_shader(shadow::uses_shader);
+        let skybox = uses_changed_shader(skybox::uses_shader);
+        let tone_map = uses_changed_shader(tonemap::uses_shader);
+
+        if !(main || shadow || skybox || tone_map) {
+            return Ok(());
+        }
+
+        // the pipelines may still be used by frames in flight
+        self.device.device_wait_idle()?;
+
+        if main {
+            pipeline::reload_pipeline(&self.device, &mut self.data);
+        }
+        if shadow {
+            shadow::reload_shadow_pipeline(&self.device, &mut self.data);
+        }
+        if skybox {
+            skybox::reload_skybox_pipeline(&self.device, &mut self.data);
+        }
+        if tone_map {
+            tonemap::reload_tone_map_pipeline(&self.device, &mut self.data);
+        }
+
+        Ok(())
+    }
+
+    // animates the scene; the transforms are passed to the shaders as push
+    // constants, when the command buffer is recorded
+    fn update_scene(&mut self) {
         let time = self.start.elapsed().as_secs_f32();
-        // define model view projection transformations in the ubo
 
         // model rotation will be around the z-axis using time
         // rotate 90 degrees per second
-        let model = glm::rotate(
+        self.data.scene.nodes[self.root_node].transform = glm::rotate(
             &glm::identity(),                         // existing transformation
             time * glm::radians(&glm::vec1(90.0))[0], // rotation angle
             &glm::vec3(0.0, 0.0, 1.0),                // rotation axis
         );
+    }
 
-        // look at geometry from aboce at 45 degree angle
-        let view = glm::look_at(
-            &glm::vec3(2.0, 2.0, 2.0), // position of the camera
-            &glm::vec3(0.0, 0.0, 0.0), // where to look at
-            &glm::vec3(0.0, 0.0, 1.0), // where is up
-        );
-
-        // we want to use the Vulkan depth range of 0.0 to 1.0 (and not the OpenGL
-        // depth range of -1.0 to 1.0); zo = zero-to-one
-        let mut proj = glm::perspective_rh_zo(
+    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
+        // define view projection transformations in the ubo; these are the same
+        // for all nodes of the scene
+        let view = self.camera.view();
+        let proj = self.camera.projection(
             self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32, // aspect ratio
-            glm::radians(&glm::vec1(45.0))[0], // fov
-            0.1,                               // near plane
-            10.0,                              // far plane
         );
 
-        // GLM was originally designed for OpenGL, where the Y coord of the clip
-        // coordinated is inverted; easiest way to compensate, is to flip sign on
-        // the scaling factor of the Y axis in the projection matrix; if we don't
-        // do this, the image will be rendered upside down
-        proj[(1, 1)] *= -1.0;
-        let ubo = descriptor_set::UniformBufferObject { model, view, proj };
-
-        // update uniform buffer memory
-        let memory = self.device.map_memory(
-            self.data.uniform_buffers_memory[image_index],
-            0,
-            size_of::<descriptor_set::UniformBufferObject>() as u64,
-            vk::MemoryMapFlags::empty(),
-        )?;
+        // without a shadow caster, the shadow map is rendered, but not sampled
+        let light_view_proj = match self.data.scene.lighting.shadow_caster() {
+            Some((_, direction)) => {
+                shadow::light_view_proj(&direction, self.data.shadow.scene_radius)
+            }
+            None => glm::identity(),
+        };
+
+        let ubo = descriptor_set::UniformBufferObject {
+            view,
+            proj,
+            light_view_proj,
+        };
 
+        // update uniform buffer memory (host visible memory stays mapped by the
+        // allocator)
+        let memory = self.data.uniform_buffers[image_index].mapped()?;
+
+        // per draw data (the model matrix) is passed as push constant instead
         memcpy(&ubo, memory.cast(), 1);
 
-        // updating the ubo in this way is not the most efficient way to update
-        // data in shaders frequently (that would be a push constant for small data)
-        self.device
-            .unmap_memory(self.data.uniform_buffers_memory[image_index]);
+        // the lights may be changed at any time, so they are uploaded every frame
+        let lights = descriptor_set::LightsBufferObject::new(
+            &self.data.scene.lighting,
+            self.camera.position,
+        );
+        let memory = self.data.light_buffers[image_index].mapped()?;
+        memcpy(&lights, memory.cast(), 1);
 
         Ok(())
     }
 
-    pub unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
         log::debug!("Recreating swapchain");
 
         // wait for device to become idle
@@ -385,103 +744,78 @@ impl App {
         swapchain::create_swapchain_image_views(&self.device, &mut self.data)?;
         render_pass::create_render_pass(&self.instance, &self.device, &mut self.data)?;
         pipeline::create_pipeline(&self.device, &mut self.data)?;
-        framebuffer::create_framebuffers(&self.device, &mut self.data)?;
+        skybox::create_skybox_pipeline(&self.device, &mut self.data)?;
+        // the color and depth images have the size of the swapchain images, so they change as well
+        image::create_color_objects(&self.instance, &self.device, &mut self.data)?;
+        image::create_depth_objects(&self.instance, &self.device, &mut self.data)?;
+        image::create_hdr_objects(&self.instance, &self.device, &mut self.data)?;
+        framebuffer::create_framebuffer(&self.device, &mut self.data)?;
+        tonemap::create_tone_map_pass(&self.device, &mut self.data)?;
         pipeline::create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
         descriptor_pool::create_descriptor_pool(&self.device, &mut self.data)?;
         descriptor_set::create_descriptor_sets(&self.device, &mut self.data)?;
-        command_buffer::create_command_buffers(&self.device, &mut self.data)?;
         self.data
             .images_in_flight
             .resize(self.data.swapchain_images.len(), vk::Fence::null());
         Ok(())
     }
 
-    /// destroy the app
-    pub unsafe fn destroy(&mut self) {
-        self.device.device_wait_idle().unwrap();
-
-        self.device.destroy_sampler(self.data.texture_sampler, None);
-
-        self.device
-            .destroy_image_view(self.data.texture_image_view, None);
-        self.device.destroy_image(self.data.texture_image, None);
-        self.device
-            .free_memory(self.data.texture_image_memory, None);
-
-        self.destroy_swapchain();
-
-        self.device
-            .destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
-
-        self.device.destroy_buffer(self.data.index_buffer, None);
-        self.device.free_memory(self.data.index_buffer_memory, None);
-
-        self.device.destroy_buffer(self.data.vertex_buffer, None);
-        self.device
-            .free_memory(self.data.vertex_buffer_memory, None);
-
-        self.data
-            .in_flight_fences
-            .iter()
-            .for_each(|f| self.device.destroy_fence(*f, None));
-
-        self.data
-            .render_finished_semaphores
-            .iter()
-            .for_each(|s| self.device.destroy_semaphore(*s, None));
-
-        self.data
-            .image_ready_semaphores
-            .iter()
-            .for_each(|s| self.device.destroy_semaphore(*s, None));
-
-        // destroying a command pool will free all ressources of the associated
-        // command buffers
-        self.device
-            .destroy_command_pool(self.data.command_pool, None);
-
-        // None is for allocation callbacks
-        self.device.destroy_device(None);
-
-        // if validation is enabled, the debug messenger needs to be destroyed,
-        // before the instance is destroyed
-        if validation::ENABLED {
-            self.instance
-                .destroy_debug_utils_messenger_ext(self.data.messenger, None);
+    // drops the objects, which depend on the swapchain (or its extent), so they
+    // can be created again for the new swapchain; dependent objects are dropped
+    // before the objects they refer to
+    fn destroy_swapchain(&mut self) {
+        let data = &mut self.data;
+        // the descriptor sets are freed along with the pool
+        data.descriptor_sets.clear();
+        data.descriptor_pool = Default::default();
+        data.uniform_buffers.clear();
+        data.light_buffers.clear();
+        data.tone_mapper.framebuffers.clear();
+        data.tone_mapper.pipeline = Default::default();
+        data.tone_mapper.render_pass = Default::default();
+        data.framebuffer = Default::default();
+        data.pipeline = Default::default();
+        if let Some(skybox) = &mut data.skybox {
+            skybox.pipeline = Default::default();
         }
-
-        self.instance.destroy_surface_khr(self.data.surface, None);
-        // be explicit about it
-        self.instance.destroy_instance(None);
+        data.pipeline_layout = Default::default();
+        data.render_pass = Default::default();
+        data.color_image_view = Default::default();
+        data.color_image = Default::default();
+        data.hdr_image_view = Default::default();
+        data.hdr_image = Default::default();
+        data.depth_image_view = Default::default();
+        data.depth_image = Default::default();
+        data.swapchain_image_views.clear();
+        // only one of these exists; the swapchain has to be destroyed, before a
+        // new one can be created for the surface
+        data.swapchain_images.clear();
+        data.swapchain = Default::default();
+        data.offscreen_image = Default::default();
     }
+}
 
-    unsafe fn destroy_swapchain(&self) {
-        self.device
-            .destroy_descriptor_pool(self.data.descriptor_pool, None);
+impl Drop for App {
+    fn drop(&mut self) {
+        log::debug!("Destroying app");
 
-        self.data
-            .uniform_buffers
-            .iter()
-            .for_each(|b| self.device.destroy_buffer(*b, None));
-        self.data
-            .uniform_buffers_memory
-            .iter()
-            .for_each(|m| self.device.free_memory(*m, None));
+        // none of the objects may be in use by the device, when they are destroyed
+        if let Err(e) = unsafe { self.device.device_wait_idle() } {
+            log::error!("Failed to wait for the device: {}", e);
+        }
 
-        self.data
-            .framebuffers
-            .iter()
-            .for_each(|f| self.device.destroy_framebuffer(*f, None));
-        self.device
-            .free_command_buffers(self.data.command_pool, &self.data.command_buffers);
-        self.device.destroy_pipeline(self.data.pipeline, None);
-        self.device
-            .destroy_pipeline_layout(self.data.pipeline_layout, None);
-        self.device.destroy_render_pass(self.data.render_pass, None);
-        self.data
-            .swapchain_image_views
-            .iter()
-            .for_each(|v| self.device.destroy_image_view(*v, None));
-        self.device.destroy_swapchain_khr(self.data.swapchain, None);
+        // the remaining objects don't depend on each other (except for the
+        // textures, which destroy their view first), so they can be dropped in
+        // any order; the device and the instance are destroyed last
+        self.destroy_swapchain();
     }
 }
+
+// create library loader for the vulkan library (LIBRARY is a constant path
+// pointing to the Vulkan library); this loads initial Vulkan commands from the
+// library, which are used to load the entry point
+unsafe fn load_entry() -> Result<Entry> {
+    let loader = LibloadingLoader::new(LIBRARY)
+        .map_err(|e| anyhow!("Failed to load the Vulkan library: {}", e))?;
+    Entry::new(loader).map_err(|e| anyhow!("{}", e))
+}
@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::builder::BoolishValueParser;
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::{Config, DeviceSelector, PresentMode, ToneMapOperator};
+
+/// renders textured models with vulkan
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub options: ConfigArgs,
+    // opens the viewer with the model of the config, if there is no subcommand
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Open a window, which shows the model
+    View {
+        /// Wavefront obj or glTF (.gltf, .glb) file
+        model: PathBuf,
+        #[command(flatten)]
+        texture: TextureArg,
+    },
+    /// Render the model without a window and save the last frame as png
+    Render {
+        /// Wavefront obj or glTF (.gltf, .glb) file
+        model: PathBuf,
+        #[command(flatten)]
+        texture: TextureArg,
+        /// Png file, which the last frame is written to
+        #[arg(short, long, value_name = "FILE")]
+        out: PathBuf,
+        /// Number of frames to render
+        #[arg(long, value_name = "N", default_value_t = 1,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        frames: u32,
+    },
+    /// List the physical devices and whether they can be used
+    Info,
+}
+
+#[derive(Debug, Args)]
+pub struct TextureArg {
+    /// Png image, which is mapped onto the model (glTF files bring their own)
+    #[arg(long, value_name = "FILE")]
+    pub texture: Option<PathBuf>,
+}
+
+/// flags, which override the options of the config file and the environment
+/// (see config::Config)
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// Config file [default: $CONFIG_PATH or config.toml, if it exists]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Width of the window (or of the image, if rendering without a window)
+    #[arg(long, global = true)]
+    pub width: Option<u32>,
+    /// Height of the window (or of the image, if rendering without a window)
+    #[arg(long, global = true)]
+    pub height: Option<u32>,
+    /// Title of the window
+    #[arg(long, global = true)]
+    pub title: Option<String>,
+    /// Preferred present mode: fifo, fifo_relaxed, mailbox or immediate
+    #[arg(long, global = true, value_name = "MODE")]
+    pub present_mode: Option<PresentMode>,
+    /// Number of frames, which may be processed at the same time
+    #[arg(long, global = true, value_name = "N")]
+    pub frames_in_flight: Option<usize>,
+    /// Number of samples for multisample anti-aliasing (1 disables it)
+    #[arg(long, global = true, value_name = "N")]
+    pub msaa_samples: Option<u32>,
+    /// Enable the validation layers
+    #[arg(long, global = true, value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    pub validation: Option<bool>,
+    /// Physical device to use, by index or (part of its) name (see the info command)
+    #[arg(long, global = true, value_name = "DEVICE")]
+    pub device: Option<DeviceSelector>,
+    /// Cube map behind the scene: a directory with the faces posx.png, negx.png,
+    /// posy.png, negy.png, posz.png and negz.png or an equirectangular png
+    #[arg(long, global = true, value_name = "PATH")]
+    pub skybox: Option<PathBuf>,
+    /// Maximum number of frames per second
+    #[arg(long, global = true, value_name = "FPS")]
+    pub frame_cap: Option<u32>,
+    /// Log the average frame time
+    #[arg(long, global = true)]
+    pub fps_counter: bool,
+    /// Width and height of the shadow map
+    #[arg(long, global = true, value_name = "TEXELS")]
+    pub shadow_resolution: Option<u32>,
+    /// Constant depth bias of the shadow map
+    #[arg(long, global = true, value_name = "BIAS")]
+    pub shadow_depth_bias: Option<f32>,
+    /// Depth bias of the shadow map, which scales with the slope of the polygons
+    #[arg(long, global = true, value_name = "BIAS")]
+    pub shadow_slope_bias: Option<f32>,
+    /// Tone mapping operator: clamp, reinhard or aces
+    #[arg(long, global = true, value_name = "OPERATOR")]
+    pub tone_mapping: Option<ToneMapOperator>,
+    /// Factor, which the colors are multiplied with before tone mapping
+    #[arg(long, global = true)]
+    pub exposure: Option<f32>,
+}
+
+impl Cli {
+    /// loads the config and applies the flags and the model and texture of the
+    /// subcommand; the result is validated
+    pub fn config(&self) -> Result<Config> {
+        let mut config = Config::load(self.options.config.as_deref())?;
+        self.options.apply(&mut config);
+
+        match &self.command {
+            Some(Command::View { model, texture })
+            | Some(Command::Render { model, texture, .. }) => {
+                config.model = Some(model.clone());
+                if let Some(texture) = &texture.texture {
+                    config.texture = texture.clone();
+                }
+            }
+            Some(Command::Info) | None => {}
+        }
+
+        if let Some(Command::Render { out, .. }) = &self.command {
+            check_output_path(out)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl ConfigArgs {
+    fn apply(&self, config: &mut Config) {
+        if let Some(width) = self.width {
+            config.window.width = width;
+        }
+        if let Some(height) = self.height {
+            config.window.height = height;
+        }
+        if let Some(title) = &self.title {
+            config.window.title = title.clone();
+        }
+        if let Some(present_mode) = self.present_mode {
+            config.present_mode = present_mode;
+        }
+        if let Some(frames_in_flight) = self.frames_in_flight {
+            config.frames_in_flight = frames_in_flight;
+        }
+        if let Some(msaa_samples) = self.msaa_samples {
+            config.msaa_samples = msaa_samples;
+        }
+        if let Some(validation) = self.validation {
+            config.validation = validation;
+        }
+        if let Some(device) = &self.device {
+            config.device = Some(device.clone());
+        }
+        if let Some(skybox) = &self.skybox {
+            config.skybox = Some(skybox.clone());
+        }
+        if let Some(frame_cap) = self.frame_cap {
+            config.frame_cap = Some(frame_cap);
+        }
+        if self.fps_counter {
+            config.fps_counter = true;
+        }
+        if let Some(resolution) = self.shadow_resolution {
+            config.shadow.resolution = resolution;
+        }
+        if let Some(depth_bias) = self.shadow_depth_bias {
+            config.shadow.depth_bias = depth_bias;
+        }
+        if let Some(slope_bias) = self.shadow_slope_bias {
+            config.shadow.slope_bias = slope_bias;
+        }
+        if let Some(operator) = self.tone_mapping {
+            config.tone_mapping.operator = operator;
+        }
+        if let Some(exposure) = self.exposure {
+            config.tone_mapping.exposure = exposure;
+        }
+    }
+}
+
+// the image is only written after rendering, so a bad path should be reported
+// before the renderer is set up
+fn check_output_path(path: &Path) -> Result<()> {
+    let is_png = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+    if !is_png {
+        return Err(anyhow!(
+            "Output file {} must have the extension .png.",
+            path.display()
+        ));
+    }
+
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) if !dir.is_dir() => Err(anyhow!(
+            "Directory {} of the output file does not exist.",
+            dir.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the flags are applied on top of the file and the environment
+    #[test]
+    fn flags_override_the_config() {
+        let mut config = Config::from_toml("msaa_samples = 4\nframes_in_flight = 3").unwrap();
+        config
+            .apply_env(|name| (name == "MSAA_SAMPLES").then(|| "8".to_string()))
+            .unwrap();
+
+        let cli = Cli::try_parse_from(["vulkanalia-tut", "--msaa-samples", "2"]).unwrap();
+        cli.options.apply(&mut config);
+
+        assert_eq!(config.msaa_samples, 2);
+        assert_eq!(config.frames_in_flight, 3);
+    }
+
+    #[test]
+    fn invalid_flags_are_rejected() {
+        assert!(Cli::try_parse_from(["vulkanalia-tut", "--present-mode", "vsync"]).is_err());
+        assert!(Cli::try_parse_from(["vulkanalia-tut", "--frames-in-flight", "-1"]).is_err());
+    }
+}
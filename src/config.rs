@@ -0,0 +1,618 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use thiserror::Error;
+use vulkanalia::vk;
+
+// the configuration file is optional, if it is not given explicitly
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {message}")]
+    Read { path: PathBuf, message: String },
+    #[error("Failed to parse config file {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("Invalid value '{value}' of {variable}: {message}")]
+    InvalidValue {
+        variable: &'static str,
+        value: String,
+        message: String,
+    },
+    #[error("Invalid configuration:\n  {}", .0.join("\n  "))]
+    Invalid(Vec<String>),
+}
+
+/// preferred presentation mode of the swapchain; FIFO is used, if the preferred
+/// mode is not supported by the surface
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    // wait for vertical blank, never tears (always supported)
+    Fifo,
+    // like fifo, but presents late images immediately
+    FifoRelaxed,
+    // replace the queued image with newer ones, never tears
+    Mailbox,
+    // present immediately, may tear
+    Immediate,
+}
+
+impl PresentMode {
+    pub fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            Self::Fifo => vk::PresentModeKHR::FIFO,
+            Self::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            Self::Mailbox => vk::PresentModeKHR::MAILBOX,
+            Self::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+}
+
+impl FromStr for PresentMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(Self::Fifo),
+            "fifo_relaxed" => Ok(Self::FifoRelaxed),
+            "mailbox" => Ok(Self::Mailbox),
+            "immediate" => Ok(Self::Immediate),
+            _ => Err("expected fifo, fifo_relaxed, mailbox or immediate".to_string()),
+        }
+    }
+}
+
+/// operator, which maps the HDR colors of the scene to the range of the display
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapOperator {
+    // no tone mapping, values above 1.0 are clipped
+    Clamp,
+    // c / (1 + c), keeps the hue, but desaturates bright colors
+    Reinhard,
+    // approximation of the ACES filmic curve, with more contrast
+    #[default]
+    Aces,
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(Self::Clamp),
+            "reinhard" => Ok(Self::Reinhard),
+            "aces" => Ok(Self::Aces),
+            _ => Err("expected clamp, reinhard or aces".to_string()),
+        }
+    }
+}
+
+/// selects a physical device by its position in the enumeration order or by
+/// (a part of) its name, ignoring case; e.g. `device = 1` or `device = "nvidia"`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum DeviceSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Name(n) => name.to_lowercase().contains(&n.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(i) => write!(f, "index {}", i),
+            Self::Name(n) => write!(f, "name '{}'", n),
+        }
+    }
+}
+
+// numbers are indices, everything else is a name
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse()
+            .map(Self::Index)
+            .unwrap_or_else(|_| Self::Name(s.to_string())))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    // logical size, which is scaled by the dpi of the monitor; also the size of
+    // the image in headless mode
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            title: "Vulkanalia Tutorial".to_string(),
+        }
+    }
+}
+
+/// the shadow map, which is rendered from the view of the first directional light
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowConfig {
+    // width and height of the shadow map in texels
+    pub resolution: u32,
+    // depth bias, which is added to the depth of the shadow map to avoid shadow
+    // acne: a constant offset (in units of the depth format) and a factor of the
+    // slope of the polygon
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            depth_bias: 1.25,
+            slope_bias: 1.75,
+        }
+    }
+}
+
+/// the tone mapping pass, which maps the HDR image of the scene into the
+/// swapchain image
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMappingConfig {
+    pub operator: ToneMapOperator,
+    // the colors are multiplied with the exposure before the operator is applied
+    pub exposure: f32,
+}
+
+impl Default for ToneMappingConfig {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::default(),
+            exposure: 1.0,
+        }
+    }
+}
+
+/// settings of the renderer
+///
+/// the values are layered: defaults, the TOML file, environment variables and
+/// command line flags (see cli::Cli), where later layers override earlier ones
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub present_mode: PresentMode,
+    // number of frames, which may be processed by the gpu at the same time
+    pub frames_in_flight: usize,
+    // number of samples for multisample anti-aliasing; 1 disables it (clamped
+    // to the maximum supported by the device)
+    pub msaa_samples: u32,
+    // enables the validation layers
+    pub validation: bool,
+    // forces a physical device; the best suitable device is used, if there is none
+    pub device: Option<DeviceSelector>,
+    pub texture: PathBuf,
+    // wavefront obj or gltf file; the built-in geometry is used, if there is none
+    pub model: Option<PathBuf>,
+    // cube map, which is drawn behind the scene: a directory with the faces
+    // posx.png, negx.png, posy.png, negy.png, posz.png and negz.png or a single
+    // equirectangular png; there is no skybox, if there is none
+    pub skybox: Option<PathBuf>,
+    // maximum number of frames per second; unlimited, if there is none
+    pub frame_cap: Option<u32>,
+    // logs the average frame time
+    pub fps_counter: bool,
+    pub shadow: ShadowConfig,
+    pub tone_mapping: ToneMappingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window: WindowConfig::default(),
+            present_mode: PresentMode::Mailbox,
+            frames_in_flight: 2,
+            msaa_samples: 1,
+            validation: cfg!(debug_assertions),
+            device: None,
+            texture: PathBuf::from("resources/texture.png"),
+            model: None,
+            skybox: None,
+            frame_cap: None,
+            fps_counter: false,
+            shadow: ShadowConfig::default(),
+            tone_mapping: ToneMappingConfig::default(),
+        }
+    }
+}
+
+// each option can be set by its key in the config file and by an environment
+// variable
+struct ConfigOption {
+    key: &'static str,
+    env: &'static str,
+}
+
+const OPTIONS: &[ConfigOption] = &[
+    ConfigOption {
+        key: "window.width",
+        env: "WINDOW_WIDTH",
+    },
+    ConfigOption {
+        key: "window.height",
+        env: "WINDOW_HEIGHT",
+    },
+    ConfigOption {
+        key: "window.title",
+        env: "WINDOW_TITLE",
+    },
+    ConfigOption {
+        key: "present_mode",
+        env: "PRESENT_MODE",
+    },
+    ConfigOption {
+        key: "frames_in_flight",
+        env: "FRAMES_IN_FLIGHT",
+    },
+    ConfigOption {
+        key: "msaa_samples",
+        env: "MSAA_SAMPLES",
+    },
+    ConfigOption {
+        key: "validation",
+        env: "VALIDATION",
+    },
+    ConfigOption {
+        key: "device",
+        env: "GPU_DEVICE",
+    },
+    ConfigOption {
+        key: "texture",
+        env: "TEXTURE_PATH",
+    },
+    ConfigOption {
+        key: "model",
+        env: "MODEL_PATH",
+    },
+    ConfigOption {
+        key: "skybox",
+        env: "SKYBOX_PATH",
+    },
+    ConfigOption {
+        key: "frame_cap",
+        env: "FRAME_CAP",
+    },
+    ConfigOption {
+        key: "fps_counter",
+        env: "FPS_COUNTER",
+    },
+    ConfigOption {
+        key: "shadow.resolution",
+        env: "SHADOW_RESOLUTION",
+    },
+    ConfigOption {
+        key: "shadow.depth_bias",
+        env: "SHADOW_DEPTH_BIAS",
+    },
+    ConfigOption {
+        key: "shadow.slope_bias",
+        env: "SHADOW_SLOPE_BIAS",
+    },
+    ConfigOption {
+        key: "tone_mapping.operator",
+        env: "TONE_MAPPING",
+    },
+    ConfigOption {
+        key: "tone_mapping.exposure",
+        env: "EXPOSURE",
+    },
+];
+
+impl Config {
+    /// loads the configuration from the given file (or the file given by the
+    /// CONFIG_PATH environment variable, or config.toml, if it exists) and
+    /// applies the environment variables
+    ///
+    /// the result is not validated yet, as the command line flags are applied
+    /// afterwards
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("CONFIG_PATH").map(PathBuf::from));
+
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Read {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        Self::from_toml(&text).map_err(|message| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// overrides the options, for which `lookup` returns a value; empty values
+    /// are ignored, so variables can be left blank in .env
+    pub fn apply_env(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), ConfigError> {
+        for option in OPTIONS {
+            if let Some(value) = lookup(option.env).filter(|v| !v.is_empty()) {
+                self.set(option, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // sets a single option from the value of its environment variable
+    fn set(&mut self, option: &ConfigOption, value: &str) -> Result<(), ConfigError> {
+        let invalid = |message: String| ConfigError::InvalidValue {
+            variable: option.env,
+            value: value.to_string(),
+            message,
+        };
+
+        match option.key {
+            "window.width" => self.window.width = parse(value).map_err(invalid)?,
+            "window.height" => self.window.height = parse(value).map_err(invalid)?,
+            "window.title" => self.window.title = value.to_string(),
+            "present_mode" => self.present_mode = value.parse().map_err(invalid)?,
+            "frames_in_flight" => self.frames_in_flight = parse(value).map_err(invalid)?,
+            "msaa_samples" => self.msaa_samples = parse(value).map_err(invalid)?,
+            "validation" => self.validation = parse_bool(value).map_err(invalid)?,
+            "device" => self.device = Some(value.parse().map_err(invalid)?),
+            "texture" => self.texture = PathBuf::from(value),
+            "model" => self.model = Some(PathBuf::from(value)),
+            "skybox" => self.skybox = Some(PathBuf::from(value)),
+            "frame_cap" => self.frame_cap = Some(parse(value).map_err(invalid)?),
+            "fps_counter" => self.fps_counter = parse_bool(value).map_err(invalid)?,
+            "shadow.resolution" => self.shadow.resolution = parse(value).map_err(invalid)?,
+            "shadow.depth_bias" => self.shadow.depth_bias = parse(value).map_err(invalid)?,
+            "shadow.slope_bias" => self.shadow.slope_bias = parse(value).map_err(invalid)?,
+            "tone_mapping.operator" => {
+                self.tone_mapping.operator = value.parse().map_err(invalid)?
+            }
+            "tone_mapping.exposure" => {
+                self.tone_mapping.exposure = parse(value).map_err(invalid)?
+            }
+            _ => unreachable!("option {} is not handled", option.key),
+        }
+
+        Ok(())
+    }
+
+    /// checks all values and reports every problem at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.window.width == 0 || self.window.height == 0 {
+            problems.push(format!(
+                "window size {}x{} must not be empty",
+                self.window.width, self.window.height
+            ));
+        }
+
+        if self.window.title.is_empty() {
+            problems.push("window title must not be empty".to_string());
+        }
+
+        if self.device == Some(DeviceSelector::Name(String::new())) {
+            problems.push("device name must not be empty".to_string());
+        }
+
+        if !(1..=8).contains(&self.frames_in_flight) {
+            problems.push(format!(
+                "frames_in_flight is {}, but must be between 1 and 8",
+                self.frames_in_flight
+            ));
+        }
+
+        if !self.msaa_samples.is_power_of_two() || self.msaa_samples > 64 {
+            problems.push(format!(
+                "msaa_samples is {}, but must be 1, 2, 4, 8, 16, 32 or 64",
+                self.msaa_samples
+            ));
+        }
+
+        if self.frame_cap == Some(0) {
+            problems.push("frame_cap must be greater than 0".to_string());
+        }
+
+        if !(1..=16384).contains(&self.shadow.resolution) {
+            problems.push(format!(
+                "shadow resolution is {}, but must be between 1 and 16384",
+                self.shadow.resolution
+            ));
+        }
+
+        for (name, bias) in [
+            ("depth_bias", self.shadow.depth_bias),
+            ("slope_bias", self.shadow.slope_bias),
+        ] {
+            if !bias.is_finite() || bias < 0.0 {
+                problems.push(format!(
+                    "shadow {} is {}, but must not be negative",
+                    name, bias
+                ));
+            }
+        }
+
+        let exposure = self.tone_mapping.exposure;
+        if !exposure.is_finite() || exposure <= 0.0 {
+            problems.push(format!(
+                "exposure is {}, but must be greater than 0",
+                exposure
+            ));
+        }
+
+        if !self.texture.is_file() {
+            problems.push(format!("texture {} does not exist", self.texture.display()));
+        }
+
+        if let Some(model) = self.model.as_ref().filter(|m| !m.is_file()) {
+            problems.push(format!("model {} does not exist", model.display()));
+        }
+
+        if let Some(skybox) = self.skybox.as_ref().filter(|s| !s.exists()) {
+            problems.push(format!("skybox {} does not exist", skybox.display()));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| e.to_string())
+}
+
+// accepts the usual spellings, so the values of the old .env file keep working
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err("expected true or false".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn problems(config: &Config) -> Vec<String> {
+        match config.validate() {
+            Ok(()) => Vec::new(),
+            Err(ConfigError::Invalid(problems)) => problems,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let mut config = Config::from_toml(
+            r#"
+            msaa_samples = 4
+            frames_in_flight = 3
+
+            [window]
+            width = 800
+            "#,
+        )
+        .unwrap();
+
+        // options, which are not in the file, keep their defaults
+        assert_eq!(config.window.height, Config::default().window.height);
+        assert_eq!(config.present_mode, PresentMode::Mailbox);
+        assert_eq!(config.window.width, 800);
+
+        // empty variables are ignored
+        config
+            .apply_env(env(&[
+                ("MSAA_SAMPLES", "8"),
+                ("PRESENT_MODE", "fifo"),
+                ("FRAMES_IN_FLIGHT", ""),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.msaa_samples, 8);
+        assert_eq!(config.present_mode, PresentMode::Fifo);
+        assert_eq!(config.frames_in_flight, 3);
+        assert_eq!(config.window.width, 800);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(problems(&Config::default()).is_empty());
+
+        let config = Config {
+            frames_in_flight: 0,
+            msaa_samples: 3,
+            ..Config::default()
+        };
+        // all problems are reported at once
+        let problems = problems(&config);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("frames_in_flight"));
+        assert!(problems[1].contains("msaa_samples"));
+
+        let config = Config {
+            msaa_samples: 128,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        assert!(Config::from_toml("present_mode = \"vsync\"").is_err());
+        assert!(Config::from_toml("msaa = 4").is_err());
+
+        let mut config = Config::default();
+        let error = config
+            .apply_env(env(&[("PRESENT_MODE", "vsync")]))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::InvalidValue {
+                variable: "PRESENT_MODE",
+                ..
+            }
+        ));
+        assert!(config
+            .apply_env(env(&[("FRAMES_IN_FLIGHT", "two")]))
+            .is_err());
+    }
+}
@@ -1,5 +1,8 @@
 mod app;
+mod cli;
+mod config;
 mod render;
+mod scene;
 
 #[allow(dead_code, unused_variables, unused_imports)]
 use anyhow::{anyhow, Result};
@@ -10,31 +13,58 @@ use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
+use clap::Parser;
+use vulkanalia::vk;
+
+use std::path::Path;
+
+use cli::{Cli, Command};
+
 fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
     pretty_env_logger::init();
 
+    // exits with a usage message, if the arguments can't be parsed
+    let cli = Cli::parse();
+
+    // defaults, overridden by config.toml, environment variables (also from
+    // .env) and command line flags
+    let config = cli.config()?;
+    log::debug!("{:?}", config);
+
+    match &cli.command {
+        Some(Command::Render { out, frames, .. }) => render_headless(&config, out, *frames),
+        Some(Command::Info) => print_info(&config),
+        Some(Command::View { .. }) | None => view(config),
+    }
+}
+
+// opens the window and renders until it is closed
+fn view(config: config::Config) -> anyhow::Result<()> {
     // Create window
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
-        .with_title("Vulkanalia Tutorial")
+        .with_title(&config.window.title)
         // using the logical size will be dpi-scaled
-        .with_inner_size(LogicalSize::new(1024, 768))
+        .with_inner_size(LogicalSize::new(config.window.width, config.window.height))
         .build(&event_loop)?;
 
-    let mut app = unsafe { app::App::create(&window)? };
-    let mut destroying = false;
+    // event_loop.run never returns, so the app is dropped explicitly, when the
+    // window is closed (which destroys all vulkan objects)
+    let mut app = Some(app::App::create(&window, &config)?);
     let mut minimized = false;
     event_loop.run(move |event, _, control_flow| {
         // poll for events, even if none is available
         *control_flow = ControlFlow::Poll;
 
+        let Some(current) = &mut app else {
+            return;
+        };
+
         match event {
             // render a new frame, if all events other than the RequestRequested have
             // been cleared
-            Event::MainEventsCleared if !destroying && !minimized => {
-                unsafe { app.render(&window) }.unwrap()
-            }
+            Event::MainEventsCleared if !minimized => current.render(&window).unwrap(),
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
@@ -46,7 +76,7 @@ fn main() -> anyhow::Result<()> {
                     log::info!("App was resized");
                     minimized = false;
                     // handle resize explicitly
-                    app.resized = true;
+                    current.resized = true;
                 }
             }
             // emitted, if the OS sends an event to the winit window (specifically
@@ -55,14 +85,89 @@ fn main() -> anyhow::Result<()> {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
-                destroying = true;
                 *control_flow = ControlFlow::Exit;
                 log::debug!("Exit...");
-                unsafe {
-                    app.destroy();
-                }
+                app = None;
+                // in debug builds, this lists all vulkan objects, which were not
+                // destroyed along with the app
+                render::leak::report();
+            }
+            // keyboard and mouse input controls the camera, the lights and the
+            // tone mapping
+            Event::WindowEvent { event, .. } => {
+                current.camera.handle_event(&event);
+                current.lighting_mut().handle_event(&event);
+                current.tone_mapper_mut().handle_event(&event);
             }
             _ => {}
         }
     });
 }
+
+fn render_headless(config: &config::Config, output: &Path, frames: u32) -> anyhow::Result<()> {
+    let mut app = app::App::create_headless(config)?;
+    let result = app.render_to_png(output, frames);
+    let device = app
+        .device_report()
+        .selected()
+        .map(|d| d.name.clone())
+        .unwrap_or_default();
+    drop(app);
+    render::leak::report();
+    result?;
+
+    println!(
+        "Rendered {} frame(s) with '{}' to {}",
+        frames,
+        device,
+        output.display()
+    );
+    Ok(())
+}
+
+fn print_info(config: &config::Config) -> anyhow::Result<()> {
+    let report = app::App::physical_devices(config)?;
+    if report.candidates.is_empty() {
+        println!("No physical devices found");
+    }
+
+    for device in &report.candidates {
+        let marker = if report.selected == Some(device.index) {
+            "*"
+        } else {
+            " "
+        };
+        println!(
+            "{}{}: {} ({:?}, Vulkan {}.{}.{})",
+            marker,
+            device.index,
+            device.name,
+            device.device_type,
+            vk::version_major(device.api_version),
+            vk::version_minor(device.api_version),
+            vk::version_patch(device.api_version),
+        );
+        match &device.rejection {
+            None => println!(
+                "    suitable, {} MiB device local memory, {} optional features",
+                device.score.device_local_memory / (1024 * 1024),
+                device.score.optional_features
+            ),
+            Some(error) => println!("    not suitable: {}", error),
+        }
+        if !device.optional_extensions.is_empty() {
+            println!(
+                "    optional extensions: {}",
+                device.optional_extensions.join(", ")
+            );
+        }
+    }
+
+    match report.selected() {
+        Some(device) => println!("* selected: {}", device.name),
+        None => println!("No device can be used (see --device)"),
+    }
+
+    render::leak::report();
+    Ok(())
+}
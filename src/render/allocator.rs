@@ -0,0 +1,577 @@
+use anyhow::{anyhow, Result};
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+
+use super::handle::OwnedDevice;
+
+// the number of calls to allocate_memory is limited by the driver
+// (max_memory_allocation_count, which can be as low as 4096), so instead of
+// allocating memory for each resource, we reserve large blocks of device memory
+// and hand out sub-ranges of them
+pub const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// kind of resource, which is bound to an allocation
+///
+/// buffers (and linearly tiled images) and optimally tiled images must not share
+/// a "page" of buffer_image_granularity bytes, if they are placed next to each
+/// other in the same memory object
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllocationKind {
+    Linear,
+    Optimal,
+}
+
+/// a sub-range of a memory block, which is bound to a single resource
+///
+/// the default allocation does not refer to any memory; freeing it is a no-op
+/// (just like freeing a null vk::DeviceMemory handle)
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    block_index: usize,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub block_count: usize,
+    pub allocation_count: usize,
+    // memory reserved with allocate_memory
+    pub reserved_bytes: vk::DeviceSize,
+    // memory actually handed out to resources (without alignment padding)
+    pub used_bytes: vk::DeviceSize,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations in {} blocks, {} of {} bytes used",
+            self.allocation_count, self.block_count, self.used_bytes, self.reserved_bytes
+        )
+    }
+}
+
+// a range inside of a block; ranges are kept sorted by offset and cover the
+// whole block without gaps
+#[derive(Copy, Clone, Debug)]
+struct Range {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    // None, if the range is free
+    kind: Option<AllocationKind>,
+}
+
+impl Range {
+    fn end(&self) -> vk::DeviceSize {
+        self.offset + self.size
+    }
+}
+
+/// bookkeeping of the used and free ranges of a single memory block
+///
+/// this does not touch any vulkan objects, it only decides, where an allocation
+/// is placed
+#[derive(Clone, Debug)]
+pub struct FreeList {
+    size: vk::DeviceSize,
+    granularity: vk::DeviceSize,
+    ranges: Vec<Range>,
+}
+
+impl FreeList {
+    pub fn new(size: vk::DeviceSize, granularity: vk::DeviceSize) -> Self {
+        Self {
+            size,
+            granularity: granularity.max(1),
+            ranges: vec![Range {
+                offset: 0,
+                size,
+                kind: None,
+            }],
+        }
+    }
+
+    /// finds the first free range, that can hold `size` bytes with the given
+    /// alignment and marks it as used; returns the offset of the allocation
+    pub fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        kind: AllocationKind,
+    ) -> Option<vk::DeviceSize> {
+        if size == 0 {
+            return None;
+        }
+
+        for i in 0..self.ranges.len() {
+            let range = self.ranges[i];
+            if range.kind.is_some() || range.size < size {
+                continue;
+            }
+
+            let mut offset = align_up(range.offset, alignment.max(1));
+
+            // a resource of a different kind ends right before the free range
+            // -> move the allocation to the next page, if they would share one
+            if let Some(previous) = i.checked_sub(1).map(|p| self.ranges[p]) {
+                if previous.kind != Some(kind) && self.same_page(previous.end() - 1, offset) {
+                    offset = align_up(offset, self.granularity);
+                }
+            }
+
+            let end = offset + size;
+            if end > range.end() {
+                continue;
+            }
+
+            // the same applies to a resource of a different kind, which starts
+            // right after the free range; the allocation can't be moved
+            // backwards, so the range is skipped
+            if let Some(next) = self.ranges.get(i + 1) {
+                if next.kind != Some(kind) && self.same_page(end - 1, next.offset) {
+                    continue;
+                }
+            }
+
+            self.split(i, offset, size, kind);
+            return Some(offset);
+        }
+
+        None
+    }
+
+    /// marks the allocation starting at `offset` as free again and merges it with
+    /// neighbouring free ranges
+    pub fn free(&mut self, offset: vk::DeviceSize) -> Result<()> {
+        let mut i = self
+            .ranges
+            .iter()
+            .position(|r| r.offset == offset && r.kind.is_some())
+            .ok_or_else(|| anyhow!("No allocation at offset {}.", offset))?;
+
+        self.ranges[i].kind = None;
+
+        if self.ranges.get(i + 1).is_some_and(|r| r.kind.is_none()) {
+            self.ranges[i].size += self.ranges[i + 1].size;
+            self.ranges.remove(i + 1);
+        }
+
+        if i > 0 && self.ranges[i - 1].kind.is_none() {
+            self.ranges[i - 1].size += self.ranges[i].size;
+            self.ranges.remove(i);
+            i -= 1;
+        }
+
+        debug_assert!(self.ranges[i].end() <= self.size);
+
+        Ok(())
+    }
+
+    pub fn allocation_count(&self) -> usize {
+        self.ranges.iter().filter(|r| r.kind.is_some()).count()
+    }
+
+    pub fn used_bytes(&self) -> vk::DeviceSize {
+        self.ranges
+            .iter()
+            .filter(|r| r.kind.is_some())
+            .map(|r| r.size)
+            .sum()
+    }
+
+    fn same_page(&self, a: vk::DeviceSize, b: vk::DeviceSize) -> bool {
+        a / self.granularity == b / self.granularity
+    }
+
+    // replaces the free range at index i with the used range and the (possibly
+    // empty) free ranges before and after it
+    fn split(
+        &mut self,
+        i: usize,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        kind: AllocationKind,
+    ) {
+        let range = self.ranges[i];
+        let mut replacement = Vec::with_capacity(3);
+
+        if offset > range.offset {
+            replacement.push(Range {
+                offset: range.offset,
+                size: offset - range.offset,
+                kind: None,
+            });
+        }
+
+        replacement.push(Range {
+            offset,
+            size,
+            kind: Some(kind),
+        });
+
+        if offset + size < range.end() {
+            replacement.push(Range {
+                offset: offset + size,
+                size: range.end() - (offset + size),
+                kind: None,
+            });
+        }
+
+        self.ranges.splice(i..=i, replacement);
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    value.div_ceil(alignment) * alignment
+}
+
+// a single vk::DeviceMemory object of one memory type
+#[derive(Clone, Debug)]
+struct Block {
+    memory: vk::DeviceMemory,
+    memory_type_index: u32,
+    free_list: FreeList,
+    // host visible blocks are mapped once for their whole lifetime, because a
+    // memory object can only be mapped once at a time, but may be shared by
+    // many resources
+    mapped: *mut c_void,
+}
+
+/// hands out sub-ranges of large memory blocks; a block is only used for a
+/// single memory type
+///
+/// the blocks are freed, when the allocator is dropped; every Memory keeps the
+/// allocator alive, so this happens after the last resource was destroyed
+#[derive(Debug, Default)]
+pub struct Allocator {
+    device: Option<Arc<OwnedDevice>>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    granularity: vk::DeviceSize,
+    blocks: Vec<Block>,
+}
+
+// the mapped pointers of the blocks are only accessed through the allocator,
+// which is shared behind a mutex
+unsafe impl Send for Allocator {}
+
+/// the allocator is shared by all resources, which free their memory on drop
+pub type SharedAllocator = Arc<Mutex<Allocator>>;
+
+impl Allocator {
+    /// allocates memory, which fulfills the given requirements, from the memory
+    /// type with index `memory_type_index` (see buffer::get_memory_type_index)
+    pub unsafe fn allocate(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+        kind: AllocationKind,
+    ) -> Result<Allocation> {
+        let existing = self
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, b)| b.memory_type_index == memory_type_index)
+            .find_map(|(i, b)| {
+                b.free_list
+                    .allocate(requirements.size, requirements.alignment, kind)
+                    .map(|offset| (i, offset))
+            });
+
+        let (block_index, offset) = match existing {
+            Some(allocation) => allocation,
+            None => {
+                // resources, which are larger than a block, get a block of their own
+                let size = requirements.size.max(BLOCK_SIZE);
+                let block_index = self.create_block(memory_type_index, size)?;
+                let offset = self.blocks[block_index]
+                    .free_list
+                    .allocate(requirements.size, requirements.alignment, kind)
+                    .ok_or_else(|| anyhow!("Failed to allocate from new memory block."))?;
+                (block_index, offset)
+            }
+        };
+
+        Ok(Allocation {
+            memory: self.blocks[block_index].memory,
+            offset,
+            block_index,
+        })
+    }
+
+    /// returns the range of the allocation to its block; the block itself is kept
+    /// for later allocations until the allocator is destroyed
+    pub fn free(&mut self, allocation: &Allocation) -> Result<()> {
+        if allocation.memory.is_null() {
+            return Ok(());
+        }
+
+        self.blocks
+            .get_mut(allocation.block_index)
+            .filter(|b| b.memory == allocation.memory)
+            .ok_or_else(|| anyhow!("Allocation does not belong to this allocator."))?
+            .free_list
+            .free(allocation.offset)
+    }
+
+    /// pointer to the start of the allocation in host memory; only valid for
+    /// allocations from HOST_VISIBLE memory types
+    pub fn mapped(&self, allocation: &Allocation) -> Result<*mut c_void> {
+        let block = self
+            .blocks
+            .get(allocation.block_index)
+            .filter(|b| b.memory == allocation.memory)
+            .ok_or_else(|| anyhow!("Allocation does not belong to this allocator."))?;
+
+        if block.mapped.is_null() {
+            return Err(anyhow!("Allocation is not host visible."));
+        }
+
+        Ok(unsafe { block.mapped.cast::<u8>().add(allocation.offset as usize) }.cast())
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            block_count: self.blocks.len(),
+            allocation_count: self
+                .blocks
+                .iter()
+                .map(|b| b.free_list.allocation_count())
+                .sum(),
+            reserved_bytes: self.blocks.iter().map(|b| b.free_list.size).sum(),
+            used_bytes: self.blocks.iter().map(|b| b.free_list.used_bytes()).sum(),
+        }
+    }
+
+    unsafe fn create_block(
+        &mut self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+    ) -> Result<usize> {
+        let device = self
+            .device
+            .as_ref()
+            .ok_or_else(|| anyhow!("Allocator was not created."))?;
+
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+
+        let memory = device.allocate_memory(&info, None)?;
+
+        let properties =
+            self.memory_properties.memory_types[memory_type_index as usize].property_flags;
+        let mapped = if properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?
+        } else {
+            std::ptr::null_mut()
+        };
+
+        log::debug!(
+            "Allocated memory block of {} bytes (memory type {})",
+            size,
+            memory_type_index
+        );
+
+        self.blocks.push(Block {
+            memory,
+            memory_type_index,
+            free_list: FreeList::new(size, self.granularity),
+            mapped,
+        });
+
+        Ok(self.blocks.len() - 1)
+    }
+}
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        let Some(device) = &self.device else {
+            return;
+        };
+
+        let stats = self.stats();
+        if stats.allocation_count > 0 {
+            log::warn!("Destroying allocator with live allocations: {}", stats);
+        } else {
+            log::debug!("Destroying allocator: {}", stats);
+        }
+
+        // freeing a memory object implicitly unmaps it
+        self.blocks
+            .drain(..)
+            .for_each(|b| unsafe { device.free_memory(b.memory, None) });
+    }
+}
+
+/// memory of a single buffer or image, which is returned to the allocator on drop
+///
+/// the default value does not refer to any memory
+#[derive(Debug, Default)]
+pub struct Memory {
+    allocator: Option<SharedAllocator>,
+    allocation: Allocation,
+}
+
+impl Memory {
+    /// allocates memory from the shared allocator (see Allocator::allocate)
+    pub unsafe fn allocate(
+        allocator: &SharedAllocator,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+        kind: AllocationKind,
+    ) -> Result<Self> {
+        let allocation = lock(allocator)?.allocate(requirements, memory_type_index, kind)?;
+
+        Ok(Self {
+            allocator: Some(allocator.clone()),
+            allocation,
+        })
+    }
+
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.allocation.memory
+    }
+
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.allocation.offset
+    }
+
+    /// pointer to the start of the memory in host memory; only valid for
+    /// HOST_VISIBLE memory types
+    pub fn mapped(&self) -> Result<*mut c_void> {
+        let allocator = self
+            .allocator
+            .as_ref()
+            .ok_or_else(|| anyhow!("Memory is not allocated."))?;
+        lock(allocator)?.mapped(&self.allocation)
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        let Some(allocator) = &self.allocator else {
+            return;
+        };
+
+        if let Err(e) = lock(allocator).and_then(|mut a| a.free(&self.allocation)) {
+            log::error!("Failed to free memory: {}", e);
+        }
+    }
+}
+
+fn lock(allocator: &SharedAllocator) -> Result<MutexGuard<'_, Allocator>> {
+    allocator
+        .lock()
+        .map_err(|_| anyhow!("Allocator is poisoned."))
+}
+
+pub unsafe fn create_allocator(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+
+    let allocator = Allocator {
+        device: Some(device.clone()),
+        memory_properties: instance.get_physical_device_memory_properties(data.physical_device),
+        granularity: properties.limits.buffer_image_granularity,
+        blocks: Vec::new(),
+    };
+
+    log::debug!(
+        "Created allocator (buffer image granularity: {})",
+        allocator.granularity
+    );
+
+    data.allocator = Arc::new(Mutex::new(allocator));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned() {
+        let mut list = FreeList::new(4096, 1);
+
+        assert_eq!(list.allocate(10, 1, AllocationKind::Linear), Some(0));
+        assert_eq!(list.allocate(16, 256, AllocationKind::Linear), Some(256));
+        // the padding before the second allocation is used for the next one
+        assert_eq!(list.allocate(8, 8, AllocationKind::Linear), Some(16));
+        assert_eq!(list.allocate(8, 8, AllocationKind::Linear), Some(24));
+        // the padding is not counted as used
+        assert_eq!(list.used_bytes(), 42);
+        assert_eq!(list.allocate(4096, 1, AllocationKind::Linear), None);
+    }
+
+    #[test]
+    fn linear_and_optimal_resources_do_not_share_a_page() {
+        let mut list = FreeList::new(4096, 1024);
+
+        assert_eq!(list.allocate(100, 1, AllocationKind::Linear), Some(0));
+        // resources of the same kind are packed tightly
+        assert_eq!(list.allocate(100, 1, AllocationKind::Linear), Some(100));
+        assert_eq!(list.allocate(100, 1, AllocationKind::Optimal), Some(1024));
+        // doesn't fit before the image, so it starts on the page after it
+        assert_eq!(list.allocate(1000, 1, AllocationKind::Linear), Some(2048));
+    }
+
+    #[test]
+    fn free_range_before_a_resource_of_another_kind_is_skipped() {
+        let mut list = FreeList::new(4096, 1024);
+
+        let first = list.allocate(512, 1, AllocationKind::Optimal).unwrap();
+        assert_eq!(list.allocate(512, 1, AllocationKind::Optimal), Some(512));
+        list.free(first).unwrap();
+
+        // the free range at 0 shares a page with the optimal image at 512
+        assert_eq!(list.allocate(100, 1, AllocationKind::Linear), Some(1024));
+        assert_eq!(list.allocate(100, 1, AllocationKind::Optimal), Some(0));
+    }
+
+    #[test]
+    fn freed_ranges_are_merged() {
+        let mut list = FreeList::new(1024, 1);
+
+        let a = list.allocate(256, 1, AllocationKind::Linear).unwrap();
+        let b = list.allocate(256, 1, AllocationKind::Linear).unwrap();
+        let c = list.allocate(256, 1, AllocationKind::Linear).unwrap();
+        assert_eq!(list.allocation_count(), 3);
+
+        list.free(b).unwrap();
+        assert_eq!(list.ranges.len(), 4);
+        list.free(a).unwrap();
+        assert_eq!(list.ranges.len(), 3);
+        list.free(c).unwrap();
+        assert_eq!(list.ranges.len(), 1);
+
+        assert_eq!(list.allocation_count(), 0);
+        assert_eq!(list.used_bytes(), 0);
+        assert_eq!(list.allocate(1024, 1, AllocationKind::Linear), Some(0));
+    }
+
+    #[test]
+    fn double_and_unknown_frees_are_rejected() {
+        let mut list = FreeList::new(1024, 1);
+
+        let a = list.allocate(256, 1, AllocationKind::Linear).unwrap();
+        let b = list.allocate(256, 1, AllocationKind::Linear).unwrap();
+        list.free(a).unwrap();
+
+        assert!(list.free(a).is_err());
+        // inside of an allocation, but not at its start
+        assert!(list.free(b + 1).is_err());
+        assert!(list.free(4096).is_err());
+        assert_eq!(list.allocation_count(), 1);
+    }
+}
@@ -1,21 +1,24 @@
 #[allow(dead_code, unused_variables, unused_imports)]
 use anyhow::{anyhow, Result};
 
-use log::info;
+use std::sync::Arc;
+
 use vulkanalia::prelude::v1_0::*;
 
 use crate::app::AppData;
 
-use super::command_buffer::{begin_single_time_commands, end_single_time_commands};
+use super::allocator::{AllocationKind, Memory};
+use super::handle::{Buffer, OwnedDevice, RawBuffer};
 
+#[track_caller]
 pub unsafe fn create_buffer(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &AppData,
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+) -> Result<Buffer> {
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(usage)
@@ -23,58 +26,31 @@ pub unsafe fn create_buffer(
         // need to share it between queue families
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-    let buffer = device.create_buffer(&buffer_info, None)?;
+    // the buffer is destroyed again, if one of the following steps fails
+    let buffer = RawBuffer::new(device, device.create_buffer(&buffer_info, None)?);
 
     // allocate buffer memory
-    let requirements = device.get_buffer_memory_requirements(buffer);
-
-    let memory_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance,
-            data,
-            properties,
-            requirements,
-        )?);
+    let requirements = device.get_buffer_memory_requirements(*buffer);
 
-    // allocate the buffer memory
+    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
 
-    // TODO: in a real scenario, we are not supposed to call allocate memory for
+    // allocate the buffer memory; we are not supposed to call allocate_memory for
     // each buffer separately, because these calls are limited to a relatively
-    // small amount; instead we should create a custom allocator, that splits
-    // up a single allocation among many differne objects by using the offset
-    // parameters, that we've seen in many functions
-    let buffer_memory = device.allocate_memory(&memory_info, None)?;
+    // small amount, so the allocator splits up larger blocks of memory
+    let buffer_memory = Memory::allocate(
+        &data.allocator,
+        requirements,
+        memory_type_index,
+        AllocationKind::Linear,
+    )?;
 
     // bind the memory to the vertex buffer
     device.bind_buffer_memory(
-        buffer,
-        buffer_memory,
-        0, // no offset
+        *buffer,
+        buffer_memory.memory(),
+        buffer_memory.offset(), // offset of the sub-range in the memory block
     )?;
-    Ok((buffer, buffer_memory))
-}
-
-pub unsafe fn copy_buffer(
-    device: &Device,
-    data: &AppData,
-    source: vk::Buffer,
-    destination: vk::Buffer,
-    size: vk::DeviceSize,
-) -> Result<()> {
-    // memory transfer operations are executed using commmand buffers
-    // allocate temp command buffer
-    let command_buffer = begin_single_time_commands(device, data)?;
-
-    // define regions to copy, defined in BufferCopy-struct, which is source buffer offset,
-    // destination buffer offset and size (we can't define vk::WHOLE_SIZE here..)
-    let regions = vk::BufferCopy::builder().size(size);
-    device.cmd_copy_buffer(command_buffer, source, destination, &[regions]);
-
-    // end recording
-    end_single_time_commands(device, data, command_buffer)?;
-
-    Ok(())
+    Ok(Buffer::new(buffer, buffer_memory))
 }
 
 // graphics cards offer more than one kind of memory with different allowed operations
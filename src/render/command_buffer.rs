@@ -1,8 +1,13 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use vulkanalia::prelude::v1_0::*;
 
-use crate::{app::AppData, render::pipeline};
-use log::info;
+use crate::app::AppData;
+
+use super::descriptor_set;
+use super::push_constants::PushConstants;
+use super::shadow;
+use super::skybox;
+use super::tonemap;
 
 pub unsafe fn begin_single_time_commands(
     device: &Device,
@@ -10,7 +15,7 @@ pub unsafe fn begin_single_time_commands(
 ) -> Result<vk::CommandBuffer> {
     let info = vk::CommandBufferAllocateInfo::builder()
         .level(vk::CommandBufferLevel::PRIMARY)
-        .command_pool(data.command_pool)
+        .command_pool(*data.command_pool)
         .command_buffer_count(1);
 
     let command_buffer = device.allocate_command_buffers(&info)?[0];
@@ -36,112 +41,173 @@ pub unsafe fn end_single_time_commands(
     device.queue_submit(data.graphics_queue, &[info], vk::Fence::null())?;
     device.queue_wait_idle(data.graphics_queue)?;
 
-    device.free_command_buffers(data.command_pool, &[command_buffer]);
+    device.free_command_buffers(*data.command_pool, &[command_buffer]);
 
     Ok(())
 }
 
 pub unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<()> {
-    debug_assert!(data.framebuffers.len() > 0);
-
-    log::debug!("Creating {} command buffers", data.framebuffers.len());
-
-    // as drawing requires binding of the correct framebuffer, we create a command
-    // buffer for each one
-    let allocate_info = vk::CommandBufferAllocateInfo::builder()
-        .command_pool(data.command_pool)
-        // PRIMARY:     can be submitted to queue directly, but can't be called from other
-        //              command buffers
-        // SECONDARY:   can't be submitted directly to queue, but can be called from
-        //              primary command buffers
-        .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
-
-    data.command_buffers = device.allocate_command_buffers(&allocate_info)?;
+    log::debug!(
+        "Creating {} command buffers",
+        data.frame_command_pools.len()
+    );
+
+    // each frame in flight records into its own command buffer, which is
+    // allocated from the command pool of the frame
+    data.command_buffers = data
+        .frame_command_pools
+        .iter()
+        .map(|pool| {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(**pool)
+                // PRIMARY:     can be submitted to queue directly, but can't be called from other
+                //              command buffers
+                // SECONDARY:   can't be submitted directly to queue, but can be called from
+                //              primary command buffers
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+
+            device
+                .allocate_command_buffers(&allocate_info)
+                .map(|buffers| buffers[0])
+        })
+        .collect::<Result<_, _>>()?;
 
-    // record command buffers
-    for (i, command_buffer) in data.command_buffers.iter().enumerate() {
-        // begin command buffer
-        let inheritance = vk::CommandBufferInheritanceInfo::builder();
+    Ok(())
+}
 
-        let begin_info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::empty()) // could further specify usage of command buffer (ONE_TIME_SUBMIT etc.)
-            .inheritance_info(&inheritance);
+/// records the commands to render the scene into the swapchain image with
+/// index `image_index`, using the command buffer of the frame in flight `frame`
+///
+/// the fence of the frame needs to be signaled already, so the command buffer
+/// is no longer in use
+pub unsafe fn record_command_buffer(
+    device: &Device,
+    data: &mut AppData,
+    frame: usize,
+    image_index: usize,
+) -> Result<()> {
+    // resetting the pool puts its command buffer back into the initial state
+    device.reset_command_pool(
+        *data.frame_command_pools[frame],
+        vk::CommandPoolResetFlags::empty(),
+    )?;
 
-        device.begin_command_buffer(*command_buffer, &begin_info)?;
+    // the world transforms of the nodes are recorded as push constants
+    data.scene.update_world_transforms();
 
-        // start command buffer
+    let command_buffer = data.command_buffers[frame];
 
-        // define render area (where data should be loaded and stored during render operations)
-        // pixels outside of this area will be undefined -> should match extent
-        // of framebuffer images for best performance
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(data.swapchain_extent);
+    // the command buffer is recorded again for the next frame, so it is only
+    // submitted once
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
+    device.begin_command_buffer(command_buffer, &begin_info)?;
+
+    // the shadow map is sampled in the main render pass, so it is rendered first
+    shadow::record_shadow_pass(device, data, command_buffer, image_index);
+
+    // define render area (where data should be loaded and stored during render operations)
+    // pixels outside of this area will be undefined -> should match extent
+    // of framebuffer images for best performance
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: data.clear_color,
+        },
+    };
+
+    // the range of depths in the depth buffer is 0.0 to 1.0 (1.0 lies at the
+    // far view plane), so the initial value should be the furthest possible depth
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    };
+
+    // begin render pass; the order of the clear values has to be identical
+    // to the order of the attachments
+    let clear_values = &[color_clear_value, depth_clear_value];
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*data.render_pass)
+        .framebuffer(*data.framebuffer)
+        .render_area(render_area)
+        .clear_values(clear_values);
+
+    device.cmd_begin_render_pass(
+        command_buffer,
+        &info,
+        // inline: render pass commands will be provided by primary command buffer
+        // secondary: render pass commands will be provided in secondary command buffer(s)
+        vk::SubpassContents::INLINE,
+    );
+
+    // the skybox doesn't write depth, so the scene is drawn over it
+    skybox::record_skybox(device, data, command_buffer, image_index);
+
+    // bind pipeline -> tells vulkan, which attachments to use
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.pipeline,
+    );
+
+    // bind vertex buffer
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[*data.vertex_buffer], &[0]);
+
+    // bind index buffer (you can only have one index buffer); loaded models
+    // can have more than 65535 vertices, so the indices are 32 bit wide
+    device.cmd_bind_index_buffer(command_buffer, *data.index_buffer, 0, vk::IndexType::UINT32);
+
+    // draw every visible node of the scene with its own model matrix; the
+    // descriptor set only needs to be rebound, if the texture changes
+    let mut bound_texture = None;
+    for draw in data.scene.draws() {
+        if bound_texture != Some(draw.material.texture) {
+            let set =
+                descriptor_set::descriptor_set_index(data, image_index, draw.material.texture);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                *data.pipeline_layout,
+                0,
+                &[data.descriptor_sets[set]],
+                &[],
+            );
+            bound_texture = Some(draw.material.texture);
+        }
+
+        // the model matrix of the node and the base color of its material are
+        // passed as push constants, which are recorded into the command buffer
+        let push_constants = PushConstants {
+            model: draw.transform,
+            base_color: draw.material.base_color_factor,
         };
-
-        // begin render pass
-        let clear_values = &[color_clear_value];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(data.render_pass)
-            .framebuffer(data.framebuffers[i])
-            .render_area(render_area)
-            .clear_values(clear_values);
-
-        device.cmd_begin_render_pass(
-            *command_buffer,
-            &info,
-            // inline: render pass commands will be provided by primary command buffer
-            // secondary: render pass commands will be provided in secondary command buffer(s)
-            vk::SubpassContents::INLINE,
-        );
-
-        // bind pipeline -> tells vulkan, which attachments to use
-        device.cmd_bind_pipeline(
-            *command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            data.pipeline,
-        );
-
-        // bind vertex buffer
-        device.cmd_bind_vertex_buffers(*command_buffer, 0, &[data.vertex_buffer], &[0]);
-
-        // bind index buffer (you can only have one index buffer)
-        device.cmd_bind_index_buffer(*command_buffer, data.index_buffer, 0, vk::IndexType::UINT16);
-
-        // bind descriptor set for each swapchain image
-        device.cmd_bind_descriptor_sets(
-            *command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            data.pipeline_layout,
-            0,
-            &[data.descriptor_sets[i]],
-            &[],
-        );
+        push_constants.push(device, command_buffer, *data.pipeline_layout);
 
         // draw (indexed)
         device.cmd_draw_indexed(
-            *command_buffer,
-            // TODO: this needs to match the total vertex count.. as the whole
-            // vertex buffer thing will be encapsulated later in a struct anyway
-            // don't care right now
-            pipeline::INDICES.len() as u32, // index count
-            1,                              // instance count
-            0,                              // first index
-            0,                              // vertex offset -> start at zeroth one
-            0,                              // first instance
+            command_buffer,
+            draw.mesh.index_count,   // index count
+            1,                       // instance count
+            draw.mesh.first_index,   // first index
+            draw.mesh.vertex_offset, // vertex offset, added to each index
+            0,                       // first instance
         );
-
-        // finishing up
-        device.cmd_end_render_pass(*command_buffer);
-        device.end_command_buffer(*command_buffer)?;
-        log::debug!("Created command buffer");
     }
 
+    device.cmd_end_render_pass(command_buffer);
+
+    // the lit scene is written into the swapchain image by the tone mapping pass
+    tonemap::record_tone_map_pass(device, data, command_buffer, image_index);
+
+    // finishing up
+    device.end_command_buffer(command_buffer)?;
+
     Ok(())
 }
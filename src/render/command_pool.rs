@@ -1,15 +1,14 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use std::sync::Arc;
 use vulkanalia::prelude::v1_0::*;
 
 use crate::app::AppData;
+use crate::render::handle::{CommandPool, OwnedDevice};
 use crate::render::queue;
 
-// TODO: create as many command pools as there are images in flight (and command
-// buffers) -> only one command buffer per command pool
-
 pub unsafe fn create_command_pool(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
     // command_pools are used to create command buffers, which will then be
@@ -19,11 +18,40 @@ pub unsafe fn create_command_pool(
 
     // get graphics queue
     let info = vk::CommandPoolCreateInfo::builder()
-        .flags(vk::CommandPoolCreateFlags::empty()) // could use this to specify
-        // hints to vulkan about usage of the command buffers, we want to create
-        // from this pool
+        // this pool is only used for short lived command buffers (uploads,
+        // layout transitions), which are freed right after their submission
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
         .queue_family_index(indices.graphics);
 
-    data.command_pool = device.create_command_pool(&info, None)?;
+    data.command_pool = CommandPool::new(device, device.create_command_pool(&info, None)?);
+    Ok(())
+}
+
+/// creates a command pool for each frame in flight; each pool holds the single
+/// command buffer of its frame, which is re-recorded every frame
+///
+/// resetting the whole pool is cheaper than resetting individual command buffers,
+/// and it is safe, because the fence of the frame guarantees, that the previous
+/// submission of the command buffer has completed
+pub unsafe fn create_frame_command_pools(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = queue::QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+        .queue_family_index(indices.graphics);
+
+    data.frame_command_pools = (0..data.frames_in_flight)
+        .map(|_| {
+            Ok(CommandPool::new(
+                device,
+                device.create_command_pool(&info, None)?,
+            ))
+        })
+        .collect::<Result<_>>()?;
+
     Ok(())
 }
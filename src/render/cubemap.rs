@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Result};
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use nalgebra_glm as glm;
+
+use super::image;
+
+/// file names of the faces in a skybox directory, in the order of the layers
+/// of a cube image
+pub const FACE_FILES: [&str; 6] = [
+    "posx.png", "negx.png", "posy.png", "negy.png", "posz.png", "negz.png",
+];
+
+/// the six faces of a cube map in 8 bit RGBA, one after another in the order
+/// +x, -x, +y, -y, +z, -z (see image::create_cube_texture)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CubeFaces {
+    // width and height of each face
+    pub size: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// loads the faces from a directory with six square pngs (see FACE_FILES) or
+/// converts a single equirectangular png
+pub fn load_cube_faces(path: &Path) -> Result<CubeFaces> {
+    if path.is_dir() {
+        load_face_files(path)
+    } else {
+        let (pixels, width, height) = image::load_png(path)?;
+        check_rgba(path, &pixels, width, height)?;
+        from_equirectangular(&pixels, width, height)
+    }
+}
+
+fn load_face_files(dir: &Path) -> Result<CubeFaces> {
+    let mut size = None;
+    let mut faces = Vec::new();
+
+    for file in FACE_FILES {
+        let path = dir.join(file);
+        let (pixels, width, height) = image::load_png(&path)
+            .map_err(|e| anyhow!("Failed to load face {}: {}", path.display(), e))?;
+        check_rgba(&path, &pixels, width, height)?;
+
+        if width != height || size.is_some_and(|s| s != width) {
+            return Err(anyhow!(
+                "Face {} has size {}x{}, but the faces of a cube map need to be squares of the same size.",
+                path.display(),
+                width,
+                height
+            ));
+        }
+        size = Some(width);
+        faces.extend(pixels);
+    }
+
+    Ok(CubeFaces {
+        size: size.unwrap_or(0),
+        pixels: faces,
+    })
+}
+
+fn check_rgba(path: &Path, pixels: &[u8], width: u32, height: u32) -> Result<()> {
+    if pixels.len() != width as usize * height as usize * 4 {
+        return Err(anyhow!("Image {} needs to be 8 bit RGBA.", path.display()));
+    }
+    Ok(())
+}
+
+/// resamples an equirectangular image (longitude along the width, latitude
+/// along the height with +y at the top row) into six faces with a quarter of
+/// its width
+pub fn from_equirectangular(pixels: &[u8], width: u32, height: u32) -> Result<CubeFaces> {
+    let size = width / 4;
+    if size == 0 || height == 0 {
+        return Err(anyhow!(
+            "Equirectangular image of size {}x{} is too small for a cube map.",
+            width,
+            height
+        ));
+    }
+
+    let mut faces = Vec::with_capacity(size as usize * size as usize * 4 * 6);
+    for face in 0..6 {
+        for y in 0..size {
+            for x in 0..size {
+                // sample at the center of the texel
+                let u = (x as f32 + 0.5) / size as f32;
+                let v = (y as f32 + 0.5) / size as f32;
+                let direction = face_direction(face, u, v).normalize();
+
+                let longitude = direction.x.atan2(-direction.z);
+                let latitude = direction.y.clamp(-1.0, 1.0).acos();
+                let s = (0.5 + longitude / (2.0 * PI)) * width as f32;
+                let t = latitude / PI * height as f32;
+
+                faces.extend(sample_bilinear(pixels, width, height, s, t));
+            }
+        }
+    }
+
+    Ok(CubeFaces {
+        size,
+        pixels: faces,
+    })
+}
+
+/// the direction from the center of the cube through the texel coordinates
+/// `u` and `v` (0.0 to 1.0) of a face; matches the face selection of vulkan
+/// (see the table "Cube map face selection" of the specification)
+pub fn face_direction(face: usize, u: f32, v: f32) -> glm::Vec3 {
+    let s = 2.0 * u - 1.0;
+    let t = 2.0 * v - 1.0;
+    match face {
+        0 => glm::vec3(1.0, -t, -s),
+        1 => glm::vec3(-1.0, -t, s),
+        2 => glm::vec3(s, 1.0, t),
+        3 => glm::vec3(s, -1.0, -t),
+        4 => glm::vec3(s, -t, 1.0),
+        _ => glm::vec3(-s, -t, -1.0),
+    }
+}
+
+// the image wraps around horizontally and is clamped at the poles; the texel
+// centers are at half coordinates
+fn sample_bilinear(pixels: &[u8], width: u32, height: u32, s: f32, t: f32) -> [u8; 4] {
+    let s = s - 0.5;
+    let t = (t - 0.5).clamp(0.0, height as f32 - 1.0);
+    let (x0, y0) = (s.floor(), t.floor());
+    let (fx, fy) = (s - x0, t - y0);
+
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as usize;
+        let y = (y as i64).clamp(0, height as i64 - 1) as usize;
+        let i = (y * width as usize + x) * 4;
+        &pixels[i..i + 4]
+    };
+
+    let mut result = [0; 4];
+    for (c, value) in result.iter_mut().enumerate() {
+        let top = texel(x0, y0)[c] as f32 * (1.0 - fx) + texel(x0 + 1.0, y0)[c] as f32 * fx;
+        let bottom =
+            texel(x0, y0 + 1.0)[c] as f32 * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0)[c] as f32 * fx;
+        *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the center of each face points along its axis
+    #[test]
+    fn face_centers_point_along_the_axes() {
+        let axes = [
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(-1.0, 0.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, 0.0, -1.0),
+        ];
+        for (face, axis) in axes.iter().enumerate() {
+            assert_eq!(face_direction(face, 0.5, 0.5), *axis);
+        }
+    }
+
+    // the faces of a horizontally striped image: the top half is red, the
+    // bottom half blue
+    #[test]
+    fn equirectangular_top_and_bottom_become_up_and_down_faces() {
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height)
+            .flat_map(|i| {
+                if i / width < height / 2 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let faces = from_equirectangular(&pixels, width, height).unwrap();
+        assert_eq!(faces.size, 4);
+        assert_eq!(faces.pixels.len(), 4 * 4 * 4 * 6);
+
+        let face = |f: usize| &faces.pixels[f * 64..(f + 1) * 64];
+        assert!(face(2).chunks(4).all(|p| p == [255, 0, 0, 255]));
+        assert!(face(3).chunks(4).all(|p| p == [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn equirectangular_needs_a_width_of_four() {
+        assert!(from_equirectangular(&[0; 3 * 4], 3, 1).is_err());
+    }
+}
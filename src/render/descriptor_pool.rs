@@ -1,31 +1,42 @@
 #[allow(dead_code, unused_variables, unused_imports)]
 use anyhow::{anyhow, Result};
 
-use log::info;
+use std::sync::Arc;
+
 use vulkanalia::prelude::v1_0::*;
 
 use crate::app::AppData;
 
-pub unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
+use super::handle::{DescriptorPool, OwnedDevice};
+
+pub unsafe fn create_descriptor_pool(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
     // we need a descriptor set to bind a descriptor layout to a vk::Buffer
     // these can't be created directly but need to be created from a descriptor pool
 
-    // which descriptor types will be contained in descriptor set and how many?
-    let ubo_size = vk::DescriptorPoolSize::builder()
-        .type_(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+    // there is a descriptor set for each texture per swapchain image
+    let set_count = (data.swapchain_images.len() * data.textures.len()) as u32;
 
-    let sampler_size = vk::DescriptorPoolSize::builder()
-        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+    // which descriptor types will be contained in descriptor set and how many?
+    // every reflected binding needs its descriptors in each set
+    let pool_sizes = data
+        .shader_reflection
+        .bindings
+        .iter()
+        .map(|b| {
+            vk::DescriptorPoolSize::builder()
+                .type_(b.descriptor_type)
+                .descriptor_count(b.count * set_count)
+                .build()
+        })
+        .collect::<Vec<_>>();
 
     // inadequate desriptor pools won't be caught by validation layers
-    let pool_sizes = &[ubo_size, sampler_size];
     let info = vk::DescriptorPoolCreateInfo::builder()
-        .pool_sizes(pool_sizes)
-        .max_sets(data.swapchain_images.len() as u32);
+        .pool_sizes(&pool_sizes)
+        .max_sets(set_count);
 
-    data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+    // descriptor sets allocated from the pool are freed along with it
+    data.descriptor_pool = DescriptorPool::new(device, device.create_descriptor_pool(&info, None)?);
 
     Ok(())
 }
@@ -1,13 +1,16 @@
 use std::mem::size_of;
+use std::sync::Arc;
 
 #[allow(dead_code, unused_variables, unused_imports)]
 use anyhow::{anyhow, Result};
 
-use log::info;
 use nalgebra_glm as glm;
 use vulkanalia::prelude::v1_0::*;
 
 use crate::app::AppData;
+use crate::scene::{LightKind, Lighting, MAX_LIGHTS};
+
+use super::handle::{DescriptorSetLayout, OwnedDevice};
 
 // vulkan has very specific alignment requirements for structs passed as
 // UBOs to a shader (see: https://www.khronos.org/registry/vulkan/specs/1.1-extensions/html/chap14.html#interfaces-resources-layout)
@@ -17,30 +20,127 @@ use crate::app::AppData;
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub(crate) struct UniformBufferObject {
-    pub(crate) model: glm::Mat4,
     pub(crate) view: glm::Mat4,
     pub(crate) proj: glm::Mat4,
+    // projection into the shadow map (see shadow::light_view_proj)
+    pub(crate) light_view_proj: glm::Mat4,
+}
+
+// the light kinds, as they are encoded in the w component of the position
+const DIRECTIONAL_LIGHT: f32 = 0.0;
+const POINT_LIGHT: f32 = 1.0;
+const SPOT_LIGHT: f32 = 2.0;
+
+// a single light of the lights uniform block; only vec4 members are used, so
+// the std140 layout of the shader does not need any padding
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct LightData {
+    // xyz: position in world space, w: kind of light
+    position: glm::Vec4,
+    // xyz: direction, in which the light shines, w: range
+    direction: glm::Vec4,
+    // rgb: color, a: intensity
+    color: glm::Vec4,
+    // x: cosine of the inner angle, y: cosine of the outer angle (spot lights)
+    cone: glm::Vec4,
+}
+
+/// the lights uniform block of the fragment shader (binding 3)
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct LightsBufferObject {
+    // rgb: ambient light, a: shininess
+    ambient: glm::Vec4,
+    // xyz: position of the camera for the specular term, w: specular strength
+    camera_position: glm::Vec4,
+    // x: number of lights, y: index of the light, which casts shadows, plus 1
+    // (0, if there is none; the rest is padding)
+    count: [u32; 4],
+    lights: [LightData; MAX_LIGHTS],
 }
 
-pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+impl LightsBufferObject {
+    pub(crate) fn new(lighting: &Lighting, camera_position: glm::Vec3) -> Self {
+        let mut lights = [LightData::default(); MAX_LIGHTS];
+        let mut count = 0;
+        let shadow_caster = lighting.shadow_caster().map_or(0, |(i, _)| i as u32 + 1);
+
+        for (data, light) in lights.iter_mut().zip(lighting.active_lights()) {
+            let color = glm::vec4(light.color.x, light.color.y, light.color.z, light.intensity);
+            *data = match light.kind {
+                LightKind::Directional { direction } => LightData {
+                    position: glm::vec4(0.0, 0.0, 0.0, DIRECTIONAL_LIGHT),
+                    direction: direction.normalize().push(0.0),
+                    color,
+                    cone: glm::Vec4::zeros(),
+                },
+                LightKind::Point { position, range } => LightData {
+                    position: position.push(POINT_LIGHT),
+                    direction: glm::vec4(0.0, 0.0, 0.0, range),
+                    color,
+                    cone: glm::Vec4::zeros(),
+                },
+                LightKind::Spot {
+                    position,
+                    direction,
+                    range,
+                    inner_angle,
+                    outer_angle,
+                } => LightData {
+                    position: position.push(SPOT_LIGHT),
+                    direction: direction.normalize().push(range),
+                    color,
+                    cone: glm::vec4(inner_angle.cos(), outer_angle.cos(), 0.0, 0.0),
+                },
+            };
+            count += 1;
+        }
+
+        Self {
+            ambient: lighting.ambient.push(lighting.shininess),
+            camera_position: camera_position.push(lighting.specular_strength),
+            count: [count, shadow_caster, 0, 0],
+            lights,
+        }
+    }
+}
+
+pub unsafe fn create_descriptor_set_layout(
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
     log::debug!("Creating descriptor set layout");
-    // leave immutable_sampler field at default(used for image sampling)
-    let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(0) // reference to vertex shader
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(1) // it is possible for a shader variable to represent an array of uniform buffer objects (number specified by this count) -> could be used to specify transform for each bone in skeletal animation
-        .stage_flags(vk::ShaderStageFlags::VERTEX); // in which stage of the graphics pipeline
 
-    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1) // reference to vertex shader
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(1) // it is possible for a shader variable to represent an array of uniform buffer objects (number specified by this count) -> could be used to specify transform for each bone in skeletal animation
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT); // in which stage of the graphics pipeline
+    // the bindings are reflected from the compiled shaders (see
+    // pipeline::compile_shaders), so they always match the shader code
+    //
+    // binding 0: uniform buffer with the camera matrices (vertex shader)
+    // binding 1: texture (fragment shader)
+    // binding 2: sampler (fragment shader); the texture and the sampler are
+    //            separate descriptors, because naga (which compiles the shaders)
+    //            does not support combined image samplers
+    // binding 3: uniform buffer with the lights (fragment shader)
+    // binding 4: shadow map (fragment shader)
+    // binding 5: comparison sampler of the shadow map (fragment shader)
+    let reflection = &data.shader_reflection;
+    if let Some(binding) = reflection.bindings.iter().find(|b| b.set != 0) {
+        return Err(anyhow!(
+            "Only descriptor set 0 is supported, but binding {} uses set {}.",
+            binding.binding,
+            binding.set
+        ));
+    }
 
-    let bindings = &[ubo_binding, sampler_binding];
-    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    let bindings = reflection
+        .bindings
+        .iter()
+        .map(|b| b.layout_binding())
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
 
-    data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+    data.descriptor_set_layout =
+        DescriptorSetLayout::new(device, device.create_descriptor_set_layout(&info, None)?);
 
     Ok(())
 }
@@ -48,29 +148,33 @@ pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData)
 pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
     log::debug!("Creating descriptor sets");
     // allocate descriptor sets (with defined layout) from descriptor pool
-    let layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
+    let texture_count = data.textures.len();
+    let layouts = vec![*data.descriptor_set_layout; data.swapchain_images.len() * texture_count];
 
-    // create a descriptor set with the same layout for each swapchain image
+    // create a descriptor set with the same layout for each combination of
+    // swapchain image and texture (see descriptor_set_index)
     let info = vk::DescriptorSetAllocateInfo::builder()
-        .descriptor_pool(data.descriptor_pool)
+        .descriptor_pool(*data.descriptor_pool)
         .set_layouts(&layouts);
 
     // descriptor sets will be freed, once the descriptor_pool is destroyed
     data.descriptor_sets = device.allocate_descriptor_sets(&info)?;
 
     // the descriptors inside the newly created sets need to be configured
-    for i in 0..data.swapchain_images.len() {
-        log::debug!("Updating descriptor set with index {}", i);
+    for (index, set) in data.descriptor_sets.iter().enumerate() {
+        let i = index / texture_count;
+        let texture = &data.textures[index % texture_count];
+        log::debug!("Updating descriptor set with index {}", index);
         // create descriptor buffer info
         let info = vk::DescriptorBufferInfo::builder()
-            .buffer(data.uniform_buffers[i]) // which buffer to bind
+            .buffer(*data.uniform_buffers[i]) // which buffer to bind
             .offset(0) // offset in the buffer
             .range(size_of::<UniformBufferObject>() as u64); // how long is the range in the buffer
 
         // descriptor sets are updated with a writeDescriptorSet-struct
         let buffer_info = &[info];
         let ubo_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
+            .dst_set(*set)
             .dst_binding(0) // we gave our uniform buffer binding index 0, so reference it here
             .dst_array_element(0) // descriptors can be array, so specify the first index in the array of the element, we want to update
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
@@ -79,20 +183,88 @@ pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Res
         // bind image and sampler resources to descriptors in the descriptor set
         let info = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(data.texture_image_view)
-            .sampler(data.texture_sampler);
+            .image_view(*texture.view);
 
         let image_info = &[info];
-        let sampler_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
+        let texture_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
             .dst_binding(1)
             .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
             .image_info(image_info);
 
+        // textures without their own sampler use the shared one
+        let sampler = if texture.sampler.is_null() {
+            *data.texture_sampler
+        } else {
+            *texture.sampler
+        };
+        let info = vk::DescriptorImageInfo::builder().sampler(sampler);
+
+        let sampler_info = &[info];
+        let sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(2)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(sampler_info);
+
+        // the lights are updated every frame, like the camera matrices
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(*data.light_buffers[i])
+            .offset(0)
+            .range(size_of::<LightsBufferObject>() as u64);
+
+        let lights_info = &[info];
+        let lights_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(3)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(lights_info);
+
+        // the shadow map is the same for all frames (see shadow::ShadowMap)
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(*data.shadow.view);
+
+        let shadow_map_info = &[info];
+        let shadow_map_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(4)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(shadow_map_info);
+
+        let info = vk::DescriptorImageInfo::builder().sampler(*data.shadow.sampler);
+
+        let shadow_sampler_info = &[info];
+        let shadow_sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(5)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(shadow_sampler_info);
+
         // actually update the descriptor set
-        device.update_descriptor_sets(&[ubo_write, sampler_write], &[] as &[vk::CopyDescriptorSet]);
+        device.update_descriptor_sets(
+            &[
+                ubo_write,
+                texture_write,
+                sampler_write,
+                lights_write,
+                shadow_map_write,
+                shadow_sampler_write,
+            ],
+            &[] as &[vk::CopyDescriptorSet],
+        );
     }
 
     Ok(())
 }
+
+/// index of the descriptor set, which binds the uniform buffer of the swapchain
+/// image and the given texture
+pub fn descriptor_set_index(data: &AppData, image_index: usize, texture: usize) -> usize {
+    image_index * data.textures.len() + texture
+}
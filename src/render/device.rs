@@ -5,14 +5,17 @@ use log::*;
 use thiserror::Error;
 
 use std::collections::HashSet;
+use std::sync::Arc;
 
 use vulkanalia::prelude::v1_0::*;
 use vulkanalia::vk;
 
+use super::handle::{OwnedDevice, OwnedInstance};
 use super::queue::QueueFamilyIndices;
 use super::swapchain;
 use super::validation;
 use crate::app::AppData;
+use crate::config::DeviceSelector;
 
 // The error macro of the thiserror-crate enables definition of custom
 // error types in terms of structs or enums without all the boilerplate code
@@ -20,12 +23,46 @@ use crate::app::AppData;
 //
 // A custom error message can be defined with a format string, which uses members
 // of data structure
+/// the reason, why a physical device can't be used
 #[derive(Debug, Error)]
-#[error("Missing {0}.")]
-pub struct SuitabilityError(pub &'static str);
+pub enum SuitabilityError {
+    #[error("Missing queue families with {} support.", .0.join(" and "))]
+    MissingQueueFamilies(Vec<&'static str>),
+    #[error("Missing device extensions: {}.", .0.join(", "))]
+    MissingExtensions(Vec<String>),
+    #[error("Missing device features: {}.", .0.join(", "))]
+    MissingFeatures(Vec<&'static str>),
+    #[error("Insufficient swapchain support: {0}.")]
+    InsufficientSwapchainSupport(&'static str),
+}
 
 const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
 
+/// device extensions, which are enabled, if the device supports them; check
+/// `data.enabled_extensions` before relying on one of them
+const OPTIONAL_DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[
+    // allows returning the memory of unused command buffers (see
+    // upload::UploadManager)
+    vk::KHR_MAINTENANCE1_EXTENSION.name,
+];
+
+// the swapchain extension is only required, if we present to a surface
+fn device_extensions(data: &AppData) -> &'static [vk::ExtensionName] {
+    if data.headless {
+        &[]
+    } else {
+        DEVICE_EXTENSIONS
+    }
+}
+
+// reads a single field of the supported features
+type FeatureQuery = fn(&vk::PhysicalDeviceFeatures) -> vk::Bool32;
+
+/// features, which need to be supported (and are enabled in
+/// `create_logical_device`)
+const REQUIRED_FEATURES: &[(&str, FeatureQuery)] =
+    &[("sampler_anisotropy", |f| f.sampler_anisotropy)];
+
 // we need to check, whether a given physical device
 // is suitable to use for our needs
 unsafe fn check_physical_device(
@@ -34,52 +71,81 @@ unsafe fn check_physical_device(
     physical_device: vk::PhysicalDevice,
 ) -> Result<()> {
     QueueFamilyIndices::get(instance, data, physical_device)?;
-    check_physical_device_extensions(instance, physical_device)?;
+    check_physical_device_extensions(instance, data, physical_device)?;
 
-    // check swapchain support
-    let sc_support = swapchain::SwapchainSupport::get(instance, data, physical_device)?;
+    // without a surface there is no swapchain, which needs to be supported
+    if !data.headless {
+        let sc_support = swapchain::SwapchainSupport::get(instance, data, physical_device)?;
 
-    // for this example, it suffices, if the swapchain supports at least one surface format
-    // and one present mode
-    if sc_support.formats.is_empty() || sc_support.present_modes.is_empty() {
-        return Err(anyhow!(SuitabilityError("Insufficient swapchain support.")));
+        // for this example, it suffices, if the swapchain supports at least one surface format
+        // and one present mode
+        if sc_support.formats.is_empty() {
+            return Err(anyhow!(SuitabilityError::InsufficientSwapchainSupport(
+                "no surface formats"
+            )));
+        }
+        if sc_support.present_modes.is_empty() {
+            return Err(anyhow!(SuitabilityError::InsufficientSwapchainSupport(
+                "no present modes"
+            )));
+        }
     }
 
     let features = instance.get_physical_device_features(physical_device);
-    if features.sampler_anisotropy != vk::TRUE {
-        return Err(anyhow!(SuitabilityError("No sampler anisotropy.")));
+    let missing = REQUIRED_FEATURES
+        .iter()
+        .filter(|(_, supported)| supported(&features) != vk::TRUE)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(anyhow!(SuitabilityError::MissingFeatures(missing)));
     }
 
     Ok(())
 }
 
-unsafe fn check_physical_device_extensions(
+unsafe fn supported_device_extensions(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
-) -> Result<()> {
-    let extensions = instance
+) -> Result<HashSet<vk::ExtensionName>> {
+    Ok(instance
         .enumerate_device_extension_properties(physical_device, None)?
         .iter()
         .map(|e| e.extension_name)
-        .collect::<HashSet<_>>();
+        .collect())
+}
+
+unsafe fn check_physical_device_extensions(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Result<()> {
+    let extensions = supported_device_extensions(instance, physical_device)?;
+
+    let missing = device_extensions(data)
+        .iter()
+        .filter(|e| !extensions.contains(e))
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
 
-    // TODO: print, which one is missing
-    if DEVICE_EXTENSIONS.iter().all(|e| extensions.contains(e)) {
+    if missing.is_empty() {
         Ok(())
     } else {
-        Err(anyhow!(SuitabilityError(
-            "Missing required device extensions."
-        )))
+        Err(anyhow!(SuitabilityError::MissingExtensions(missing)))
     }
 }
 
-pub unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) -> Result<Device> {
+pub unsafe fn create_logical_device(
+    instance: &Arc<OwnedInstance>,
+    data: &mut AppData,
+) -> Result<Arc<OwnedDevice>> {
     // specify queues to be created
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
 
     let mut unique_indices = HashSet::new();
     unique_indices.insert(indices.graphics);
     unique_indices.insert(indices.presentation);
+    unique_indices.insert(indices.transfer);
 
     // the queue priorities specify the prio of a queue for scheduling of
     // command execution
@@ -100,18 +166,39 @@ pub unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) ->
         .collect::<Vec<_>>();
 
     // enable device specific layers
-    let layers = if validation::ENABLED {
+    let layers = if data.validation {
         vec![validation::LAYER.as_ptr()]
     } else {
         vec![]
     };
 
-    // specify used device features (queried for in check_physical_device)
-    // TODO: nothing special required for now, specify later
+    // specify used device features (queried for in check_physical_device, see
+    // REQUIRED_FEATURES)
     let features = vk::PhysicalDeviceFeatures::builder().sampler_anisotropy(true);
 
+    // the required extensions and the optional ones, which are supported
+    let supported = supported_device_extensions(instance, data.physical_device)?;
+    data.enabled_extensions = device_extensions(data)
+        .iter()
+        .chain(
+            OPTIONAL_DEVICE_EXTENSIONS
+                .iter()
+                .filter(|e| supported.contains(e)),
+        )
+        .cloned()
+        .collect();
+
+    for extension in OPTIONAL_DEVICE_EXTENSIONS {
+        if data.enabled_extensions.contains(extension) {
+            debug!("Enabling optional device extension {}", extension);
+        } else {
+            debug!("Optional device extension {} is not supported", extension);
+        }
+    }
+
     // convert device_extension Strings to null terminated strings
-    let extensions = DEVICE_EXTENSIONS
+    let extensions = data
+        .enabled_extensions
         .iter()
         .map(|e| e.as_ptr())
         .collect::<Vec<_>>();
@@ -128,27 +215,427 @@ pub unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) ->
     // get handle to the graphics queue
     data.graphics_queue = device.get_device_queue(indices.graphics, 0);
     data.present_queue = device.get_device_queue(indices.presentation, 0);
+    data.transfer_queue = device.get_device_queue(indices.transfer, 0);
 
     trace!("graphics queue family index: {}", indices.graphics);
     trace!("presentation queue family index: {}", indices.presentation);
+    trace!("transfer queue family index: {}", indices.transfer);
+
+    Ok(OwnedDevice::new(instance, device))
+}
+
+/// returns the highest sample count, which is supported for color and depth
+/// attachments by the selected physical device
+pub unsafe fn get_max_msaa_samples(instance: &Instance, data: &AppData) -> vk::SampleCountFlags {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+
+    // we use a depth buffer, so the sample count needs to be supported by both
+    max_sample_count(
+        properties.limits.framebuffer_color_sample_counts
+            & properties.limits.framebuffer_depth_sample_counts,
+    )
+}
+
+// the highest of the given sample counts
+fn max_sample_count(counts: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    [
+        vk::SampleCountFlags::_64,
+        vk::SampleCountFlags::_32,
+        vk::SampleCountFlags::_16,
+        vk::SampleCountFlags::_8,
+        vk::SampleCountFlags::_4,
+        vk::SampleCountFlags::_2,
+    ]
+    .iter()
+    .cloned()
+    .find(|c| counts.contains(*c))
+    .unwrap_or(vk::SampleCountFlags::_1)
+}
+
+/// selects the sample count for multisample anti-aliasing; `requested` is the
+/// number of samples (a power of two up to 64), which is reduced to the highest
+/// count supported by the physical device
+pub unsafe fn pick_msaa_samples(
+    instance: &Instance,
+    data: &mut AppData,
+    requested: u32,
+) -> Result<()> {
+    data.msaa_samples = clamp_msaa_samples(requested, get_max_msaa_samples(instance, data))?;
+
+    info!("Using {:?} MSAA samples", data.msaa_samples);
+
+    Ok(())
+}
+
+// converts the number of samples into the flag and reduces it to `max`
+fn clamp_msaa_samples(requested: u32, max: vk::SampleCountFlags) -> Result<vk::SampleCountFlags> {
+    let requested = match requested {
+        1 => vk::SampleCountFlags::_1,
+        2 => vk::SampleCountFlags::_2,
+        4 => vk::SampleCountFlags::_4,
+        8 => vk::SampleCountFlags::_8,
+        16 => vk::SampleCountFlags::_16,
+        32 => vk::SampleCountFlags::_32,
+        64 => vk::SampleCountFlags::_64,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported MSAA sample count {} (expected 1, 2, 4, 8, 16, 32 or 64).",
+                requested
+            ))
+        }
+    };
+
+    // the flags are single bits, so they can be compared by their value
+    if requested.bits() > max.bits() {
+        warn!(
+            "Requested {:?} MSAA samples, but only {:?} are supported",
+            requested, max
+        );
+        Ok(max)
+    } else {
+        Ok(requested)
+    }
+}
+
+/// features, which are not required, but make a device preferable, if it
+/// supports them
+const OPTIONAL_FEATURES: &[(&str, FeatureQuery)] = &[
+    ("sample_rate_shading", |f| f.sample_rate_shading),
+    ("fill_mode_non_solid", |f| f.fill_mode_non_solid),
+    ("wide_lines", |f| f.wide_lines),
+    ("texture_compression_bc", |f| f.texture_compression_bc),
+];
+
+/// rating of a suitable physical device; devices are compared by type first,
+/// then by the size of their device local memory and then by the number of
+/// supported optional features
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceScore {
+    // discrete > integrated > virtual > cpu > other
+    pub type_rank: u32,
+    pub device_local_memory: vk::DeviceSize,
+    pub optional_features: usize,
+}
+
+/// a physical device and either its score or the reason, why it can't be used
+#[derive(Debug)]
+pub struct DeviceCandidate {
+    // position in the enumeration order, which can be used to select the device
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    pub score: DeviceScore,
+    pub rejection: Option<SuitabilityError>,
+    // optional extensions, which would be enabled for this device
+    pub optional_extensions: Vec<String>,
+    physical_device: vk::PhysicalDevice,
+}
+
+/// all physical devices, which were considered, and the one which was selected
+#[derive(Debug, Default)]
+pub struct DeviceReport {
+    pub candidates: Vec<DeviceCandidate>,
+    // index into candidates
+    pub selected: Option<usize>,
+}
+
+impl DeviceReport {
+    pub fn selected(&self) -> Option<&DeviceCandidate> {
+        self.selected.map(|i| &self.candidates[i])
+    }
+
+    /// the devices, which can't be used, along with the reason
+    pub fn rejected(&self) -> impl Iterator<Item = (&DeviceCandidate, &SuitabilityError)> {
+        self.candidates
+            .iter()
+            .filter_map(|c| c.rejection.as_ref().map(|r| (c, r)))
+    }
+}
+
+fn type_rank(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    }
+}
+
+unsafe fn score_physical_device(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceScore {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let memory = instance.get_physical_device_memory_properties(physical_device);
+    let features = instance.get_physical_device_features(physical_device);
 
-    Ok(device)
+    // integrated gpus may report a device local heap as well (shared with the
+    // cpu), so the type is compared first
+    let device_local_memory = memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|h| h.size)
+        .sum();
+
+    let optional_features = OPTIONAL_FEATURES
+        .iter()
+        .filter(|(_, supported)| supported(&features) == vk::TRUE)
+        .count();
+
+    DeviceScore {
+        type_rank: type_rank(properties.device_type),
+        device_local_memory,
+        optional_features,
+    }
 }
 
-pub unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
-    for device in instance.enumerate_physical_devices()? {
+/// checks and scores all physical devices; errors of the checks, which are
+/// not a `SuitabilityError`, are returned
+pub unsafe fn rate_physical_devices(
+    instance: &Instance,
+    data: &AppData,
+) -> Result<Vec<DeviceCandidate>> {
+    let mut candidates = Vec::new();
+    for (index, device) in instance
+        .enumerate_physical_devices()?
+        .into_iter()
+        .enumerate()
+    {
         let properties = instance.get_physical_device_properties(device);
         trace!("Checking physical device {}", properties.device_name);
-        if let Err(error) = check_physical_device(instance, data, device) {
-            warn!(
-                "Skipping physical device ('{}'): {}",
-                properties.device_name, error
+
+        let rejection = match check_physical_device(instance, data, device) {
+            Ok(()) => None,
+            Err(error) => Some(error.downcast::<SuitabilityError>()?),
+        };
+
+        let supported = supported_device_extensions(instance, device)?;
+        let optional_extensions = OPTIONAL_DEVICE_EXTENSIONS
+            .iter()
+            .filter(|e| supported.contains(e))
+            .map(|e| e.to_string())
+            .collect();
+
+        candidates.push(DeviceCandidate {
+            index,
+            name: properties.device_name.to_string(),
+            device_type: properties.device_type,
+            api_version: properties.api_version,
+            score: score_physical_device(instance, device),
+            rejection,
+            optional_extensions,
+            physical_device: device,
+        });
+    }
+    Ok(candidates)
+}
+
+/// returns the index of the candidate, which should be used: the one given by
+/// the selector (if it is suitable) or the suitable one with the highest score
+pub fn select_physical_device(
+    candidates: &[DeviceCandidate],
+    selector: Option<&DeviceSelector>,
+) -> Result<usize> {
+    let Some(selector) = selector else {
+        // max_by_key returns the last maximum, but we prefer the first one
+        return candidates
+            .iter()
+            .filter(|c| c.rejection.is_none())
+            .rev()
+            .max_by_key(|c| c.score)
+            .map(|c| c.index)
+            .ok_or_else(|| anyhow!("Failed to select a physical device: no device is suitable."));
+    };
+
+    let candidate = candidates
+        .iter()
+        .find(|c| selector.matches(c.index, &c.name))
+        .ok_or_else(|| {
+            let available = candidates
+                .iter()
+                .map(|c| format!("{}: {}", c.index, c.name))
+                .collect::<Vec<_>>();
+            anyhow!(
+                "No physical device matches {} (available: {}).",
+                selector,
+                available.join(", ")
             )
-        } else {
-            info!("Selecting physical device ('{}')", properties.device_name);
-            data.physical_device = device;
-            return Ok(());
+        })?;
+
+    match &candidate.rejection {
+        Some(error) => Err(anyhow!(
+            "Physical device {} ('{}') was selected, but can't be used: {}",
+            candidate.index,
+            candidate.name,
+            error
+        )),
+        None => Ok(candidate.index),
+    }
+}
+
+/// selects the physical device given by the selector or the best suitable one
+/// (see `DeviceScore`); the candidates are kept in `data.device_report`
+pub unsafe fn pick_physical_device(
+    instance: &Instance,
+    data: &mut AppData,
+    selector: Option<&DeviceSelector>,
+) -> Result<()> {
+    let mut report = DeviceReport {
+        candidates: rate_physical_devices(instance, data)?,
+        selected: None,
+    };
+
+    for (candidate, error) in report.rejected() {
+        warn!("Skipping physical device ('{}'): {}", candidate.name, error);
+    }
+
+    let selected = select_physical_device(&report.candidates, selector)?;
+    let candidate = &report.candidates[selected];
+    info!(
+        "Selecting physical device ('{}', {:?})",
+        candidate.name, candidate.score
+    );
+    data.physical_device = candidate.physical_device;
+
+    report.selected = Some(selected);
+    data.device_report = report;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(index: usize, name: &str, score: DeviceScore) -> DeviceCandidate {
+        DeviceCandidate {
+            index,
+            name: name.to_string(),
+            device_type: vk::PhysicalDeviceType::OTHER,
+            api_version: 0,
+            score,
+            rejection: None,
+            optional_extensions: Vec::new(),
+            physical_device: vk::PhysicalDevice::null(),
+        }
+    }
+
+    fn score(
+        device_type: vk::PhysicalDeviceType,
+        device_local_memory: vk::DeviceSize,
+        optional_features: usize,
+    ) -> DeviceScore {
+        DeviceScore {
+            type_rank: type_rank(device_type),
+            device_local_memory,
+            optional_features,
         }
     }
-    Err(anyhow!("Failed to select a physical device"))
+
+    #[test]
+    fn scores_compare_type_then_memory_then_features() {
+        use vk::PhysicalDeviceType as T;
+
+        // a discrete gpu wins even with less memory and no optional features
+        assert!(score(T::DISCRETE_GPU, 1, 0) > score(T::INTEGRATED_GPU, 16, 4));
+        assert!(score(T::INTEGRATED_GPU, 1, 0) > score(T::VIRTUAL_GPU, 16, 4));
+        assert!(score(T::VIRTUAL_GPU, 1, 0) > score(T::CPU, 16, 4));
+        assert!(score(T::CPU, 1, 0) > score(T::OTHER, 16, 4));
+
+        assert!(score(T::DISCRETE_GPU, 8, 0) > score(T::DISCRETE_GPU, 4, 4));
+        assert!(score(T::DISCRETE_GPU, 8, 2) > score(T::DISCRETE_GPU, 8, 1));
+    }
+
+    #[test]
+    fn best_suitable_device_is_selected() {
+        use vk::PhysicalDeviceType as T;
+
+        let mut candidates = vec![
+            candidate(0, "llvmpipe", score(T::CPU, 0, 4)),
+            candidate(1, "Integrated", score(T::INTEGRATED_GPU, 8, 2)),
+            candidate(2, "Discrete", score(T::DISCRETE_GPU, 8, 2)),
+            candidate(3, "Discrete", score(T::DISCRETE_GPU, 8, 2)),
+        ];
+        // the first of equally good devices
+        assert_eq!(select_physical_device(&candidates, None).unwrap(), 2);
+
+        candidates[2].rejection = Some(SuitabilityError::MissingFeatures(vec!["wide_lines"]));
+        candidates[3].rejection = Some(SuitabilityError::MissingFeatures(vec!["wide_lines"]));
+        assert_eq!(select_physical_device(&candidates, None).unwrap(), 1);
+
+        candidates[1].rejection = Some(SuitabilityError::MissingFeatures(vec!["wide_lines"]));
+        candidates[0].rejection = Some(SuitabilityError::MissingFeatures(vec!["wide_lines"]));
+        assert!(select_physical_device(&candidates, None).is_err());
+    }
+
+    #[test]
+    fn selector_matches_index_or_name() {
+        use vk::PhysicalDeviceType as T;
+
+        let mut candidates = vec![
+            candidate(0, "NVIDIA GeForce", score(T::DISCRETE_GPU, 8, 0)),
+            candidate(1, "llvmpipe (LLVM 15)", score(T::CPU, 0, 0)),
+        ];
+
+        let select = |candidates: &[DeviceCandidate], selector| {
+            select_physical_device(candidates, Some(&selector))
+        };
+
+        // the selector overrides the score
+        assert_eq!(select(&candidates, DeviceSelector::Index(1)).unwrap(), 1);
+        assert_eq!(
+            select(&candidates, DeviceSelector::Name("LLVMPIPE".into())).unwrap(),
+            1
+        );
+        assert_eq!(
+            select(&candidates, DeviceSelector::Name("geforce".into())).unwrap(),
+            0
+        );
+
+        let error = select(&candidates, DeviceSelector::Index(2)).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("No physical device matches index 2"));
+        let error = select(&candidates, DeviceSelector::Name("radeon".into())).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("No physical device matches name 'radeon'"));
+
+        // a selected device, which can't be used, is an error instead of falling
+        // back to another one
+        candidates[1].rejection = Some(SuitabilityError::MissingExtensions(vec![
+            "VK_KHR_swapchain".into(),
+        ]));
+        let error = select(&candidates, DeviceSelector::Index(1)).unwrap_err();
+        assert!(error.to_string().contains("can't be used"));
+    }
+
+    #[test]
+    fn msaa_samples_are_clamped_to_the_supported_maximum() {
+        let max = max_sample_count(
+            vk::SampleCountFlags::_1 | vk::SampleCountFlags::_2 | vk::SampleCountFlags::_4,
+        );
+        assert_eq!(max, vk::SampleCountFlags::_4);
+        assert_eq!(
+            max_sample_count(vk::SampleCountFlags::_1),
+            vk::SampleCountFlags::_1
+        );
+
+        assert_eq!(
+            clamp_msaa_samples(1, max).unwrap(),
+            vk::SampleCountFlags::_1
+        );
+        assert_eq!(
+            clamp_msaa_samples(4, max).unwrap(),
+            vk::SampleCountFlags::_4
+        );
+        assert_eq!(
+            clamp_msaa_samples(16, max).unwrap(),
+            vk::SampleCountFlags::_4
+        );
+        assert!(clamp_msaa_samples(3, max).is_err());
+        assert!(clamp_msaa_samples(0, max).is_err());
+    }
 }
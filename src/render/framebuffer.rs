@@ -1,39 +1,48 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+
+use std::sync::Arc;
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::app::AppData;
 
+use super::handle::{Framebuffer, OwnedDevice};
+
 // attachments specified in render pass creation are bound by wrapping them into
 // vk::Framebuffer objects -> references an vk::ImageView, that represents the attachment
 //
-// The image we use for attachment depends on which image is returned by the swapchain
-// if we retrieve one for presentation -> create framebuffers for each imageView!
-pub unsafe fn create_framebuffers(device: &Device, data: &mut AppData) -> Result<()> {
-    log::debug!("creating framebuffers");
-
-    debug_assert!(data.swapchain_image_views.len() > 0);
-
-    data.framebuffers = data
-        .swapchain_image_views
-        .iter()
-        .map(|i| {
-            // use imageview as attachment
-            let attachments = &[*i];
-            let create_info = vk::FramebufferCreateInfo::builder()
-                // render pass with which this framebuffer needs to be compatible with
-                // -> roughly means same number and type of attachments
-                .render_pass(data.render_pass)
-                .attachments(attachments)
-                // define dimensions of the framebuffer
-                .width(data.swapchain_extent.width)
-                .height(data.swapchain_extent.height)
-                .layers(1);
-            device.create_framebuffer(&create_info, None)
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+// the main render pass draws into the HDR image, which is shared by all
+// swapchain images, so a single framebuffer is enough; the swapchain images are
+// written by the tone mapping pass, which has its own framebuffers (see tonemap.rs)
+pub unsafe fn create_framebuffer(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    log::debug!("creating framebuffer");
 
-    debug_assert!(data.framebuffers.len() > 0);
+    // the depth image (and the multisampled color image) is shared as well;
+    // the external dependency of the render pass orders the writes of
+    // consecutive frames (see render_pass.rs)
+    //
+    // with multisampling, the HDR image is the resolve attachment
+    let multisampled = [
+        *data.color_image_view,
+        *data.depth_image_view,
+        *data.hdr_image_view,
+    ];
+    let single_sampled = [*data.hdr_image_view, *data.depth_image_view];
+    let attachments: &[vk::ImageView] = if data.msaa_samples != vk::SampleCountFlags::_1 {
+        &multisampled
+    } else {
+        &single_sampled
+    };
+    let create_info = vk::FramebufferCreateInfo::builder()
+        // render pass with which this framebuffer needs to be compatible with
+        // -> roughly means same number and type of attachments
+        .render_pass(*data.render_pass)
+        .attachments(attachments)
+        // define dimensions of the framebuffer
+        .width(data.swapchain_extent.width)
+        .height(data.swapchain_extent.height)
+        .layers(1);
+    data.framebuffer = Framebuffer::new(device, device.create_framebuffer(&create_info, None)?);
 
     Ok(())
 }
@@ -0,0 +1,600 @@
+use anyhow::{anyhow, Result};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use gltf::image::Format;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::render::handle::OwnedDevice;
+use crate::scene::{Material, Mesh};
+
+use super::image::{self, SamplerOptions};
+use super::model::generate_normals;
+use super::pipeline::Vertex;
+
+/// a glTF scene, which is converted into the data of the renderer, but not
+/// uploaded yet (see create_gltf_scene)
+#[derive(Clone, Debug, Default)]
+pub struct GltfModel {
+    // geometry of all primitives, which is appended to the vertex and index buffer
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    // one mesh per primitive; the vertex offsets and first indices refer to the
+    // vertices and indices above
+    pub meshes: Vec<Mesh>,
+    // one texture per glTF texture, in the same order
+    pub textures: Vec<TextureData>,
+    // the materials of the file, followed by the default material, which is used
+    // by primitives without material
+    pub materials: Vec<GltfMaterial>,
+    // parents are always stored before their children
+    pub nodes: Vec<GltfNode>,
+}
+
+/// decoded image of a texture along with its sampler
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    // 8 bit RGBA, row by row
+    pub pixels: Vec<u8>,
+    pub sampler: SamplerOptions,
+}
+
+/// the factors and the base color texture of a metallic-roughness material
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GltfMaterial {
+    pub base_color_factor: glm::Vec4,
+    // index into GltfModel::textures
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+}
+
+// the defaults of the glTF specification
+impl Default for GltfMaterial {
+    fn default() -> Self {
+        Self {
+            base_color_factor: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: String,
+    // index into GltfModel::nodes
+    pub parent: Option<usize>,
+    // relative to the parent
+    pub transform: glm::Mat4,
+    pub mesh: Option<usize>,
+    pub material: usize,
+}
+
+/// whether the file is loaded with load_gltf (.gltf or .glb) instead of as
+/// wavefront obj
+pub fn is_gltf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gltf") || e.eq_ignore_ascii_case("glb"))
+}
+
+/// loads the default scene (or the first scene) of a glTF 2.0 file, either as
+/// .gltf with external or embedded buffers and images or as binary .glb
+///
+/// normals are generated, if a primitive does not define them; primitives,
+/// which are not made of triangles, are skipped
+pub fn load_gltf(path: &Path) -> Result<GltfModel> {
+    log::info!("Loading glTF scene {}", path.display());
+
+    let (document, buffers, images) = gltf::import(path)
+        .map_err(|e| anyhow!("Failed to load glTF file {}: {}", path.display(), e))?;
+
+    let model = convert(&document, &buffers, &images)
+        .map_err(|e| anyhow!("Invalid glTF file {}: {}", path.display(), e))?;
+
+    log::debug!(
+        "Loaded {} meshes, {} textures and {} nodes with {} vertices",
+        model.meshes.len(),
+        model.textures.len(),
+        model.nodes.len(),
+        model.vertices.len()
+    );
+
+    Ok(model)
+}
+
+// converts a document, whose buffers and images were already loaded
+fn convert(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    images: &[gltf::image::Data],
+) -> Result<GltfModel> {
+    let mut model = GltfModel::default();
+
+    for texture in document.textures() {
+        let index = texture.source().index();
+        let image = images
+            .get(index)
+            .ok_or_else(|| anyhow!("Image {} of texture {} is missing.", index, texture.index()))?;
+
+        model.textures.push(TextureData {
+            width: image.width,
+            height: image.height,
+            pixels: to_rgba8(image)?,
+            sampler: sampler_options(&texture.sampler()),
+        });
+    }
+
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        model.materials.push(GltfMaterial {
+            base_color_factor: glm::make_vec4(&pbr.base_color_factor()),
+            base_color_texture: pbr.base_color_texture().map(|t| t.texture().index()),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+        });
+    }
+
+    let default_material = model.materials.len();
+    model.materials.push(GltfMaterial::default());
+
+    // the converted primitives of each glTF mesh as (mesh, material)
+    let mut primitives = Vec::new();
+    for mesh in document.meshes() {
+        let mut converted = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "Skipping primitive {} of mesh {}, it uses {:?} instead of triangles",
+                    primitive.index(),
+                    mesh.index(),
+                    primitive.mode()
+                );
+                continue;
+            }
+
+            let index = load_primitive(&mut model, &primitive, buffers)?;
+            let material = primitive.material().index().unwrap_or(default_material);
+            converted.push((index, material));
+        }
+        primitives.push(converted);
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("The file contains no scene."))?;
+
+    let mut visited = vec![false; document.nodes().len()];
+    for node in scene.nodes() {
+        add_node(
+            &mut model,
+            &node,
+            None,
+            &primitives,
+            default_material,
+            &mut visited,
+        )?;
+    }
+
+    if model.indices.is_empty() {
+        return Err(anyhow!("The scene contains no triangles."));
+    }
+
+    Ok(model)
+}
+
+// appends the vertices and indices of the primitive and returns its mesh
+fn load_primitive(
+    model: &mut GltfModel,
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Result<usize> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|b| &b[..]));
+
+    let positions = reader
+        .read_positions()
+        .ok_or_else(|| anyhow!("Primitive {} has no positions.", primitive.index()))?
+        .collect::<Vec<_>>();
+
+    // without indices, every three vertices form a triangle
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    if let Some(index) = indices.iter().find(|i| **i as usize >= positions.len()) {
+        return Err(anyhow!(
+            "Invalid vertex index {} in primitive {}.",
+            index,
+            primitive.index()
+        ));
+    }
+
+    let normals = match reader.read_normals() {
+        Some(normals) => normals.map(|n| glm::make_vec3(&n)).collect(),
+        None => generate_normals(positions.as_flattened(), &indices),
+    };
+    // the texture coordinates of glTF start at the top left corner of the image,
+    // like the uploaded textures, so they don't need to be flipped
+    let tex_coords = reader
+        .read_tex_coords(0)
+        .map(|t| t.into_f32().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let colors = reader
+        .read_colors(0)
+        .map(|c| c.into_rgb_f32().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mesh = Mesh {
+        first_index: model.indices.len() as u32,
+        index_count: indices.len() as u32,
+        vertex_offset: model.vertices.len() as i32,
+    };
+
+    for (i, position) in positions.iter().enumerate() {
+        let color = colors
+            .get(i)
+            .map(|c| glm::make_vec3(c))
+            .unwrap_or_else(|| glm::vec3(1.0, 1.0, 1.0));
+        let tex_coord = tex_coords
+            .get(i)
+            .map(|t| glm::make_vec2(t))
+            .unwrap_or_else(glm::Vec2::zeros);
+        let normal = normals.get(i).copied().unwrap_or_else(glm::Vec3::zeros);
+
+        model.vertices.push(Vertex::new(
+            glm::make_vec3(position),
+            color,
+            tex_coord,
+            normal,
+        ));
+    }
+
+    model.indices.extend(indices);
+    model.meshes.push(mesh);
+    Ok(model.meshes.len() - 1)
+}
+
+// adds the node and its descendants; a scene node draws a single mesh with a
+// single material, so the other primitives of the mesh become its children
+fn add_node(
+    model: &mut GltfModel,
+    node: &gltf::Node,
+    parent: Option<usize>,
+    primitives: &[Vec<(usize, usize)>],
+    default_material: usize,
+    visited: &mut [bool],
+) -> Result<()> {
+    // the nodes have to form trees; this also prevents endless recursion
+    if std::mem::replace(&mut visited[node.index()], true) {
+        return Err(anyhow!("Node {} has more than one parent.", node.index()));
+    }
+
+    let name = node
+        .name()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("node {}", node.index()));
+    let node_primitives = node
+        .mesh()
+        .map(|m| primitives[m.index()].as_slice())
+        .unwrap_or_default();
+    let (mesh, material) = match node_primitives.first() {
+        Some((mesh, material)) => (Some(*mesh), *material),
+        None => (None, default_material),
+    };
+
+    model.nodes.push(GltfNode {
+        name: name.clone(),
+        parent,
+        // column major, like glm
+        transform: glm::make_mat4(node.transform().matrix().as_flattened()),
+        mesh,
+        material,
+    });
+    let index = model.nodes.len() - 1;
+
+    for (i, (mesh, material)) in node_primitives.iter().enumerate().skip(1) {
+        model.nodes.push(GltfNode {
+            name: format!("{} (primitive {})", name, i),
+            parent: Some(index),
+            transform: glm::identity(),
+            mesh: Some(*mesh),
+            material: *material,
+        });
+    }
+
+    for child in node.children() {
+        add_node(
+            model,
+            &child,
+            Some(index),
+            primitives,
+            default_material,
+            visited,
+        )?;
+    }
+
+    Ok(())
+}
+
+// the textures of the renderer are 8 bit RGBA; gray images are expanded and 16
+// bit channels are reduced to their most significant byte
+fn to_rgba8(image: &gltf::image::Data) -> Result<Vec<u8>> {
+    let (channels, wide) = match image.format {
+        Format::R8 => (1, false),
+        Format::R8G8 => (2, false),
+        Format::R8G8B8 => (3, false),
+        Format::R8G8B8A8 => (4, false),
+        Format::R16 => (1, true),
+        Format::R16G16 => (2, true),
+        Format::R16G16B16 => (3, true),
+        Format::R16G16B16A16 => (4, true),
+        format => return Err(anyhow!("Unsupported image format {:?}.", format)),
+    };
+
+    let values = if wide {
+        image
+            .pixels
+            .chunks_exact(2)
+            .map(|c| (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8)
+            .collect()
+    } else {
+        image.pixels.clone()
+    };
+
+    let pixels = values
+        .chunks_exact(channels)
+        .flat_map(|pixel| match *pixel {
+            [l] => [l, l, l, u8::MAX],
+            [l, a] => [l, l, l, a],
+            [r, g, b] => [r, g, b, u8::MAX],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!("pixels have 1 to 4 channels"),
+        })
+        .collect();
+
+    Ok(pixels)
+}
+
+// the textures always have a mip chain, so filters without mipmapping use the
+// nearest mip level
+fn sampler_options(sampler: &gltf::texture::Sampler) -> SamplerOptions {
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+        Some(MagFilter::Linear) | None => vk::Filter::LINEAR,
+    };
+
+    let (min_filter, mipmap_mode) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+            (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST)
+        }
+        Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => {
+            (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST)
+        }
+        Some(MinFilter::NearestMipmapLinear) => {
+            (vk::Filter::NEAREST, vk::SamplerMipmapMode::LINEAR)
+        }
+        Some(MinFilter::LinearMipmapLinear) | None => {
+            (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR)
+        }
+    };
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+    };
+
+    SamplerOptions {
+        mag_filter,
+        min_filter,
+        mipmap_mode,
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+    }
+}
+
+/// adds the model to the scene below the given parent node: the textures are
+/// uploaded by the upload manager, the geometry is appended to the vertices and
+/// indices, which still have to be uploaded into the vertex and index buffer
+pub unsafe fn create_gltf_scene(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    model: GltfModel,
+    parent: usize,
+) -> Result<()> {
+    // materials without base color texture sample a white texture, so only
+    // their factor remains
+    let mut white = image::create_texture_from_pixels(instance, device, data, vec![255; 4], 1, 1)?;
+    image::create_texture_image_view(device, &mut white)?;
+    data.textures.push(white);
+    let white = data.textures.len() - 1;
+
+    let first_texture = data.textures.len();
+    for texture in model.textures {
+        let mut created = image::create_texture_from_pixels(
+            instance,
+            device,
+            data,
+            texture.pixels,
+            texture.width,
+            texture.height,
+        )?;
+        image::create_texture_image_view(device, &mut created)?;
+        created.sampler = image::create_sampler(device, &texture.sampler, created.mip_levels)?;
+        data.textures.push(created);
+    }
+
+    let first_material = data.scene.materials.len();
+    for material in &model.materials {
+        data.scene.add_material(Material {
+            texture: material
+                .base_color_texture
+                .map_or(white, |t| first_texture + t),
+            base_color_factor: material.base_color_factor,
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+        });
+    }
+
+    // the meshes refer to the geometry of the model, which is appended to the
+    // geometry of the scene
+    let first_mesh = data.scene.meshes.len();
+    for mesh in &model.meshes {
+        data.scene.add_mesh(Mesh {
+            first_index: data.indices.len() as u32 + mesh.first_index,
+            index_count: mesh.index_count,
+            vertex_offset: data.vertices.len() as i32 + mesh.vertex_offset,
+        });
+    }
+    data.vertices.extend(model.vertices);
+    data.indices.extend(model.indices);
+
+    let first_node = data.scene.nodes.len();
+    for node in &model.nodes {
+        data.scene.add_node(
+            &node.name,
+            Some(node.parent.map_or(parent, |p| first_node + p)),
+            node.transform,
+            node.mesh.map(|m| first_mesh + m),
+            first_material + node.material,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the sample files embed their buffers and images
+    fn load(name: &str) -> GltfModel {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/gltf")
+            .join(name);
+        load_gltf(&path).unwrap()
+    }
+
+    #[test]
+    fn loads_gltf_with_embedded_texture() {
+        let model = load("triangle.gltf");
+
+        // the triangle has no normals, so they are generated from its winding
+        let white = glm::vec3(1.0, 1.0, 1.0);
+        let normal = glm::vec3(0.0, 0.0, 1.0);
+        assert_eq!(
+            model.vertices,
+            vec![
+                Vertex::new(glm::vec3(0.0, 0.0, 0.0), white, glm::vec2(0.0, 1.0), normal),
+                Vertex::new(glm::vec3(1.0, 0.0, 0.0), white, glm::vec2(1.0, 1.0), normal),
+                Vertex::new(glm::vec3(0.0, 1.0, 0.0), white, glm::vec2(0.0, 0.0), normal),
+            ]
+        );
+        assert_eq!(model.indices, vec![0, 1, 2]);
+        assert_eq!(
+            model.meshes,
+            vec![Mesh {
+                first_index: 0,
+                index_count: 3,
+                vertex_offset: 0,
+            }]
+        );
+
+        // the rgb png is expanded to rgba
+        let texture = &model.textures[0];
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(&texture.pixels[..8], &[255, 0, 0, 255, 0, 255, 0, 255]);
+        assert_eq!(
+            texture.sampler,
+            SamplerOptions {
+                mag_filter: vk::Filter::NEAREST,
+                min_filter: vk::Filter::NEAREST,
+                mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_v: vk::SamplerAddressMode::MIRRORED_REPEAT,
+            }
+        );
+
+        assert_eq!(
+            model.materials,
+            vec![
+                GltfMaterial {
+                    base_color_factor: glm::vec4(1.0, 0.5, 0.25, 1.0),
+                    base_color_texture: Some(0),
+                    metallic_factor: 0.0,
+                    roughness_factor: 0.5,
+                },
+                GltfMaterial::default(),
+            ]
+        );
+
+        let [root, triangle] = &model.nodes[..] else {
+            panic!("expected two nodes, got {:?}", model.nodes);
+        };
+        assert_eq!(root.name, "root");
+        assert_eq!(root.parent, None);
+        assert_eq!(root.mesh, None);
+        assert_eq!(root.transform, glm::translation(&glm::vec3(1.0, 2.0, 3.0)));
+        assert_eq!(triangle.parent, Some(0));
+        assert_eq!(triangle.mesh, Some(0));
+        assert_eq!(triangle.material, 0);
+    }
+
+    #[test]
+    fn loads_glb_with_multiple_primitives() {
+        let model = load("primitives.glb");
+
+        // the line strip is skipped; the triangle without indices gets its own
+        assert_eq!(
+            model.meshes,
+            vec![
+                Mesh {
+                    first_index: 0,
+                    index_count: 3,
+                    vertex_offset: 0,
+                },
+                Mesh {
+                    first_index: 3,
+                    index_count: 3,
+                    vertex_offset: 3,
+                },
+            ]
+        );
+        assert_eq!(model.indices, vec![0, 1, 2, 0, 1, 2]);
+        assert!(model.textures.is_empty());
+
+        // the first primitive is drawn by the node, the second by a child
+        let [shape, primitive] = &model.nodes[..] else {
+            panic!("expected two nodes, got {:?}", model.nodes);
+        };
+        assert_eq!(shape.transform, glm::scaling(&glm::vec3(2.0, 2.0, 2.0)));
+        assert_eq!(shape.mesh, Some(0));
+        assert_eq!(shape.material, 1);
+        assert_eq!(model.materials[1], GltfMaterial::default());
+        assert_eq!(primitive.parent, Some(0));
+        assert_eq!(primitive.transform, glm::identity::<f32, 4>());
+        assert_eq!(primitive.mesh, Some(1));
+        assert_eq!(primitive.material, 0);
+        assert_eq!(
+            model.materials[0].base_color_factor,
+            glm::vec4(0.0, 1.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn recognizes_gltf_extensions() {
+        assert!(is_gltf(Path::new("scene.gltf")));
+        assert!(is_gltf(Path::new("scene.GLB")));
+        assert!(!is_gltf(Path::new("model.obj")));
+    }
+}
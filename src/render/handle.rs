@@ -0,0 +1,262 @@
+use anyhow::Result;
+
+use std::fmt;
+use std::ops::Deref;
+use std::os::raw::c_void;
+use std::panic::Location;
+use std::sync::Arc;
+
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::{ExtDebugUtilsExtension, KhrSurfaceExtension, KhrSwapchainExtension};
+
+use super::allocator::Memory;
+use super::leak;
+
+// every vulkan object has to be destroyed before the object it was created from
+// (e.g. a buffer before the device, the device before the instance); the owning
+// wrappers in this module keep an Arc to their parent, so the parent is only
+// destroyed, after the last of its children was dropped
+
+/// the vulkan instance and the objects, which are created directly from it
+///
+/// the debug messenger and the surface are destroyed along with the instance
+pub struct OwnedInstance {
+    // keeps the loaded Vulkan library alive
+    _entry: Entry,
+    instance: Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+    surface: vk::SurfaceKHR,
+}
+
+impl OwnedInstance {
+    /// takes ownership of the instance, the messenger and the surface (the latter
+    /// two may be null)
+    pub unsafe fn new(
+        entry: Entry,
+        instance: Instance,
+        messenger: vk::DebugUtilsMessengerEXT,
+        surface: vk::SurfaceKHR,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            _entry: entry,
+            instance,
+            messenger,
+            surface,
+        })
+    }
+}
+
+impl Deref for OwnedInstance {
+    type Target = Instance;
+
+    fn deref(&self) -> &Self::Target {
+        &self.instance
+    }
+}
+
+impl fmt::Debug for OwnedInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OwnedInstance")
+            .field(&self.instance.handle())
+            .finish()
+    }
+}
+
+impl Drop for OwnedInstance {
+    fn drop(&mut self) {
+        log::debug!("Destroying instance");
+        unsafe {
+            // if validation is enabled, the debug messenger needs to be destroyed,
+            // before the instance is destroyed
+            if !self.messenger.is_null() {
+                self.instance
+                    .destroy_debug_utils_messenger_ext(self.messenger, None);
+            }
+            // headless instances neither create a surface nor enable
+            // VK_KHR_surface, so vkDestroySurfaceKHR must not be called
+            if !self.surface.is_null() {
+                self.instance.destroy_surface_khr(self.surface, None);
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// the logical device; it is destroyed, when the last object created from it
+/// was dropped
+pub struct OwnedDevice {
+    device: Device,
+    // the device has to be destroyed before the instance
+    _instance: Arc<OwnedInstance>,
+}
+
+impl OwnedDevice {
+    pub unsafe fn new(instance: &Arc<OwnedInstance>, device: Device) -> Arc<Self> {
+        Arc::new(Self {
+            device,
+            _instance: instance.clone(),
+        })
+    }
+}
+
+impl Deref for OwnedDevice {
+    type Target = Device;
+
+    fn deref(&self) -> &Self::Target {
+        &self.device
+    }
+}
+
+impl fmt::Debug for OwnedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OwnedDevice")
+            .field(&self.device.handle())
+            .finish()
+    }
+}
+
+impl Drop for OwnedDevice {
+    fn drop(&mut self) {
+        log::debug!("Destroying device");
+        unsafe {
+            // queues are implicitly cleaned up, when the device is destroyed
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+/// a vulkan handle, which is created from the device and can be destroyed with it
+pub trait Destroy: vk::Handle<Repr = u64> {
+    unsafe fn destroy(self, device: &Device);
+}
+
+macro_rules! destroy {
+    ($($alias:ident: $handle:ty => $function:ident),* $(,)?) => {
+        $(
+            impl Destroy for $handle {
+                unsafe fn destroy(self, device: &Device) {
+                    device.$function(self, None);
+                }
+            }
+
+            pub type $alias = Owned<$handle>;
+        )*
+    };
+}
+
+destroy! {
+    CommandPool: vk::CommandPool => destroy_command_pool,
+    DescriptorPool: vk::DescriptorPool => destroy_descriptor_pool,
+    DescriptorSetLayout: vk::DescriptorSetLayout => destroy_descriptor_set_layout,
+    Fence: vk::Fence => destroy_fence,
+    Framebuffer: vk::Framebuffer => destroy_framebuffer,
+    ImageView: vk::ImageView => destroy_image_view,
+    Pipeline: vk::Pipeline => destroy_pipeline,
+    PipelineLayout: vk::PipelineLayout => destroy_pipeline_layout,
+    RenderPass: vk::RenderPass => destroy_render_pass,
+    Sampler: vk::Sampler => destroy_sampler,
+    Semaphore: vk::Semaphore => destroy_semaphore,
+    ShaderModule: vk::ShaderModule => destroy_shader_module,
+    Swapchain: vk::SwapchainKHR => destroy_swapchain_khr,
+    RawBuffer: vk::Buffer => destroy_buffer,
+    RawImage: vk::Image => destroy_image,
+}
+
+/// owns a handle, which is destroyed on drop
+///
+/// dereferences to the raw handle, so it can be passed to the vulkan commands;
+/// the default value holds a null handle and does nothing on drop
+///
+/// in debug builds, the handle is recorded by the leak tracker along with the
+/// place, where it was created (see render::leak)
+#[derive(Debug, Default)]
+pub struct Owned<T: Destroy> {
+    device: Option<Arc<OwnedDevice>>,
+    handle: T,
+}
+
+impl<T: Destroy> Owned<T> {
+    /// takes ownership of a handle, which was created from `device`
+    ///
+    /// the leak tracker records the caller as the creation site; functions,
+    /// which create handles for their callers (like buffer::create_buffer),
+    /// are #[track_caller] as well, so the site is where they were called
+    #[track_caller]
+    pub unsafe fn new(device: &Arc<OwnedDevice>, handle: T) -> Self {
+        leak::track(T::TYPE, handle.as_raw(), Location::caller());
+        Self {
+            device: Some(device.clone()),
+            handle,
+        }
+    }
+}
+
+impl<T: Destroy> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl<T: Destroy> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(device) = &self.device {
+            unsafe { self.handle.destroy(device) };
+            leak::untrack(T::TYPE, self.handle.as_raw());
+        }
+    }
+}
+
+/// a buffer and the memory bound to it
+#[derive(Debug, Default)]
+pub struct Buffer {
+    // the buffer is destroyed, before its memory is freed (fields are dropped
+    // in declaration order)
+    buffer: RawBuffer,
+    memory: Memory,
+}
+
+impl Buffer {
+    pub fn new(buffer: RawBuffer, memory: Memory) -> Self {
+        Self { buffer, memory }
+    }
+
+    /// pointer to the memory of the buffer; only valid for host visible memory
+    pub fn mapped(&self) -> Result<*mut c_void> {
+        self.memory.mapped()
+    }
+}
+
+impl Deref for Buffer {
+    type Target = vk::Buffer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+/// an image and the memory bound to it
+#[derive(Debug, Default)]
+pub struct Image {
+    image: RawImage,
+    // only held to be freed after the image was destroyed
+    _memory: Memory,
+}
+
+impl Image {
+    pub fn new(image: RawImage, memory: Memory) -> Self {
+        Self {
+            image,
+            _memory: memory,
+        }
+    }
+}
+
+impl Deref for Image {
+    type Target = vk::Image;
+
+    fn deref(&self) -> &Self::Target {
+        &self.image
+    }
+}
@@ -1,54 +1,156 @@
 #[allow(dead_code, unused_variables, unused_imports)]
 use anyhow::{anyhow, Result};
 
-use log::info;
 use std::fs::File;
+use std::path::Path;
 use std::ptr::copy_nonoverlapping as memcpy;
+use std::sync::Arc;
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
     app::AppData,
+    render::allocator::{AllocationKind, Memory},
     render::buffer::{self, get_memory_type_index},
+    render::handle::{Image, ImageView, OwnedDevice, RawImage, Sampler},
 };
 
 use super::command_buffer;
+use super::tonemap::HDR_FORMAT;
+use super::upload::Destination;
 
-// TODO: all helper methods that submit command buffers do that synchronously,
-// by waiting for the queue to become idle; practical applications should combine
-// these operations in a single command buffer and execute them asynchronously for
-// higher throughput
-// -> setup_command_buffer, that the helper functions record commands into and add a
-//    flush_setup_commands, to execute the commands that have been recorded so far
-//    It's best to do this after the texture mapping works to check if the texture
-//    resources are still set up correctly
+// textures are loaded from png files with 8 bit RGBA in sRGB color space
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// a sampled image with its mip chain; materials of the scene refer to
+/// textures by their index in AppData::textures
+#[derive(Debug, Default)]
+pub struct Texture {
+    // the view is destroyed before the image
+    pub view: ImageView,
+    pub image: Image,
+    // number of levels in the mip chain of the image
+    pub mip_levels: u32,
+    // sampler with the options of the texture; textures without their own
+    // sampler (a null handle) use the shared AppData::texture_sampler
+    pub sampler: Sampler,
+}
+
+/// filtering and wrapping of a texture (see create_sampler); the default is
+/// trilinear filtering, which repeats the texture
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SamplerOptions {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+        }
+    }
+}
+
+/// loads a png file into a texture (see create_texture_from_pixels)
 pub unsafe fn create_texture_image(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
-) -> Result<()> {
-    let image = File::open("resources/texture.png")?;
+    path: &Path,
+) -> Result<Texture> {
+    let (pixels, width, height) = load_png(path)?;
+
+    create_texture_from_pixels(instance, device, data, pixels, width, height)
+}
 
-    let decoder = png::Decoder::new(image);
+/// decodes a png file into its 8 bit RGBA pixels, width and height
+pub fn load_png(path: &Path) -> Result<(Vec<u8>, u32, u32)> {
+    let image = File::open(path)?;
+
+    let mut decoder = png::Decoder::new(image);
+    // expand palettes and low bit depths and strip 16 bit channels, so every
+    // pixel has 1 to 4 channels with 8 bits each
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
     let mut reader = decoder.read_info()?;
-    // 4 bytes per pixel
-    let mut pixels = vec![0; reader.info().raw_bytes()];
-    reader.next_frame(&mut pixels)?;
-
-    let size = reader.info().raw_bytes() as u64;
-    let (width, height) = reader.info().size();
-    log::debug!("Raw bytes size: {}", size);
-    log::debug!("width: {}, height: {}", width, height);
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    let (width, height) = (info.width, info.height);
+    log::debug!("Raw bytes size: {}", reader.info().raw_bytes());
     log::debug!(
-        "width * height * 4: {}",
-        width as u64 * height as u64 * 4 as u64
+        "width: {}, height: {}, color type: {:?}",
+        width,
+        height,
+        info.color_type
     );
 
-    // the following lines expect the image to have an Alpha channel; the png
-    // crate does not support converting from RGB to RGBA, so make sure, that
-    // the image has an alpha channel
+    // the callers expect 4 bytes per pixel, the png crate can not add the alpha
+    // channel itself, so grayscale and RGB images are expanded here (like
+    // gltf_model::to_rgba8 does for the glTF images)
+    let channels = info.color_type.samples();
+    let pixels = buffer
+        .chunks_exact(channels)
+        .flat_map(|pixel| match *pixel {
+            [l] => [l, l, l, u8::MAX],
+            [l, a] => [l, l, l, a],
+            [r, g, b] => [r, g, b, u8::MAX],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!("pixels have 1 to 4 channels"),
+        })
+        .collect();
+
+    Ok((pixels, width, height))
+}
+
+// the texture is uploaded by the upload manager (see render::upload): the copy
+// is executed on the transfer queue and the mip levels are generated on the
+// graphics queue, so the texture can only be used, after the uploads were flushed
+pub unsafe fn create_texture_from_pixels(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    // 8 bit RGBA in sRGB color space, row by row
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+) -> Result<Texture> {
+    if pixels.len() != width as usize * height as usize * 4 {
+        return Err(anyhow!(
+            "Texture of size {}x{} has {} bytes instead of 4 per pixel.",
+            width,
+            height,
+            pixels.len()
+        ));
+    }
+
+    // the number of levels in the mip chain: each level has half the size of
+    // the previous one, down to a single pixel
+    let mip_levels = mip_level_count(width, height);
+    log::debug!("mip levels: {}", mip_levels);
+
+    // mip levels are generated on the gpu by blitting, which requires linear
+    // filtering support for the texture format; otherwise the levels are
+    // generated on the cpu and uploaded along with the original image
+    let blit_supported = supports_linear_blitting(instance, data, TEXTURE_FORMAT);
+
+    let pixels = if blit_supported {
+        pixels
+    } else {
+        log::info!("Linear blitting not supported, generating mip levels on the cpu");
+        generate_mip_chain(&pixels, width, height, mip_levels)
+    };
+    let size = pixels.len() as u64;
 
     // stage image data in host visible memory
-    let (staging_buffer, staging_buffer_memory) = buffer::create_buffer(
+    let staging_buffer = buffer::create_buffer(
         instance,
         device,
         data,
@@ -58,108 +160,546 @@ pub unsafe fn create_texture_image(
     )?;
 
     // copy pixel data to staging buffer memory
-    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    let memory = staging_buffer.mapped()?;
 
     memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
 
-    device.unmap_memory(staging_buffer_memory);
-
-    let (texture_image, texture_image_memory) = create_image(
+    // the image is also the source of the blit operations, which generate the
+    // mip levels
+    let texture_image = create_image(
         instance,
         device,
         data,
         width,
         height,
-        vk::Format::R8G8B8A8_SRGB,
+        mip_levels,
+        vk::SampleCountFlags::_1,
+        TEXTURE_FORMAT,
         vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::TRANSFER_SRC,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.texture_image = texture_image;
-    data.texture_image_memory = texture_image_memory;
+    let commands = data.uploads.commands()?;
 
-    // transition the texture image to vk::ImageLayout::TRANSFER_DST_OPTIMAL
-    transition_image_layout(
+    // transition all mip levels of the texture image to vk::ImageLayout::TRANSFER_DST_OPTIMAL
+    record_layout_transition(
         device,
-        data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        commands.transfer,
+        *texture_image,
+        TEXTURE_FORMAT,
         vk::ImageLayout::UNDEFINED, // image was defined with this layout, so we should pass it as the old layout
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mip_levels,
+        1,
     )?;
 
-    // execute the buffer to image copy operation
-    copy_buffer_to_image(
+    // record the buffer to image copy operation; the staging buffer contains
+    // either only the original image or the complete mip chain
+    record_copy_buffer_to_image(
         device,
-        data,
-        staging_buffer,
-        data.texture_image,
+        commands.transfer,
+        *staging_buffer,
+        *texture_image,
         width,
         height,
+        if blit_supported { 1 } else { mip_levels },
+        1,
+    );
+
+    // the staging buffer is released, after the upload completed
+    data.uploads.keep_alive(staging_buffer);
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+
+    if blit_supported {
+        // blitting requires the graphics queue, so the levels stay transfer
+        // destinations, until the mip levels are generated there
+        data.uploads.hand_over_image(
+            *texture_image,
+            subresource,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Destination {
+                stage: vk::PipelineStageFlags::TRANSFER,
+                access: vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+            },
+        )?;
+
+        // transitions all levels to SHADER_READ_ONLY_OPTIMAL, while generating them
+        generate_mipmaps(
+            device,
+            commands.graphics,
+            *texture_image,
+            width,
+            height,
+            mip_levels,
+        );
+    } else {
+        // to be able to start sampling from the image, we need to transition it
+        // to prepare for shader access; this happens along with the hand over
+        data.uploads.hand_over_image(
+            *texture_image,
+            subresource,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Destination {
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            },
+        )?;
+    }
+
+    Ok(Texture {
+        view: ImageView::default(),
+        image: texture_image,
+        mip_levels,
+        sampler: Sampler::default(),
+    })
+}
+
+/// uploads the six faces of a cube map into a texture with a cube view and its
+/// own sampler; like create_texture_from_pixels, the texture can only be used,
+/// after the uploads were flushed
+///
+/// the faces are stored one after another in the order +x, -x, +y, -y, +z, -z,
+/// each with size x size pixels in 8 bit RGBA (sRGB); there are no mip levels,
+/// as the skybox is never minified
+pub unsafe fn create_cube_texture(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    pixels: Vec<u8>,
+    size: u32,
+) -> Result<Texture> {
+    if pixels.len() != size as usize * size as usize * 4 * 6 {
+        return Err(anyhow!(
+            "Cube map with faces of size {}x{} has {} bytes instead of 4 per pixel.",
+            size,
+            size,
+            pixels.len()
+        ));
+    }
+
+    let staging_buffer = buffer::create_buffer(
+        instance,
+        device,
+        data,
+        pixels.len() as u64,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
     )?;
+    let memory = staging_buffer.mapped()?;
+    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
 
-    // to be able to start sampling from the image, we need to transition it to prepare for shader access
-    transition_image_layout(
+    // each face is a layer of the image; the flag allows cube views of it
+    let image = create_layered_image(
+        instance,
         device,
         data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        size,
+        size,
+        1,
+        6,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        vk::SampleCountFlags::_1,
+        TEXTURE_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    let commands = data.uploads.commands()?;
+    record_layout_transition(
+        device,
+        commands.transfer,
+        *image,
+        TEXTURE_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+        6,
+    )?;
+    record_copy_buffer_to_image(
+        device,
+        commands.transfer,
+        *staging_buffer,
+        *image,
+        size,
+        size,
+        1,
+        6,
+    );
+    data.uploads.keep_alive(staging_buffer);
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(6)
+        .build();
+    data.uploads.hand_over_image(
+        *image,
+        subresource,
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
         vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        Destination {
+            stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            access: vk::AccessFlags::SHADER_READ,
+        },
     )?;
 
-    // cleanup
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    let components = vk::ComponentMapping::builder()
+        .r(vk::ComponentSwizzle::IDENTITY)
+        .g(vk::ComponentSwizzle::IDENTITY)
+        .b(vk::ComponentSwizzle::IDENTITY)
+        .a(vk::ComponentSwizzle::IDENTITY);
+    let view = create_image_view_with_components(
+        device,
+        *image,
+        TEXTURE_FORMAT,
+        components.build(),
+        vk::ImageAspectFlags::COLOR,
+        1,
+        vk::ImageViewType::CUBE,
+        6,
+    )?;
 
-    Ok(())
+    // cube maps are filtered across the edges of the faces, the address modes
+    // only matter for the corners
+    let options = SamplerOptions {
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        ..Default::default()
+    };
+    let sampler = create_sampler(device, &options, 1)?;
+
+    Ok(Texture {
+        view,
+        image,
+        mip_levels: 1,
+        sampler,
+    })
 }
 
-pub unsafe fn create_image(
+// number of mip levels for an image of the given size; log2 of the largest
+// dimension tells, how many times the image can be divided by 2
+fn mip_level_count(width: u32, height: u32) -> u32 {
+    width.max(height).max(1).ilog2() + 1
+}
+
+unsafe fn supports_linear_blitting(
     instance: &Instance,
+    data: &AppData,
+    format: vk::Format,
+) -> bool {
+    get_supported_format(
+        instance,
+        data,
+        &[format],
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+            | vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST,
+    )
+    .is_ok()
+}
+
+// records the generation of the mip levels of the texture by blitting each level
+// into the next smaller one; expects all levels to be in TRANSFER_DST_OPTIMAL
+// layout and level 0 to contain the image, leaves all levels in
+// SHADER_READ_ONLY_OPTIMAL
+//
+// the command buffer needs to be submitted to a graphics queue
+unsafe fn generate_mipmaps(
     device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) {
+    // the barrier is reused for every level, only the level and layouts change
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_array_layer(0)
+        .layer_count(1)
+        .level_count(1);
+
+    let mut barrier = vk::ImageMemoryBarrier::builder()
+        .image(image)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(subresource);
+
+    let mut mip_width = width as i32;
+    let mut mip_height = height as i32;
+
+    for i in 1..mip_levels {
+        // level i - 1 was written to (by the copy or the previous blit) and is
+        // read from by the blit now
+        barrier.subresource_range.base_mip_level = i - 1;
+        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+
+        let next_width = (mip_width / 2).max(1);
+        let next_height = (mip_height / 2).max(1);
+
+        let src_subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(i - 1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let dst_subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(i)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        // the offsets define the 3D region, which is blitted from and to
+        let blit = vk::ImageBlit::builder()
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: mip_width,
+                    y: mip_height,
+                    z: 1,
+                },
+            ])
+            .src_subresource(src_subresource)
+            .dst_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: next_width,
+                    y: next_height,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(dst_subresource);
+
+        // source and destination are the same image, but different levels
+        device.cmd_blit_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+
+        // level i - 1 is finished and can be read by the fragment shader
+        barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
+        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+
+        mip_width = next_width;
+        mip_height = next_height;
+    }
+
+    // the last level is never blitted from, so it is still a transfer destination
+    barrier.subresource_range.base_mip_level = mip_levels - 1;
+    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+}
+
+// fallback for formats without linear blitting support: generates all mip levels
+// of an RGBA image on the cpu; the levels are stored tightly packed one after
+// another, starting with the original image
+fn generate_mip_chain(pixels: &[u8], width: u32, height: u32, mip_levels: u32) -> Vec<u8> {
+    let mut chain = pixels.to_vec();
+
+    let mut level = pixels.to_vec();
+    let (mut level_width, mut level_height) = (width, height);
+
+    for _ in 1..mip_levels {
+        let (next_width, next_height) = ((level_width / 2).max(1), (level_height / 2).max(1));
+        let mut next = vec![0; (next_width * next_height * 4) as usize];
+
+        for y in 0..next_height {
+            for x in 0..next_width {
+                // average the (up to) 2x2 texels of the previous level, which
+                // are covered by this texel; odd sizes repeat the last row/column
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                    let sx = (2 * x + dx).min(level_width - 1);
+                    let sy = (2 * y + dy).min(level_height - 1);
+                    ((sy * level_width + sx) * 4) as usize
+                });
+
+                let target = ((y * next_width + x) * 4) as usize;
+                for c in 0..4 {
+                    // the texture is in sRGB, so the color channels have to be
+                    // averaged in linear space; alpha is always linear
+                    let average = if c < 3 {
+                        let sum: f32 = texels.iter().map(|t| srgb_to_linear(level[t + c])).sum();
+                        linear_to_srgb(sum / 4.0)
+                    } else {
+                        let sum: u32 = texels.iter().map(|t| level[t + c] as u32).sum();
+                        ((sum + 2) / 4) as u8
+                    };
+                    next[target + c] = average;
+                }
+            }
+        }
+
+        chain.extend_from_slice(&next);
+        level = next;
+        level_width = next_width;
+        level_height = next_height;
+    }
+
+    chain
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let v = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+#[allow(clippy::too_many_arguments)]
+#[track_caller]
+pub unsafe fn create_image(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
     data: &AppData,
     width: u32,
     height: u32,
+    mip_levels: u32,
+    samples: vk::SampleCountFlags,
     format: vk::Format,
     tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Image, vk::DeviceMemory)> {
+) -> Result<Image> {
+    create_layered_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        1,
+        vk::ImageCreateFlags::empty(),
+        samples,
+        format,
+        tiling,
+        usage,
+        properties,
+    )
+}
+
+/// like create_image, but with `array_layers` layers; cube maps need 6 layers
+/// and the CUBE_COMPATIBLE flag
+#[allow(clippy::too_many_arguments)]
+#[track_caller]
+pub unsafe fn create_layered_image(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    array_layers: u32,
+    flags: vk::ImageCreateFlags,
+    samples: vk::SampleCountFlags,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<Image> {
     let info = vk::ImageCreateInfo::builder()
+        .flags(flags)
         .image_type(vk::ImageType::_2D)
         .extent(vk::Extent3D {
             width,
             height,
             depth: 1,
         })
-        .mip_levels(1)
-        .array_layers(1)
+        .mip_levels(mip_levels)
+        .array_layers(array_layers)
         .format(format)
         .tiling(tiling)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(usage)
-        .samples(vk::SampleCountFlags::_1)
+        .samples(samples)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-    let image = device.create_image(&info, None)?;
-    let requirements = device.get_image_memory_requirements(image);
+    let image = RawImage::new(device, device.create_image(&info, None)?);
+    let requirements = device.get_image_memory_requirements(*image);
 
-    let info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance,
-            data,
-            properties,
-            requirements,
-        )?);
+    let memory_type_index = get_memory_type_index(instance, data, properties, requirements)?;
+
+    // optimally tiled images have to be kept apart from buffers and linear images
+    // in memory (see buffer_image_granularity)
+    let kind = match tiling {
+        vk::ImageTiling::OPTIMAL => AllocationKind::Optimal,
+        _ => AllocationKind::Linear,
+    };
 
-    let image_memory = device.allocate_memory(&info, None)?;
+    let image_memory = Memory::allocate(&data.allocator, requirements, memory_type_index, kind)?;
 
-    device.bind_image_memory(image, image_memory, 0)?;
+    device.bind_image_memory(*image, image_memory.memory(), image_memory.offset())?;
 
-    Ok((image, image_memory))
+    Ok(Image::new(image, image_memory))
 }
 
 //  TODO: besser verstehen
@@ -173,6 +713,35 @@ unsafe fn transition_image_layout(
     format: vk::Format,
     old_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
+    mip_levels: u32,
+) -> Result<()> {
+    let command_buffer = command_buffer::begin_single_time_commands(device, data)?;
+    record_layout_transition(
+        device,
+        command_buffer,
+        image,
+        format,
+        old_layout,
+        new_layout,
+        mip_levels,
+        1,
+    )?;
+    command_buffer::end_single_time_commands(device, data, command_buffer)?;
+
+    Ok(())
+}
+
+// records the barrier for the layout transition into the command buffer
+#[allow(clippy::too_many_arguments)]
+unsafe fn record_layout_transition(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    mip_levels: u32,
+    layer_count: u32,
 ) -> Result<()> {
     // Note: check this table for reference: https://registry.khronos.org/vulkan/specs/1.0/html/vkspec.html#synchronization-access-types-supported
     // TODO: what is the differnece between access masks and stage masks
@@ -200,8 +769,6 @@ unsafe fn transition_image_layout(
             _ => return Err(anyhow!("Unsupported image layout transition!")),
         };
 
-    let command_buffer = command_buffer::begin_single_time_commands(device, data)?;
-
     // need to select the right aspect mask
     let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
         match format {
@@ -221,12 +788,13 @@ unsafe fn transition_image_layout(
     // access to resources); that can be used to transition image layouts and
     // transfer queue family ownership, when vk::SharingMode::Exclusive is used
     // there is an equivalent buffer memory barrier to do this for buffers
+    // all mip levels and layers are transitioned at once
     let subresource = vk::ImageSubresourceRange::builder()
         .aspect_mask(aspect_mask)
         .base_mip_level(0)
-        .level_count(1)
+        .level_count(mip_levels)
         .base_array_layer(0)
-        .layer_count(1);
+        .layer_count(layer_count);
 
     let barrier = vk::ImageMemoryBarrier::builder()
         .old_layout(old_layout)
@@ -249,113 +817,166 @@ unsafe fn transition_image_layout(
         &[barrier],
     );
 
-    command_buffer::end_single_time_commands(device, data, command_buffer)?;
-
     Ok(())
 }
 
-pub unsafe fn copy_buffer_to_image(
+// records the copy of `mip_levels` levels of an RGBA image with `layer_count`
+// layers from the buffer into the image; the levels need to be stored tightly
+// packed one after another in the buffer, each with all of its layers
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn record_copy_buffer_to_image(
     device: &Device,
-    data: &AppData,
+    command_buffer: vk::CommandBuffer,
     buffer: vk::Buffer,
     image: vk::Image,
     width: u32,
     height: u32,
-) -> Result<()> {
-    let command_buffer = command_buffer::begin_single_time_commands(device, data)?;
+    mip_levels: u32,
+    layer_count: u32,
+) {
+    let mut regions = Vec::with_capacity(mip_levels as usize);
+    let mut buffer_offset = 0;
+    let (mut level_width, mut level_height) = (width, height);
 
-    // specify, which parts of the buffer are going to be copied to which parts of the image
-    let subresource = vk::ImageSubresourceLayers::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .mip_level(0)
-        .base_array_layer(0)
-        .layer_count(1);
-
-    let region = vk::BufferImageCopy::builder()
-        .buffer_offset(0) // byte offset in the buffer, at which pixel values start
-        .buffer_row_length(0) // row_length and image_height specify, how pixels are laid out in memory (could have some padding bytes; 0 signals, that pixels are tightly packed)
-        .buffer_image_height(0)
-        .image_subresource(subresource)
-        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
-        .image_extent(vk::Extent3D {
-            width,
-            height,
-            depth: 1,
-        });
+    for level in 0..mip_levels {
+        // specify, which parts of the buffer are going to be copied to which parts of the image
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(level)
+            .base_array_layer(0)
+            .layer_count(layer_count);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(buffer_offset) // byte offset in the buffer, at which pixel values start
+            .buffer_row_length(0) // row_length and image_height specify, how pixels are laid out in memory (could have some padding bytes; 0 signals, that pixels are tightly packed)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: level_width,
+                height: level_height,
+                depth: 1,
+            });
+
+        regions.push(region);
+
+        buffer_offset += level_width as u64 * level_height as u64 * 4 * layer_count as u64;
+        level_width = (level_width / 2).max(1);
+        level_height = (level_height / 2).max(1);
+    }
 
     device.cmd_copy_buffer_to_image(
         command_buffer,
         buffer,
         image,
         vk::ImageLayout::TRANSFER_DST_OPTIMAL, // indicates, which layout the image is currently using
-        &[region], // it's possible to specify an array of vk::BufferImageCopy to perform many different copies from this buffer to the image in one operation
+        &regions, // it's possible to specify an array of vk::BufferImageCopy to perform many different copies from this buffer to the image in one operation
     );
-
-    command_buffer::end_single_time_commands(device, data, command_buffer)?;
-    Ok(())
 }
 
+#[track_caller]
 pub(crate) unsafe fn create_image_view(
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     image: vk::Image,
     format: vk::Format,
     aspects: vk::ImageAspectFlags,
-) -> Result<vk::ImageView> {
+    mip_levels: u32,
+) -> Result<ImageView> {
     let components = vk::ComponentMapping::builder()
         .r(vk::ComponentSwizzle::IDENTITY)
         .g(vk::ComponentSwizzle::IDENTITY)
         .b(vk::ComponentSwizzle::IDENTITY)
         .a(vk::ComponentSwizzle::IDENTITY);
 
-    create_image_view_with_components(device, image, format, components.build(), aspects)
+    create_image_view_with_components(
+        device,
+        image,
+        format,
+        components.build(),
+        aspects,
+        mip_levels,
+        vk::ImageViewType::_2D,
+        1,
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
+#[track_caller]
 pub(crate) unsafe fn create_image_view_with_components(
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     image: vk::Image,
     format: vk::Format,
     components: vk::ComponentMapping,
     aspects: vk::ImageAspectFlags,
-) -> Result<vk::ImageView> {
+    mip_levels: u32,
+    view_type: vk::ImageViewType,
+    layer_count: u32,
+) -> Result<ImageView> {
     // define subresource range -> describe purpose and which parts of
     // image should be accessed
-    // we use the images as color targets and don't use mipmaps or multiple layers
+    // the view covers all mip levels and all layers of the image
     let subresource_range = vk::ImageSubresourceRange::builder()
         .aspect_mask(aspects)
         .base_mip_level(0)
-        .level_count(1)
+        .level_count(mip_levels)
         .base_array_layer(0)
-        .layer_count(1);
+        .layer_count(layer_count);
 
     // create image view create info..
     let info = vk::ImageViewCreateInfo::builder()
         .image(image)
-        .view_type(vk::ImageViewType::_2D) // specifies, how the image data should be interpreted, allows to treat images as 1D, 2D, 3D and cube maps
+        .view_type(view_type) // specifies, how the image data should be interpreted, allows to treat images as 1D, 2D, 3D and cube maps
         .format(format)
         .subresource_range(subresource_range)
         .components(components);
 
-    Ok(device.create_image_view(&info, None)?)
+    Ok(ImageView::new(
+        device,
+        device.create_image_view(&info, None)?,
+    ))
 }
 
-pub(crate) unsafe fn create_texture_image_view(device: &Device, data: &mut AppData) -> Result<()> {
-    data.texture_image_view = create_image_view(
+pub(crate) unsafe fn create_texture_image_view(
+    device: &Arc<OwnedDevice>,
+    texture: &mut Texture,
+) -> Result<()> {
+    texture.view = create_image_view(
         device,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        *texture.image,
+        TEXTURE_FORMAT,
         vk::ImageAspectFlags::COLOR,
+        texture.mip_levels,
     )?;
     Ok(())
 }
 
 // Note: a sampler does not reference an image directly, but it can be applied to
 // any image, we want
-pub unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Result<()> {
+pub unsafe fn create_texture_sampler(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    // the sampler is shared by all textures, so it has to cover the longest mip chain
+    let max_mip_levels = data
+        .textures
+        .iter()
+        .map(|t| t.mip_levels)
+        .max()
+        .unwrap_or(1);
+
+    data.texture_sampler = create_sampler(device, &SamplerOptions::default(), max_mip_levels)?;
+    Ok(())
+}
+
+/// creates a sampler, which can sample from all levels of a mip chain with
+/// `mip_levels` levels
+pub unsafe fn create_sampler(
+    device: &Arc<OwnedDevice>,
+    options: &SamplerOptions,
+    mip_levels: u32,
+) -> Result<Sampler> {
     let info = vk::SamplerCreateInfo::builder()
-        .mag_filter(vk::Filter::LINEAR) // how to interpolate texels that are magnified (oversampling)
-        .min_filter(vk::Filter::LINEAR) // how to interpolate texels that are minified (undersampling)
-        .address_mode_u(vk::SamplerAddressMode::REPEAT) // address mode for x (in texel space this is u)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT) // address mode for y (in texel space this is v)
+        .mag_filter(options.mag_filter) // how to interpolate texels that are magnified (oversampling)
+        .min_filter(options.min_filter) // how to interpolate texels that are minified (undersampling)
+        .address_mode_u(options.address_mode_u) // address mode for x (in texel space this is u)
+        .address_mode_v(options.address_mode_v) // address mode for y (in texel space this is v)
         .address_mode_w(vk::SamplerAddressMode::REPEAT) // address mode for z (in texel space this is w)
         .anisotropy_enable(true) // TODO: yeah, but what IS anisotropy exactly?
         .max_anisotropy(16.0)
@@ -363,18 +984,17 @@ pub unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Res
         .unnormalized_coordinates(false) // if set to true, we can adress texels in [0, width) range, if normalized, then [0, 1)
         .compare_enable(false) // if comparison function is enabled, texels will first be compared to a value and the result of that comp is used in filtering operations
         .compare_op(vk::CompareOp::ALWAYS)
-        .mipmap_mode(vk::SamplerMipmapMode::LINEAR) // TODO: look at that later
-        .mip_lod_bias(0.0)
+        .mipmap_mode(options.mipmap_mode) // LINEAR interpolates between the two closest mip levels
+        .mip_lod_bias(0.0) // could be used to force the use of lower levels of detail
         .min_lod(0.0)
-        .max_lod(0.0);
+        .max_lod(mip_levels as f32); // allow sampling from all levels of the mip chain
 
-    data.texture_sampler = device.create_sampler(&info, None)?;
-    Ok(())
+    Ok(Sampler::new(device, device.create_sampler(&info, None)?))
 }
 
 pub(crate) unsafe fn create_depth_objects(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
     // depth attachment should have the same dimensions as the color attachment
@@ -388,41 +1008,114 @@ pub(crate) unsafe fn create_depth_objects(
     //   24 bits in real world applications)
     let format = get_depth_format(instance, data)?;
 
-    let (depth_image, depth_image_memory) = create_image(
+    data.depth_image = create_image(
         instance,
         device,
         data,
         data.swapchain_extent.width,
         data.swapchain_extent.height,
+        1,
+        data.msaa_samples, // has to match the sample count of the color attachment
         format,
         vk::ImageTiling::OPTIMAL,
         vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.depth_image = depth_image;
-    data.depth_image_memory = depth_image_memory;
-
     data.depth_image_view = create_image_view(
         device,
-        data.depth_image,
+        *data.depth_image,
         format,
         vk::ImageAspectFlags::DEPTH,
+        1,
     )?;
 
     transition_image_layout(
         device,
         data,
-        data.depth_image,
+        *data.depth_image,
         format,
         vk::ImageLayout::UNDEFINED, // we can use the undefined layout as initial layout, because there are no existing depth image contents, that matter
         vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        1,
     )?;
 
     Ok(())
 }
 
-unsafe fn get_supported_format(
+// with multisampling, the scene is rendered into a multisampled color image,
+// which is resolved into the HDR image afterwards; the HDR image itself only
+// has a single sample per pixel
+pub(crate) unsafe fn create_color_objects(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
+    // without multisampling, there is nothing to resolve
+    if data.msaa_samples == vk::SampleCountFlags::_1 {
+        return Ok(());
+    }
+
+    // the contents are only needed during the render pass, so the image can be
+    // transient (the implementation may then never back it with actual memory)
+    data.color_image = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        data.msaa_samples,
+        HDR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.color_image_view = create_image_view(
+        device,
+        *data.color_image,
+        HDR_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    Ok(())
+}
+
+// the scene is rendered into the HDR image (or the multisampled color image is
+// resolved into it), which is sampled by the tone mapping pass afterwards
+pub(crate) unsafe fn create_hdr_objects(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
+    data.hdr_image = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::SampleCountFlags::_1,
+        HDR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.hdr_image_view = create_image_view(
+        device,
+        *data.hdr_image,
+        HDR_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )?;
+
+    Ok(())
+}
+
+pub(crate) unsafe fn get_supported_format(
     instance: &Instance,
     data: &AppData,
     candidates: &[vk::Format],
@@ -450,7 +1143,7 @@ unsafe fn get_supported_format(
         .ok_or_else(|| anyhow!("Failed to find supported format!"))
 }
 
-unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
+pub(crate) unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
     let candidates = &[
         vk::Format::D32_SFLOAT,
         vk::Format::D32_SFLOAT_S8_UINT,
@@ -465,3 +1158,72 @@ unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Fo
         vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_level_count_halves_down_to_one_texel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(512, 512), 10);
+        assert_eq!(mip_level_count(300, 200), 9);
+        assert_eq!(mip_level_count(7, 3), 3);
+    }
+
+    #[test]
+    fn mip_chain_of_odd_size_ends_with_single_texel() {
+        let (width, height) = (5, 3);
+        let pixels = vec![255; width * height * 4];
+        let mip_levels = mip_level_count(width as u32, height as u32);
+
+        // 5x3, 2x1, 1x1
+        let chain = generate_mip_chain(&pixels, width as u32, height as u32, mip_levels);
+        assert_eq!(chain.len(), (15 + 2 + 1) * 4);
+        assert_eq!(&chain[chain.len() - 4..], &[255; 4]);
+    }
+
+    #[test]
+    fn mip_chain_averages_2x2_blocks() {
+        #[rustfmt::skip]
+        let pixels = [
+            255, 0, 10, 0,      0, 0, 10, 100,
+            255, 0, 10, 200,    0, 0, 10, 255,
+        ];
+
+        let chain = generate_mip_chain(&pixels, 2, 2, 2);
+        assert_eq!(chain.len(), (4 + 1) * 4);
+        // the colors are averaged in linear space, so half of the texels being fully
+        // red is brighter than 128; alpha is averaged directly
+        assert_eq!(&chain[16..], &[188, 0, 10, 139]);
+    }
+
+    fn load_encoded_png(name: &str, color_type: png::ColorType, data: &[u8]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("{}-{}.png", name, std::process::id()));
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 1);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+
+        let (pixels, width, height) = load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((width, height), (2, 1));
+        pixels
+    }
+
+    #[test]
+    fn load_png_expands_rgb_and_grayscale_to_rgba() {
+        let rgb = load_encoded_png("rgb", png::ColorType::Rgb, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(rgb, [1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let gray = load_encoded_png("gray", png::ColorType::GrayscaleAlpha, &[7, 8, 9, 10]);
+        assert_eq!(gray, [7, 7, 7, 8, 9, 9, 9, 10]);
+
+        let rgba = load_encoded_png("rgba", png::ColorType::Rgba, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(rgba, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
@@ -5,6 +5,7 @@ use log::*;
 
 // std
 use std::collections::HashSet;
+use std::sync::Arc;
 
 use vulkanalia::prelude::v1_0::*;
 use vulkanalia::vk::ExtDebugUtilsExtension;
@@ -12,20 +13,25 @@ use vulkanalia::window as vk_window;
 
 use winit::window::Window;
 
+use super::handle::OwnedInstance;
 use super::validation;
 use crate::app::AppData;
 
 /// creates a new vulkan instance using entry.create_instance
-/// the window parameter is used to enumerate all required extensions
+/// the window parameter is used to enumerate all required extensions and to
+/// create the surface; without a window (headless rendering), no surface
+/// extensions are enabled
 ///
 /// The 'Instance' returned by this function is not a raw vulkan instance
 /// (this would be vk::Instance), it is an abstraction created by vulkanalia,
-/// which combines the raw vulkan instance and the loaded commands for that instance
+/// which combines the raw vulkan instance and the loaded commands for that instance;
+/// it is owned along with the entry, the debug messenger and the surface, which
+/// are all destroyed, when the last reference is dropped
 pub unsafe fn create_instance(
-    window: &Window,
-    entry: &Entry,
+    window: Option<&Window>,
+    entry: Entry,
     data: &mut AppData,
-) -> Result<Instance> {
+) -> Result<Arc<OwnedInstance>> {
     // no strictly necessary
     let application_info = vk::ApplicationInfo::builder()
         .application_name(b"Vulkan Tutorial\0")
@@ -45,15 +51,15 @@ pub unsafe fn create_instance(
         .map(|l| l.layer_name)
         .collect::<HashSet<_>>();
 
-    if validation::ENABLED {
+    if data.validation {
         debug!("Setting up validation layers");
     }
 
-    if validation::ENABLED && !available_layers.contains(&validation::LAYER) {
+    if data.validation && !available_layers.contains(&validation::LAYER) {
         return Err(anyhow!("Validation layer requested but not supported."));
     }
 
-    let layers = if validation::ENABLED {
+    let layers = if data.validation {
         vec![validation::LAYER.as_ptr()]
     } else {
         Vec::new()
@@ -66,20 +72,22 @@ pub unsafe fn create_instance(
     // null terminated c_strings (*const i8)
     //
     // globally means global for the whole program
-    let mut extensions = vk_window::get_required_instance_extensions(window)
+    let mut extensions = window
+        .map(|w| vk_window::get_required_instance_extensions(w))
+        .unwrap_or(&[])
         .iter()
         .map(|e| e.as_ptr())
         .collect::<Vec<_>>();
 
     // this extension is needed to set up a custom debug messenger with custom
     // message callback for validation layers
-    if validation::ENABLED {
+    if data.validation {
         extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
     }
 
     // create a vulkan instance (the connection between our program and the
     // Vulkan library)
-    let mut info = vk::InstanceCreateInfo::builder()
+    let info = vk::InstanceCreateInfo::builder()
         .application_info(&application_info)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions);
@@ -92,7 +100,7 @@ pub unsafe fn create_instance(
         // destroying instance
         .user_callback(Some(validation::debug_callback));
 
-    if validation::ENABLED {
+    if data.validation {
         trace!("Pushing debug_info to InstanceCreateInfo::pnext");
         // this does not seem to need a mutable instance of info..
         // this is pretty odd, because push_next will modify the internals of
@@ -102,10 +110,20 @@ pub unsafe fn create_instance(
 
     let instance = entry.create_instance(&info, None)?;
 
-    if validation::ENABLED {
-        // register the debug messenger and store the result in AppData
+    if data.validation {
+        // register the debug messenger
         data.messenger = instance.create_debug_utils_messenger_ext(&debug_info, None)?;
     }
 
-    Ok(instance)
+    // setup window surface
+    if let Some(window) = window {
+        data.surface = vk_window::create_surface(&instance, window)?;
+    }
+
+    Ok(OwnedInstance::new(
+        entry,
+        instance,
+        data.messenger,
+        data.surface,
+    ))
 }
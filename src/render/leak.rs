@@ -0,0 +1,119 @@
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use vulkanalia::vk;
+
+// handles are only tracked in debug builds, so release builds don't pay for
+// the bookkeeping
+pub const ENABLED: bool = cfg!(debug_assertions);
+
+/// where a handle, which is still alive, was created
+#[derive(Debug)]
+pub struct LiveHandle {
+    pub object_type: vk::ObjectType,
+    pub handle: u64,
+    pub location: &'static Location<'static>,
+    // only captured, if RUST_BACKTRACE (or RUST_LIB_BACKTRACE) is set
+    pub backtrace: Backtrace,
+}
+
+lazy_static! {
+    // handles are unique per object type, so the type is part of the key
+    static ref LIVE_HANDLES: Mutex<HashMap<(vk::ObjectType, u64), LiveHandle>> =
+        Mutex::new(HashMap::new());
+}
+
+/// records a newly created handle; called by the owning wrappers in render::handle
+pub fn track(object_type: vk::ObjectType, handle: u64, location: &'static Location<'static>) {
+    if !ENABLED || handle == 0 {
+        return;
+    }
+
+    let live_handle = LiveHandle {
+        object_type,
+        handle,
+        location,
+        backtrace: Backtrace::capture(),
+    };
+
+    if let Ok(mut handles) = LIVE_HANDLES.lock() {
+        handles.insert((object_type, handle), live_handle);
+    }
+}
+
+/// removes a handle, which was destroyed
+pub fn untrack(object_type: vk::ObjectType, handle: u64) {
+    if !ENABLED || handle == 0 {
+        return;
+    }
+
+    if let Ok(mut handles) = LIVE_HANDLES.lock() {
+        handles.remove(&(object_type, handle));
+    }
+}
+
+/// the number of tracked handles, which were not destroyed yet; always 0 in
+/// release builds
+pub fn live_count() -> usize {
+    LIVE_HANDLES.lock().map(|h| h.len()).unwrap_or(0)
+}
+
+/// logs all handles, which are still alive; should be called at shutdown, after
+/// the app was dropped
+pub fn report() {
+    if !ENABLED {
+        return;
+    }
+
+    let count = live_count();
+    if count == 0 {
+        log::debug!("No leaked vulkan handles");
+        return;
+    }
+
+    log::warn!("{} vulkan handles were not destroyed:", count);
+
+    let Ok(handles) = LIVE_HANDLES.lock() else {
+        return;
+    };
+
+    // sorted by creation site, so repeated leaks end up next to each other
+    let mut handles = handles.values().collect::<Vec<_>>();
+    handles.sort_by_key(|h| (h.location.file(), h.location.line(), h.handle));
+
+    for h in handles {
+        log::warn!(
+            "  {:?} {:#x} created at {}",
+            h.object_type,
+            h.handle,
+            h.location
+        );
+        if h.backtrace.status() == BacktraceStatus::Captured {
+            log::warn!("{}", h.backtrace);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no other test creates vulkan handles, so nothing else is tracked
+    #[test]
+    fn live_count_returns_to_zero() {
+        assert_eq!(live_count(), 0);
+
+        let handle = 0x1234;
+        track(vk::ObjectType::BUFFER, handle, Location::caller());
+        // the same value of another object type is a different handle
+        track(vk::ObjectType::IMAGE, handle, Location::caller());
+        assert_eq!(live_count(), if ENABLED { 2 } else { 0 });
+
+        untrack(vk::ObjectType::BUFFER, handle);
+        untrack(vk::ObjectType::IMAGE, handle);
+        assert_eq!(live_count(), 0);
+    }
+}
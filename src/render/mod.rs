@@ -1,15 +1,30 @@
+pub(crate) mod allocator;
 pub(crate) mod buffer;
 pub(crate) mod command_buffer;
 pub(crate) mod command_pool;
+pub(crate) mod cubemap;
 pub(crate) mod descriptor_pool;
 pub(crate) mod descriptor_set;
 pub(crate) mod device;
 pub(crate) mod framebuffer;
+pub(crate) mod gltf_model;
+pub(crate) mod handle;
 pub(crate) mod image;
 pub(crate) mod instance;
+pub(crate) mod leak;
+pub(crate) mod model;
+pub(crate) mod offscreen;
 pub(crate) mod pipeline;
+pub(crate) mod push_constants;
 mod queue;
+pub(crate) mod reflection;
 pub(crate) mod render_pass;
+pub(crate) mod shader;
+pub(crate) mod shadow;
+pub(crate) mod skybox;
 pub(crate) mod swapchain;
 pub(crate) mod synchronization;
+pub(crate) mod tonemap;
+pub(crate) mod upload;
 pub(crate) mod validation;
+pub(crate) mod vertex;
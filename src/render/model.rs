@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Result};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use nalgebra_glm as glm;
+
+use super::pipeline::Vertex;
+
+/// loads a model in wavefront format (.obj) from `path` and converts it into
+/// vertex and index data, which can be uploaded into the vertex and index buffer
+///
+/// the material library (.mtl) referenced by the model is loaded along with it;
+/// the diffuse color of a material is used as vertex color, if the model does
+/// not define vertex colors itself; smooth normals are generated, if the model
+/// does not define normals
+pub fn load_model(path: &Path) -> Result<(Vec<Vertex>, Vec<u32>)> {
+    log::info!("Loading model {}", path.display());
+
+    // - triangulate: faces with more than three vertices are split into triangles
+    // - single_index: positions, texture coordinates (and normals) share one
+    //   index, so every index refers to exactly one complete vertex
+    let options = tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    };
+
+    let (models, materials) = tobj::load_obj(path, &options)?;
+
+    // a missing material library is not fatal, the vertices just stay white
+    let materials = materials.unwrap_or_else(|e| {
+        log::warn!("Failed to load materials of {}: {}", path.display(), e);
+        Vec::new()
+    });
+
+    // the same vertex is referenced by many faces; this maps each unique vertex
+    // to its index in the vertex array, so it is only stored once
+    let mut unique_vertices = HashMap::new();
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for model in &models {
+        let mesh = &model.mesh;
+
+        let material_color = mesh
+            .material_id
+            .and_then(|id| materials.get(id))
+            .map(|m| glm::make_vec3(&m.diffuse))
+            .unwrap_or_else(|| glm::vec3(1.0, 1.0, 1.0));
+
+        let generated_normals = if mesh.normals.is_empty() {
+            generate_normals(&mesh.positions, &mesh.indices)
+        } else {
+            Vec::new()
+        };
+
+        for index in &mesh.indices {
+            let index = *index as usize;
+
+            let pos_offset = 3 * index;
+            if pos_offset + 2 >= mesh.positions.len() {
+                return Err(anyhow!(
+                    "Invalid vertex index {} in model {}.",
+                    index,
+                    path.display()
+                ));
+            }
+
+            let pos = glm::vec3(
+                mesh.positions[pos_offset],
+                mesh.positions[pos_offset + 1],
+                mesh.positions[pos_offset + 2],
+            );
+
+            let color = if mesh.vertex_color.len() > pos_offset + 2 {
+                glm::vec3(
+                    mesh.vertex_color[pos_offset],
+                    mesh.vertex_color[pos_offset + 1],
+                    mesh.vertex_color[pos_offset + 2],
+                )
+            } else {
+                material_color
+            };
+
+            // the obj format assumes a coordinate system, where a vertical
+            // coordinate of 0 means the bottom of the image, but we upload the
+            // image in top to bottom orientation -> flip the vertical coordinate
+            let tex_coord_offset = 2 * index;
+            let tex_coord = if mesh.texcoords.len() > tex_coord_offset + 1 {
+                glm::vec2(
+                    mesh.texcoords[tex_coord_offset],
+                    1.0 - mesh.texcoords[tex_coord_offset + 1],
+                )
+            } else {
+                glm::vec2(0.0, 0.0)
+            };
+
+            let normal = if mesh.normals.len() > pos_offset + 2 {
+                glm::vec3(
+                    mesh.normals[pos_offset],
+                    mesh.normals[pos_offset + 1],
+                    mesh.normals[pos_offset + 2],
+                )
+            } else {
+                generated_normals
+                    .get(index)
+                    .copied()
+                    .unwrap_or_else(glm::Vec3::zeros)
+            };
+
+            let vertex = Vertex::new(pos, color, tex_coord, normal);
+
+            let index = *unique_vertices.entry(vertex).or_insert_with(|| {
+                vertices.push(vertex);
+                vertices.len() as u32 - 1
+            });
+
+            indices.push(index);
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(anyhow!("Model {} contains no faces.", path.display()));
+    }
+
+    log::debug!(
+        "Loaded {} unique vertices and {} indices",
+        vertices.len(),
+        indices.len()
+    );
+
+    Ok((vertices, indices))
+}
+
+// averages the normals of all faces, which share a position; the cross product
+// is not normalized before it is added, so larger faces have more weight
+pub(super) fn generate_normals(positions: &[f32], indices: &[u32]) -> Vec<glm::Vec3> {
+    let position = |i: u32| {
+        let offset = 3 * i as usize;
+        positions
+            .get(offset..offset + 3)
+            .map(glm::make_vec3)
+            .unwrap_or_else(glm::Vec3::zeros)
+    };
+
+    let mut normals = vec![glm::Vec3::zeros(); positions.len() / 3];
+
+    for face in indices.chunks_exact(3) {
+        let (a, b, c) = (position(face[0]), position(face[1]), position(face[2]));
+        let normal = (b - a).cross(&(c - a));
+        for i in face {
+            if let Some(n) = normals.get_mut(*i as usize) {
+                *n += normal;
+            }
+        }
+    }
+
+    // degenerate faces may leave a normal at zero, which can't be normalized
+    for normal in &mut normals {
+        if normal.norm_squared() > 0.0 {
+            normal.normalize_mut();
+        }
+    }
+
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a quad of two triangles and a third triangle, which shares the position of
+    // the top right corner, but not its texture coordinate
+    const QUAD: &str = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vt 0.5 0.5
+f 1/1 2/2 3/3
+f 1/1 3/3 4/4
+f 2/2 3/5 4/4
+";
+
+    #[test]
+    fn shared_corners_are_stored_once() {
+        let path = std::env::temp_dir().join(format!("quad-{}.obj", std::process::id()));
+        std::fs::write(&path, QUAD).unwrap();
+        let model = load_model(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (vertices, indices) = model.unwrap();
+
+        assert_eq!(vertices.len(), 5);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3, 1, 4, 3]);
+        assert!(indices.iter().all(|i| (*i as usize) < vertices.len()));
+
+        // the top right corner with both texture coordinates
+        assert_eq!(vertices[2].pos(), vertices[4].pos());
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+
+use super::handle::OwnedDevice;
+use super::{buffer, command_buffer, image};
+
+// format of the offscreen color image; uses the same channel order as the
+// png crate, so the pixels can be written without swizzling
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// creates a color image, which takes the place of the swapchain images in
+/// headless mode (when there is no window and surface to present to)
+///
+/// the image is stored as the only "swapchain image", so image views, framebuffers,
+/// uniform buffers and command buffers can be created the same way as for the
+/// window
+pub unsafe fn create_offscreen_target(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    log::debug!("Creating offscreen target ({}x{})", width, height);
+
+    // the image is rendered to as color attachment and copied to a buffer
+    // afterwards, so it is also a transfer source
+    data.offscreen_image = image::create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        OFFSCREEN_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.swapchain_format = OFFSCREEN_FORMAT;
+    data.swapchain_extent = vk::Extent2D { width, height };
+    data.swapchain_images = vec![*data.offscreen_image];
+
+    Ok(())
+}
+
+/// copies the contents of the offscreen image into a host visible buffer and
+/// writes them to a png file at `path`
+///
+/// expects the rendering into the offscreen image to be submitted already; the
+/// render pass leaves the image in TRANSFER_SRC_OPTIMAL layout
+pub unsafe fn save_offscreen_image(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<()> {
+    let width = data.swapchain_extent.width;
+    let height = data.swapchain_extent.height;
+    // 4 bytes per pixel
+    let size = width as u64 * height as u64 * 4;
+
+    let staging_buffer = buffer::create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let command_buffer = command_buffer::begin_single_time_commands(device, data)?;
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    // the copy has to wait for the color writes of the render pass, which was
+    // submitted before; the layout stays the same
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(*data.offscreen_image)
+        .subresource_range(subresource_range)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    // tightly packed, same as in copy_buffer_to_image, just the other way round
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        *data.offscreen_image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        *staging_buffer,
+        &[region],
+    );
+
+    // make the transfer writes visible to the host, before the buffer is mapped
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    // waits for the queue to become idle, so the buffer can be read right after
+    command_buffer::end_single_time_commands(device, data, command_buffer)?;
+
+    let memory = staging_buffer.mapped()?;
+    let pixels = slice::from_raw_parts(memory.cast::<u8>(), size as usize);
+
+    // the staging buffer is released, when it goes out of scope
+    write_png(path, width, height, pixels)
+}
+
+fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // the offscreen image uses an sRGB format, so the values are already gamma encoded
+    encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+
+    log::info!("Wrote offscreen image to {}", path.display());
+
+    Ok(())
+}
@@ -4,7 +4,12 @@ use anyhow::{anyhow, Result};
 use log::info;
 use vulkanalia::prelude::v1_0::*;
 
-use super::{buffer, descriptor_set};
+use super::handle::{OwnedDevice, Pipeline, PipelineLayout, ShaderModule};
+use super::push_constants::PushConstants;
+use super::reflection::ShaderReflection;
+use super::upload::Destination;
+use super::vertex::VertexLayout;
+use super::{buffer, descriptor_set, shader};
 use crate::app::AppData;
 
 // vertex data related
@@ -16,73 +21,70 @@ use nalgebra_glm as glm;
 
 // used to calculate the size of vertex data
 use std::mem::size_of;
+use std::path::Path;
+use std::sync::Arc;
+
+// vertices need to be hashable to remove duplicates, when loading models
+use std::hash::{Hash, Hasher};
 
 // memcpy
 use std::ptr::copy_nonoverlapping as memcpy;
 
 // repr annotation states to use other memory layout strategies (C in this case)
+// the binding and attribute descriptions are derived from the fields: pos is
+// location 0, color location 1, tex_coord location 2 and normal location 3 (see
+// shader.vert)
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, VertexLayout)]
 pub struct Vertex {
     pos: glm::Vec3,
     color: glm::Vec3,
     tex_coord: glm::Vec2,
+    // in model space; used for lighting
+    normal: glm::Vec3,
 }
 
 impl Vertex {
-    fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2) -> Self {
+    pub fn pos(&self) -> glm::Vec3 {
+        self.pos
+    }
+
+    pub fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2, normal: glm::Vec3) -> Self {
         Self {
             pos,
             color,
             tex_coord,
+            normal,
         }
     }
+}
+
+// floats don't implement Eq and Hash (because of NaN), so compare and hash the
+// bit patterns of the components instead; this is fine for deduplicating vertices,
+// which are loaded from the same model. both need to use the bit patterns, as
+// 0.0 == -0.0 would otherwise be equal, but hash differently
+impl Vertex {
+    fn bits(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pos
+            .iter()
+            .chain(self.color.iter())
+            .chain(self.tex_coord.iter())
+            .chain(self.normal.iter())
+            .map(|c| c.to_bits())
+    }
+}
 
-    // needed to tell vulkan, how to pass vertex data to the shader
-    // Vertex binding describes, at which rate to laod data from memory
-    // specifies number of bytes between data entries whether to move to next entry
-    // after each vertex or instance
-    fn binding_description() -> vk::VertexInputBindingDescription {
-        vk::VertexInputBindingDescription::builder()
-            // specifies the index of the binding in array of bindings
-            .binding(0)
-            // specifies the number of bytes from one entry to the next
-            .stride(size_of::<Vertex>() as u32)
-            // either vertex or instance (for instanced rendering)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build()
+impl PartialEq for Vertex {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits().eq(other.bits())
     }
+}
+
+impl Eq for Vertex {}
 
-    // used to specify how to handle vertex input
-    fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
-        let pos = vk::VertexInputAttributeDescription::builder()
-            // from which binding does the per-vertex data come?
-            .binding(0)
-            // references the location directive of the attribute in shader code
-            .location(0)
-            // describes the type of data of the attribute (confusingly the same enum as color formats)
-            // in this case: vec3 has three 32 bit floats
-            // should also consider bitwidth of the datatypes
-            .format(vk::Format::R32G32B32_SFLOAT)
-            // number of bytes since the start of the per vertex data
-            .offset(0)
-            .build();
-
-        let color = vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(1)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(size_of::<glm::Vec2>() as u32)
-            .build();
-
-        let tex_coord = vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(2)
-            .format(vk::Format::R32G32_SFLOAT)
-            .offset((size_of::<glm::Vec2>() + size_of::<glm::Vec3>()) as u32)
-            .build();
-
-        [pos, color, tex_coord]
+impl Hash for Vertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits().for_each(|b| b.hash(state));
     }
 }
 
@@ -97,54 +99,64 @@ impl Vertex {
     ];
 }*/
 
+// built-in geometry, which is used if no model is loaded; two quads, which
+// face the positive z-axis
 lazy_static! {
-    static ref VERTICES: Vec<Vertex> = vec![
+    pub static ref VERTICES: Vec<Vertex> = vec![
         Vertex::new(
             glm::vec3(-0.5, -0.5, 0.0),
             glm::vec3(1.0, 0.0, 0.0),
-            glm::vec2(1.0, 0.0)
+            glm::vec2(1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(0.5, -0.5, 0.0),
             glm::vec3(0.0, 1.0, 0.0),
-            glm::vec2(0.0, 0.0)
+            glm::vec2(0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(0.5, 0.5, 0.0),
             glm::vec3(0.0, 0.0, 1.0),
-            glm::vec2(0.0, 1.0)
+            glm::vec2(0.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(-0.5, 0.5, 0.0),
             glm::vec3(1.0, 1.0, 1.0),
-            glm::vec2(1.0, 1.0)
+            glm::vec2(1.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(-0.5, -0.5, -0.5),
             glm::vec3(1.0, 0.0, 0.0),
-            glm::vec2(1.0, 0.0)
+            glm::vec2(1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(0.5, -0.5, -0.5),
             glm::vec3(0.0, 1.0, 0.0),
-            glm::vec2(0.0, 0.0)
+            glm::vec2(0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(0.5, 0.5, -0.5),
             glm::vec3(0.0, 0.0, 1.0),
-            glm::vec2(0.0, 1.0)
+            glm::vec2(0.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
         Vertex::new(
             glm::vec3(-0.5, 0.5, -0.5),
             glm::vec3(1.0, 1.0, 1.0),
-            glm::vec2(1.0, 1.0)
+            glm::vec2(1.0, 1.0),
+            glm::vec3(0.0, 0.0, 1.0)
         ),
     ];
 }
 
 // indices for drawing two triangles with vertex data
 // this also needs to be uploaded into an vk::Buffer
-pub const INDICES: &[u16] = &[0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
+pub const INDICES: &[u32] = &[0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
 
 // buffers are regions of memory used for storage of arbitraty data and can
 // be read by the graphics card
@@ -152,14 +164,14 @@ pub const INDICES: &[u16] = &[0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
 // for themselves)
 pub unsafe fn create_vertex_buffer(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
-    let size = (size_of::<Vertex>() * VERTICES.len()) as u64;
+    let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
 
     // use staging buffer to store the vertex data and transfer it later to
     // the actual vertex buffer
-    let (staging_buffer, staging_buffer_memory) = buffer::create_buffer(
+    let staging_buffer = buffer::create_buffer(
         instance,
         device,
         data,
@@ -168,19 +180,14 @@ pub unsafe fn create_vertex_buffer(
         vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
     )?;
 
-    // fill the staging buffer -> map cpu memory to the staging_buffer_memory
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0, // no offset
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
+    // fill the staging buffer -> the allocator keeps host visible memory mapped
+    // to cpu memory, so we only need to get the pointer to the staging_buffer_memory
+    let memory = staging_buffer.mapped()?;
 
-    memcpy(VERTICES.as_ptr(), memory.cast(), VERTICES.len());
-    device.unmap_memory(staging_buffer_memory);
+    memcpy(data.vertices.as_ptr(), memory.cast(), data.vertices.len());
 
     // create a vertex buffer and memory in device_local memory
-    let (vertex_buffer, vertex_buffer_memory) = buffer::create_buffer(
+    let vertex_buffer = buffer::create_buffer(
         instance,
         device,
         data,
@@ -189,15 +196,19 @@ pub unsafe fn create_vertex_buffer(
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    // copy from staging buffer to vertex buffer
-    buffer::copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
-
-    // release temporary resources
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    // copy from staging buffer to vertex buffer on the transfer queue; the
+    // staging buffer is released, after the upload completed
+    data.uploads.upload_buffer(
+        staging_buffer,
+        *vertex_buffer,
+        size,
+        Destination {
+            stage: vk::PipelineStageFlags::VERTEX_INPUT,
+            access: vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        },
+    )?;
 
     data.vertex_buffer = vertex_buffer;
-    data.vertex_buffer_memory = vertex_buffer_memory;
 
     // the driver may not copy the data immediately into the buffer memory
     // two ways to deal with this:
@@ -214,16 +225,16 @@ pub unsafe fn create_vertex_buffer(
 
 pub unsafe fn create_index_buffer(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
-    let size = (size_of::<u16>() * INDICES.len()) as u64;
+    let size = std::mem::size_of_val(data.indices.as_slice()) as u64;
 
     log::debug!("Index buffer size: {}", size);
 
     // use staging buffer to store the vertex data and transfer it later to
     // the actual vertex buffer
-    let (staging_buffer, staging_buffer_memory) = buffer::create_buffer(
+    let staging_buffer = buffer::create_buffer(
         instance,
         device,
         data,
@@ -232,19 +243,13 @@ pub unsafe fn create_index_buffer(
         vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
     )?;
 
-    // fill the staging buffer -> map cpu memory to the staging_buffer_memory
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0, // no offset
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
+    // fill the staging buffer
+    let memory = staging_buffer.mapped()?;
 
-    memcpy(INDICES.as_ptr(), memory.cast(), INDICES.len());
-    device.unmap_memory(staging_buffer_memory);
+    memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
 
     // create an index buffer and memory in device_local memory
-    let (index_buffer, index_buffer_memory) = buffer::create_buffer(
+    let index_buffer = buffer::create_buffer(
         instance,
         device,
         data,
@@ -253,26 +258,29 @@ pub unsafe fn create_index_buffer(
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.index_buffer = index_buffer;
-    data.index_buffer_memory = index_buffer_memory;
-
-    // copy from staging buffer to vertex buffer
-    buffer::copy_buffer(device, data, staging_buffer, index_buffer, size)?;
+    // copy from staging buffer to index buffer (see create_vertex_buffer)
+    data.uploads.upload_buffer(
+        staging_buffer,
+        *index_buffer,
+        size,
+        Destination {
+            stage: vk::PipelineStageFlags::VERTEX_INPUT,
+            access: vk::AccessFlags::INDEX_READ,
+        },
+    )?;
 
-    // release temporary resources
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    data.index_buffer = index_buffer;
 
     Ok(())
 }
 
 pub unsafe fn create_uniform_buffers(
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
     data.uniform_buffers.clear();
-    data.uniform_buffers_memory.clear();
+    data.light_buffers.clear();
 
     // we will update the uniform buffers contents each frame; as we have multiple
     // frames in flight, we could either create one uniform buffer for each frame
@@ -280,7 +288,7 @@ pub unsafe fn create_uniform_buffers(
     // buffer (which is per swapchain image), so we create one uniform buffer for
     // each swapchain image as well
     for _ in 0..data.swapchain_images.len() {
-        let (uniform_buffer, uniform_buffer_memory) = buffer::create_buffer(
+        let uniform_buffer = buffer::create_buffer(
             instance,
             device,
             data,
@@ -289,37 +297,68 @@ pub unsafe fn create_uniform_buffers(
             vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
         )?;
         data.uniform_buffers.push(uniform_buffer);
-        data.uniform_buffers_memory.push(uniform_buffer_memory);
+
+        // the lights are updated along with the camera matrices
+        let light_buffer = buffer::create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<descriptor_set::LightsBufferObject>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+        data.light_buffers.push(light_buffer);
 
         // we will create a specific function, which updates the buffer with
-        // new data every frame, so there is no need for map_memory here
+        // new data every frame, so there is no need to write to the memory here
     }
 
     Ok(())
 }
 
-pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+pub unsafe fn create_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
     log::debug!("creating pipeline");
 
-    let vert = include_bytes!("../../shaders/vert.spv");
-    let frag = include_bytes!("../../shaders/frag.spv");
+    // the shaders are compiled at runtime (see compile_shaders); this is the code
+    // of the last successful compilation
+    let (pipeline_layout, pipeline) = build_pipeline(
+        device,
+        data,
+        &data.vertex_shader_code,
+        &data.fragment_shader_code,
+    )?;
+    data.pipeline = pipeline;
+    data.pipeline_layout = pipeline_layout;
+
+    info!("Created pipeline");
 
-    let vert_shader_module = create_shader_module(device, vert)?;
-    let frag_shader_module = create_shader_module(device, frag)?;
+    Ok(())
+}
+
+// creates the pipeline and its layout from the given shader code without
+// touching `data`, so a failed reload leaves the current pipeline intact
+unsafe fn build_pipeline(
+    device: &Arc<OwnedDevice>,
+    data: &AppData,
+    vertex_shader_code: &[u32],
+    fragment_shader_code: &[u32],
+) -> Result<(PipelineLayout, Pipeline)> {
+    let vert_shader_module = create_shader_module(device, vertex_shader_code)?;
+    let frag_shader_module = create_shader_module(device, fragment_shader_code)?;
 
     // assign shaders to specific pipeline stage with vk::PipelineShaderStageCreateInfo
     // NOTE: this features a member specialization_info, which allows for passing
     // values for shader constants (more efficient than passing in runtime)
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX) // in which pipeline stage should we use it
-        .module(vert_shader_module)
+        .module(*vert_shader_module)
         .name(b"main\0"); // specify name of entrypoint -> it's possible to combine
                           // multiple shaders in one bytecode file and reference different shaders in
                           // pipeline creation
 
     let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
-        .module(frag_shader_module)
+        .module(*frag_shader_module)
         .name(b"main\0");
 
     // --- FIXED FUNCTION STAGE CONFIGURATION ---
@@ -378,16 +417,26 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()>
         // because of the y-sign flip, we did in the update_uniform_buffers (to update the
         // model view proj matrix, vertices are drawn in counter-clockwise direction,
         // which needs to be reflected here)
-        .depth_bias_enable(false); // could offset depth value based on slope of fragment, sometime used in shadowmapping
+        .depth_bias_enable(false); // offsets the depth value based on the slope of the fragment; only used by the shadow pass (see shadow.rs)
 
     // --- multisampling configuration ---
 
-    // disable multisampling for now
+    // the number of samples has to match the attachments of the render pass;
+    // sample shading (which also smooths the interior of polygons) is not used
     let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
         .sample_shading_enable(false)
-        .rasterization_samples(vk::SampleCountFlags::_1);
+        .rasterization_samples(data.msaa_samples);
 
-    // ignore depth and stencil buffers and tests for now
+    // --- depth and stencil configuration ---
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true) // compare depth of new fragments to the depth buffer
+        .depth_write_enable(true) // write depth of fragments, that passed the test
+        .depth_compare_op(vk::CompareOp::LESS) // lower depth = closer
+        .depth_bounds_test_enable(false) // could be used to only keep fragments in a specific depth range
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false); // no stencil buffer used
 
     // --- color blending configuration ---
 
@@ -429,12 +478,16 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()>
     // on draw-time -> causes the configuration at compile time to be ignored!!
 
     // reference descriptor set layouts used in the pipeline
-    let set_layouts = &[data.descriptor_set_layout];
+    let set_layouts = &[*data.descriptor_set_layout];
     // specify pipeline layout (could be used to pass uniforms or push-constants (i.e. arguments) to shader stages)
-    // even though, we don't use this right now, we need to create an empty pipeline layout
-    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    // the model matrix of each scene node is pushed to the vertex shader
+    let push_constant_ranges = &[PushConstants::range()];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
 
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+    let pipeline_layout =
+        PipelineLayout::new(device, device.create_pipeline_layout(&layout_info, None)?);
 
     let stages = &[vert_stage, frag_stage];
     let info = vk::GraphicsPipelineCreateInfo::builder()
@@ -446,16 +499,17 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()>
         .viewport_state(&viewport_state)
         .rasterization_state(&rasterization_state)
         .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
         .color_blend_state(&color_blend_state)
         // pipeline layout
-        .layout(data.pipeline_layout)
+        .layout(*pipeline_layout)
         // render pass
-        .render_pass(data.render_pass)
+        .render_pass(*data.render_pass)
         .subpass(0); // "index of the subpass in the renderpass where this pipeline will be used"
                      // .base_pipeline_handle(vk::Pipeline::null()) // would be used to derive from another pipeline
                      // .base_pipeline_index(-1) // could be used to derive from another pipeline by idx
 
-    data.pipeline = device
+    let pipeline = device
         .create_graphics_pipelines(
             vk::PipelineCache::null(), // could be used to reference a pipeline cache -> significantly speed up pipeline creation
             &[info],
@@ -463,29 +517,169 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()>
         )?
         .0;
 
-    info!("Created pipeline");
+    // the shader modules are only needed during pipeline creation and are
+    // destroyed, when they go out of scope
 
-    device.destroy_shader_module(vert_shader_module, None);
-    device.destroy_shader_module(frag_shader_module, None);
+    Ok((pipeline_layout, Pipeline::new(device, pipeline)))
+}
+
+pub(crate) unsafe fn create_shader_module(
+    device: &Arc<OwnedDevice>,
+    code: &[u32],
+) -> Result<ShaderModule> {
+    // the code size is given in bytes, even though the code is passed as u32 words
+    let info = vk::ShaderModuleCreateInfo::builder()
+        .code_size(code.len() * 4)
+        .code(code);
+
+    Ok(ShaderModule::new(
+        device,
+        device.create_shader_module(&info, None)?,
+    ))
+}
+
+/// compiles the vertex and fragment shader of the pipeline and stores the
+/// SPIR-V code in `data`; on error, the previous code is kept
+pub fn compile_shaders(data: &mut AppData) -> Result<()> {
+    (
+        data.vertex_shader_code,
+        data.fragment_shader_code,
+        data.shader_reflection,
+    ) = compile_pipeline_shaders(data)?;
 
     Ok(())
 }
 
-unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
-    // this will pass the bytecode to ShaderModuleCreateInfo, which expects an &[u32]
-    // slice -> use slice::align_to to convert the &[u8], but have to make sure
-    // that the slice matches the alignment requirements. We can't be sure of that,
-    // so we create a Vec from it first
+// the compiled shaders along with their reflection
+type PipelineShaders = (Vec<u32>, Vec<u32>, ShaderReflection);
+
+// the compiled code is reflected to check, that it matches the Vertex layout
+// and the push constants; once the descriptor set layout exists, the shaders
+// also have to use the same descriptors as before
+fn compile_pipeline_shaders(data: &AppData) -> Result<PipelineShaders> {
+    let vertex_shader_code = shader::compile_shader(Path::new(shader::VERTEX_SHADER))?;
+    let fragment_shader_code = shader::compile_shader(Path::new(shader::FRAGMENT_SHADER))?;
+
+    let reflection = ShaderReflection::new(&[
+        (&vertex_shader_code, vk::ShaderStageFlags::VERTEX),
+        (&fragment_shader_code, vk::ShaderStageFlags::FRAGMENT),
+    ])?;
 
-    let bytecode = Vec::<u8>::from(bytecode);
-    let (prefix, code, suffix) = bytecode.align_to::<u32>();
-    if !prefix.is_empty() || !suffix.is_empty() {
-        return Err(anyhow!("Shader bytecode is not properly aligned."));
+    reflection.check_vertex_layout(
+        &Vertex::binding_description(),
+        &Vertex::attribute_descriptions(),
+    )?;
+
+    if reflection.push_constants_size > PushConstants::range().size {
+        return Err(anyhow!(
+            "Shaders use {} bytes of push constants, but only {} are pushed.",
+            reflection.push_constants_size,
+            PushConstants::range().size
+        ));
     }
 
-    let info = vk::ShaderModuleCreateInfo::builder()
-        .code_size(bytecode.len())
-        .code(code);
+    if !data.descriptor_set_layout.is_null()
+        && reflection.bindings != data.shader_reflection.bindings
+    {
+        return Err(anyhow!(
+            "Shaders use different descriptors than the descriptor set layout: {:?}",
+            reflection.bindings
+        ));
+    }
 
-    Ok(device.create_shader_module(&info, None)?)
+    Ok((vertex_shader_code, fragment_shader_code, reflection))
+}
+
+/// whether the pipeline is built from the shader at `path`
+pub fn uses_shader(path: &Path) -> bool {
+    shader::is_any_of(path, &[shader::VERTEX_SHADER, shader::FRAGMENT_SHADER])
+}
+
+/// compiles the shaders again and replaces the pipeline with one, that uses the
+/// new code; if the shaders fail to compile or the pipeline can't be created,
+/// the error is logged and the current pipeline and its code stay in use
+///
+/// the pipeline must not be in use by the device
+pub unsafe fn reload_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) {
+    match try_reload_pipeline(device, data) {
+        Ok(()) => log::info!("Reloaded pipeline"),
+        Err(e) => log::error!(
+            "Failed to reload the pipeline, keeping the last working one:\n{}",
+            e
+        ),
+    }
+}
+
+// `data` is only changed, once everything succeeded
+unsafe fn try_reload_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    let (vertex_shader_code, fragment_shader_code, reflection) = compile_pipeline_shaders(data)?;
+    let (pipeline_layout, pipeline) =
+        build_pipeline(device, data, &vertex_shader_code, &fragment_shader_code)?;
+
+    // the old pipeline and layout are destroyed, when they are replaced
+    data.pipeline = pipeline;
+    data.pipeline_layout = pipeline_layout;
+    data.vertex_shader_code = vertex_shader_code;
+    data.fragment_shader_code = fragment_shader_code;
+    data.shader_reflection = reflection;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::mem::offset_of;
+
+    fn vertex_at(pos: glm::Vec3) -> Vertex {
+        Vertex::new(
+            pos,
+            glm::vec3(1.0, 1.0, 1.0),
+            glm::vec2(0.0, 0.0),
+            glm::Vec3::z(),
+        )
+    }
+
+    // the locations have to match the inputs of shader.vert
+    #[test]
+    fn vertex_attributes_match_the_fields() {
+        let formats_and_offsets = Vertex::attribute_descriptions()
+            .iter()
+            .map(|a| (a.location, a.format, a.offset as usize))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            formats_and_offsets,
+            [
+                (0, vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, pos)),
+                (1, vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, color)),
+                (2, vk::Format::R32G32_SFLOAT, offset_of!(Vertex, tex_coord)),
+                (3, vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, normal)),
+            ]
+        );
+        assert_eq!(
+            Vertex::binding_description().stride as usize,
+            size_of::<Vertex>()
+        );
+    }
+
+    // the bit patterns differ, so the vertices are not merged; this keeps Eq
+    // consistent with Hash
+    #[test]
+    fn signed_zeros_are_different_vertices() {
+        let a = vertex_at(glm::vec3(0.0, 1.0, 2.0));
+        let b = vertex_at(glm::vec3(-0.0, 1.0, 2.0));
+        assert_ne!(a, b);
+        assert_eq!(HashSet::from([a, b]).len(), 2);
+    }
+
+    // unlike f32, the comparison of the bits is reflexive, so NaN vertices are
+    // merged like any other vertex
+    #[test]
+    fn nan_vertices_equal_themselves() {
+        let a = vertex_at(glm::vec3(f32::NAN, 0.0, 0.0));
+        assert_eq!(a, a);
+        assert_eq!(HashSet::from([a, a]).len(), 1);
+        assert_ne!(a, vertex_at(glm::vec3(0.0, 0.0, 0.0)));
+    }
 }
@@ -0,0 +1,54 @@
+use std::mem::size_of;
+use std::slice;
+
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+// the specification only guarantees 128 bytes of push constants, which is
+// enough for two 4x4 matrices
+const MAX_PUSH_CONSTANTS_SIZE: usize = 128;
+
+/// data, which is pushed to the shaders before each draw call
+///
+/// push constants are recorded directly into the command buffer, so they are
+/// the fastest way to pass small amounts of data, which change per draw; the
+/// layout has to match the push_constant blocks in the shaders
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PushConstants {
+    // transformation of the drawn scene node into world space
+    pub model: glm::Mat4,
+    // base color factor of the material, multiplied with the texture color
+    pub base_color: glm::Vec4,
+}
+
+// fail at compile time, if the struct grows beyond the guaranteed size
+const _: () = assert!(size_of::<PushConstants>() <= MAX_PUSH_CONSTANTS_SIZE);
+
+impl PushConstants {
+    /// shader stages, which can access the push constants
+    pub const STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_bits_truncate(
+        vk::ShaderStageFlags::VERTEX.bits() | vk::ShaderStageFlags::FRAGMENT.bits(),
+    );
+
+    /// the range, which has to be part of the pipeline layout
+    pub fn range() -> vk::PushConstantRange {
+        vk::PushConstantRange::builder()
+            .stage_flags(Self::STAGES)
+            .offset(0)
+            .size(size_of::<Self>() as u32)
+            .build()
+    }
+
+    /// records the push constants into the command buffer; the pipeline layout
+    /// has to contain the range
+    pub unsafe fn push(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+    ) {
+        let bytes = slice::from_raw_parts((self as *const Self).cast::<u8>(), size_of::<Self>());
+        device.cmd_push_constants(command_buffer, layout, Self::STAGES, 0, bytes);
+    }
+}
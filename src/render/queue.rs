@@ -11,6 +11,9 @@ use crate::app::AppData;
 pub struct QueueFamilyIndices {
     pub graphics: u32,
     pub presentation: u32,
+    // used for uploads; the graphics queue family, if there is no other family
+    // with transfer support
+    pub transfer: u32,
 }
 
 impl QueueFamilyIndices {
@@ -31,27 +34,56 @@ impl QueueFamilyIndices {
 
         let mut present = None;
 
-        // find the queue family, which supports presentation
-        for (index, properties) in properties.iter().enumerate() {
-            if instance.get_physical_device_surface_support_khr(
-                physical_device,
-                index as u32,
-                data.surface,
-            )? {
-                present = Some(index as u32);
-                break;
+        if data.headless {
+            // without a surface, nothing is presented, so the graphics queue
+            // family is used in place of the presentation queue family
+            present = graphics_property;
+        } else {
+            // find the queue family, which supports presentation
+            for (index, _) in properties.iter().enumerate() {
+                if instance.get_physical_device_surface_support_khr(
+                    physical_device,
+                    index as u32,
+                    data.surface,
+                )? {
+                    present = Some(index as u32);
+                    break;
+                }
             }
         }
 
-        if let (Some(graphics), Some(present)) = (graphics_property, present) {
-            Ok(Self {
+        match (graphics_property, present) {
+            (Some(graphics), Some(present)) => Ok(Self {
                 graphics,
                 presentation: present,
-            })
-        } else {
-            Err(anyhow!(SuitabilityError(
-                "Missing required queue families."
-            )))
+                transfer: find_transfer_family(&properties).unwrap_or(graphics),
+            }),
+            _ => {
+                let missing = [(graphics_property, "graphics"), (present, "presentation")]
+                    .iter()
+                    .filter(|(index, _)| index.is_none())
+                    .map(|(_, name)| *name)
+                    .collect();
+                Err(anyhow!(SuitabilityError::MissingQueueFamilies(missing)))
+            }
         }
     }
 }
+
+// graphics and compute queues always support transfers as well, but a family,
+// which only supports transfers, usually maps to a dedicated dma engine, which
+// copies in parallel to rendering; otherwise any non-graphics family is used
+fn find_transfer_family(properties: &[vk::QueueFamilyProperties]) -> Option<u32> {
+    let transfer_without = |excluded: vk::QueueFlags| {
+        properties
+            .iter()
+            .position(|p| {
+                p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !p.queue_flags.intersects(excluded)
+            })
+            .map(|i| i as u32)
+    };
+
+    transfer_without(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        .or_else(|| transfer_without(vk::QueueFlags::GRAPHICS))
+}
@@ -0,0 +1,545 @@
+use anyhow::{anyhow, Result};
+
+use naga::front::spv;
+use naga::{
+    AddressSpace, ArraySize, Binding, ImageClass, Module, ScalarKind, TypeInner, VectorSize,
+};
+use vulkanalia::prelude::v1_0::*;
+
+/// a descriptor, which is used by at least one shader stage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    // number of descriptors (greater than 1 for arrays of descriptors)
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+impl DescriptorBinding {
+    pub fn layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(self.binding)
+            .descriptor_type(self.descriptor_type)
+            .descriptor_count(self.count)
+            .stage_flags(self.stages)
+            .build()
+    }
+}
+
+/// an input of the vertex shader, which is fed by a vertex attribute
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub name: Option<String>,
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+/// the resources used by the shaders of a pipeline
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderReflection {
+    // sorted by set and binding
+    pub bindings: Vec<DescriptorBinding>,
+    // sorted by location
+    pub vertex_inputs: Vec<VertexInput>,
+    // size of the push constant block in bytes (0, if there is none)
+    pub push_constants_size: u32,
+}
+
+impl ShaderReflection {
+    /// reflects the SPIR-V code of the given shader stages and merges the results;
+    /// descriptors, which are used by several stages, are combined
+    pub fn new(shaders: &[(&[u32], vk::ShaderStageFlags)]) -> Result<Self> {
+        let mut reflection = Self::default();
+
+        for (code, stage) in shaders {
+            let options = spv::Options {
+                adjust_coordinate_space: false,
+                strict_capabilities: false,
+                block_ctx_dump_prefix: None,
+            };
+            let module = spv::Frontend::new(code.iter().cloned(), &options)
+                .parse()
+                .map_err(|e| anyhow!("Failed to parse SPIR-V of {:?} shader: {}", stage, e))?;
+
+            reflection.add_bindings(&module, *stage)?;
+
+            if *stage == vk::ShaderStageFlags::VERTEX {
+                reflection.vertex_inputs = vertex_inputs(&module)?;
+            }
+
+            reflection.push_constants_size = reflection
+                .push_constants_size
+                .max(push_constants_size(&module));
+        }
+
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+
+        Ok(reflection)
+    }
+
+    fn add_bindings(&mut self, module: &Module, stage: vk::ShaderStageFlags) -> Result<()> {
+        for (_, variable) in module.global_variables.iter() {
+            let Some(resource) = &variable.binding else {
+                continue;
+            };
+
+            let (descriptor_type, count) = descriptor_type(module, variable.space, variable.ty)?;
+
+            match self
+                .bindings
+                .iter_mut()
+                .find(|b| b.set == resource.group && b.binding == resource.binding)
+            {
+                Some(existing) if existing.descriptor_type != descriptor_type => {
+                    return Err(anyhow!(
+                        "Binding {} of set {} is used as {:?} and {:?}.",
+                        resource.binding,
+                        resource.group,
+                        existing.descriptor_type,
+                        descriptor_type
+                    ));
+                }
+                Some(existing) => existing.stages |= stage,
+                None => self.bindings.push(DescriptorBinding {
+                    set: resource.group,
+                    binding: resource.binding,
+                    descriptor_type,
+                    count,
+                    stages: stage,
+                }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// checks, that every input of the vertex shader is fed by an attribute of the
+    /// same format, and that the attributes fit into the vertex without overlapping
+    pub fn check_vertex_layout(
+        &self,
+        binding: &vk::VertexInputBindingDescription,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Result<()> {
+        for input in &self.vertex_inputs {
+            let name = input.name.as_deref().unwrap_or("<unnamed>");
+            let attribute = attributes
+                .iter()
+                .find(|a| a.location == input.location)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Vertex input '{}' (location {}) has no vertex attribute.",
+                        name,
+                        input.location
+                    )
+                })?;
+
+            if attribute.format != input.format {
+                return Err(anyhow!(
+                    "Vertex input '{}' (location {}) expects {:?}, but the attribute is {:?}.",
+                    name,
+                    input.location,
+                    input.format,
+                    attribute.format
+                ));
+            }
+        }
+
+        let mut attributes = attributes
+            .iter()
+            .filter(|a| a.binding == binding.binding)
+            .collect::<Vec<_>>();
+        attributes.sort_by_key(|a| a.offset);
+
+        for (i, attribute) in attributes.iter().enumerate() {
+            let end = attribute.offset + format_size(attribute.format)?;
+            if end > binding.stride {
+                return Err(anyhow!(
+                    "Vertex attribute at location {} ends at byte {}, but the vertex stride is {}.",
+                    attribute.location,
+                    end,
+                    binding.stride
+                ));
+            }
+
+            if let Some(next) = attributes.get(i + 1).filter(|n| n.offset < end) {
+                return Err(anyhow!(
+                    "Vertex attributes at locations {} and {} overlap (offsets {} and {}).",
+                    attribute.location,
+                    next.location,
+                    attribute.offset,
+                    next.offset
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn descriptor_type(
+    module: &Module,
+    space: AddressSpace,
+    ty: naga::Handle<naga::Type>,
+) -> Result<(vk::DescriptorType, u32)> {
+    let descriptor_type = match (space, &module.types[ty].inner) {
+        (AddressSpace::Uniform, _) => vk::DescriptorType::UNIFORM_BUFFER,
+        (AddressSpace::Storage { .. }, _) => vk::DescriptorType::STORAGE_BUFFER,
+        (AddressSpace::Handle, TypeInner::BindingArray { base, size }) => {
+            let (descriptor_type, _) = descriptor_type(module, space, *base)?;
+            let count = match size {
+                ArraySize::Constant(count) => count.get(),
+                _ => return Err(anyhow!("Descriptor arrays need a constant size.")),
+            };
+            return Ok((descriptor_type, count));
+        }
+        (AddressSpace::Handle, TypeInner::Sampler { .. }) => vk::DescriptorType::SAMPLER,
+        (AddressSpace::Handle, TypeInner::Image { class, .. }) => match class {
+            ImageClass::Storage { .. } => vk::DescriptorType::STORAGE_IMAGE,
+            _ => vk::DescriptorType::SAMPLED_IMAGE,
+        },
+        (space, inner) => {
+            return Err(anyhow!(
+                "Unsupported shader resource {:?} in {:?}.",
+                inner,
+                space
+            ))
+        }
+    };
+
+    Ok((descriptor_type, 1))
+}
+
+// the inputs of the vertex shader are the arguments of its entry point, which
+// are bound to a location
+fn vertex_inputs(module: &Module) -> Result<Vec<VertexInput>> {
+    let entry_point = module
+        .entry_points
+        .iter()
+        .find(|e| e.stage == naga::ShaderStage::Vertex)
+        .ok_or_else(|| anyhow!("Vertex shader has no entry point."))?;
+
+    let mut inputs = Vec::new();
+    for argument in &entry_point.function.arguments {
+        if let Some(Binding::Location { location, .. }) = argument.binding {
+            inputs.push(VertexInput {
+                name: argument.name.clone(),
+                location,
+                format: vertex_format(&module.types[argument.ty].inner)?,
+            });
+        }
+    }
+
+    inputs.sort_by_key(|i| i.location);
+
+    Ok(inputs)
+}
+
+fn push_constants_size(module: &Module) -> u32 {
+    module
+        .global_variables
+        .iter()
+        .filter(|(_, v)| v.space == AddressSpace::Immediate)
+        .map(|(_, v)| module.types[v.ty].inner.size(module.to_ctx()))
+        .max()
+        .unwrap_or(0)
+}
+
+/// the vertex attribute format for a scalar or vector type of a shader
+pub fn vertex_format(inner: &TypeInner) -> Result<vk::Format> {
+    let (size, scalar) = match inner {
+        TypeInner::Scalar(scalar) => (1, *scalar),
+        TypeInner::Vector { size, scalar } => {
+            let size = match size {
+                VectorSize::Bi => 2,
+                VectorSize::Tri => 3,
+                VectorSize::Quad => 4,
+            };
+            (size, *scalar)
+        }
+        _ => return Err(anyhow!("Unsupported vertex input type {:?}.", inner)),
+    };
+
+    use vk::Format as F;
+    let format = match (scalar.kind, scalar.width, size) {
+        (ScalarKind::Float, 4, 1) => F::R32_SFLOAT,
+        (ScalarKind::Float, 4, 2) => F::R32G32_SFLOAT,
+        (ScalarKind::Float, 4, 3) => F::R32G32B32_SFLOAT,
+        (ScalarKind::Float, 4, 4) => F::R32G32B32A32_SFLOAT,
+        (ScalarKind::Sint, 4, 1) => F::R32_SINT,
+        (ScalarKind::Sint, 4, 2) => F::R32G32_SINT,
+        (ScalarKind::Sint, 4, 3) => F::R32G32B32_SINT,
+        (ScalarKind::Sint, 4, 4) => F::R32G32B32A32_SINT,
+        (ScalarKind::Uint, 4, 1) => F::R32_UINT,
+        (ScalarKind::Uint, 4, 2) => F::R32G32_UINT,
+        (ScalarKind::Uint, 4, 3) => F::R32G32B32_UINT,
+        (ScalarKind::Uint, 4, 4) => F::R32G32B32A32_UINT,
+        _ => return Err(anyhow!("Unsupported vertex input type {:?}.", inner)),
+    };
+
+    Ok(format)
+}
+
+/// size of a single element of the vertex attribute format in bytes
+pub fn format_size(format: vk::Format) -> Result<u32> {
+    use vk::Format as F;
+    let size = match format {
+        F::R8G8B8A8_UNORM | F::R8G8B8A8_SNORM | F::R8G8B8A8_UINT | F::R8G8B8A8_SINT => 4,
+        F::R16G16_SFLOAT => 4,
+        F::R16G16B16A16_SFLOAT => 8,
+        F::R32_SFLOAT | F::R32_SINT | F::R32_UINT => 4,
+        F::R32G32_SFLOAT | F::R32G32_SINT | F::R32G32_UINT => 8,
+        F::R32G32B32_SFLOAT | F::R32G32B32_SINT | F::R32G32B32_UINT => 12,
+        F::R32G32B32A32_SFLOAT | F::R32G32B32A32_SINT | F::R32G32B32A32_UINT => 16,
+        _ => return Err(anyhow!("Unsupported vertex attribute format {:?}.", format)),
+    };
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::shader;
+
+    const VERTEX_SHADER: &str = "#version 450
+layout(binding = 0) uniform Camera {
+    mat4 viewProj;
+} camera;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    vec4 color;
+} node;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 2) in vec2 inTexCoord;
+layout(location = 1) in uvec4 inJoints;
+
+layout(location = 0) out vec2 fragTexCoord;
+
+void main() {
+    gl_Position = camera.viewProj * node.model * vec4(inPosition, 1.0);
+    fragTexCoord = inTexCoord + vec2(inJoints.xy) * node.color.xy;
+}
+";
+
+    const FRAGMENT_SHADER: &str = "#version 450
+layout(binding = 0) uniform Camera {
+    mat4 viewProj;
+} camera;
+
+layout(set = 1, binding = 0) uniform texture2D tex;
+layout(set = 1, binding = 1) uniform sampler texSampler;
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = texture(sampler2D(tex, texSampler), fragTexCoord) * camera.viewProj[0];
+}
+";
+
+    // compiles the source like the shaders of the renderer; the extension of
+    // `name` selects the stage
+    fn compile(name: &str, source: &str) -> Vec<u32> {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        std::fs::write(&path, source).unwrap();
+        let code = shader::compile_shader(&path);
+        std::fs::remove_file(&path).unwrap();
+        code.unwrap()
+    }
+
+    fn reflect() -> ShaderReflection {
+        let vertex = compile("reflect.vert", VERTEX_SHADER);
+        let fragment = compile("reflect.frag", FRAGMENT_SHADER);
+        ShaderReflection::new(&[
+            (&vertex, vk::ShaderStageFlags::VERTEX),
+            (&fragment, vk::ShaderStageFlags::FRAGMENT),
+        ])
+        .unwrap()
+    }
+
+    fn attribute(
+        location: u32,
+        format: vk::Format,
+        offset: u32,
+    ) -> vk::VertexInputAttributeDescription {
+        vk::VertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset,
+        }
+    }
+
+    fn binding(stride: u32) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    #[test]
+    fn bindings_of_both_stages_are_merged() {
+        let reflection = reflect();
+        let binding = |set, binding, descriptor_type, count, stages| DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            stages,
+        };
+
+        assert_eq!(
+            reflection.bindings,
+            [
+                binding(
+                    0,
+                    0,
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                    1,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+                ),
+                binding(
+                    1,
+                    0,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                    1,
+                    vk::ShaderStageFlags::FRAGMENT
+                ),
+                binding(
+                    1,
+                    1,
+                    vk::DescriptorType::SAMPLER,
+                    1,
+                    vk::ShaderStageFlags::FRAGMENT
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn vertex_inputs_are_sorted_by_location() {
+        let inputs = reflect()
+            .vertex_inputs
+            .iter()
+            .map(|i| (i.location, i.format))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            inputs,
+            [
+                (0, vk::Format::R32G32B32_SFLOAT),
+                (1, vk::Format::R32G32B32A32_UINT),
+                (2, vk::Format::R32G32_SFLOAT),
+            ]
+        );
+    }
+
+    // a mat4 and a vec4
+    #[test]
+    fn push_constants_size_is_the_size_of_the_block() {
+        assert_eq!(reflect().push_constants_size, 80);
+    }
+
+    #[test]
+    fn binding_with_different_types_is_an_error() {
+        let vertex = compile("conflict.vert", VERTEX_SHADER);
+        let fragment = compile(
+            "conflict.frag",
+            "#version 450
+layout(binding = 0) uniform texture2D tex;
+layout(binding = 1) uniform sampler texSampler;
+layout(location = 0) out vec4 outColor;
+void main() {
+    outColor = texture(sampler2D(tex, texSampler), vec2(0.0));
+}
+",
+        );
+
+        let error = ShaderReflection::new(&[
+            (&vertex, vk::ShaderStageFlags::VERTEX),
+            (&fragment, vk::ShaderStageFlags::FRAGMENT),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("is used as"), "{}", error);
+    }
+
+    #[test]
+    fn matching_vertex_layout_is_accepted() {
+        let attributes = [
+            attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32B32A32_UINT, 12),
+            attribute(2, vk::Format::R32G32_SFLOAT, 28),
+        ];
+        reflect()
+            .check_vertex_layout(&binding(36), &attributes)
+            .unwrap();
+    }
+
+    #[test]
+    fn vertex_layout_mismatches_are_errors() {
+        let reflection = reflect();
+        let check = |stride, attributes: &[_]| {
+            reflection
+                .check_vertex_layout(&binding(stride), attributes)
+                .unwrap_err()
+                .to_string()
+        };
+
+        // location 2 is missing
+        let error = check(
+            28,
+            &[
+                attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+                attribute(1, vk::Format::R32G32B32A32_UINT, 12),
+            ],
+        );
+        assert!(error.contains("has no vertex attribute"), "{}", error);
+
+        // the joints are passed as floats
+        let error = check(
+            36,
+            &[
+                attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+                attribute(1, vk::Format::R32G32B32A32_SFLOAT, 12),
+                attribute(2, vk::Format::R32G32_SFLOAT, 28),
+            ],
+        );
+        assert!(error.contains("expects R32G32B32A32_UINT"), "{}", error);
+
+        // the texture coordinates end after the vertex
+        let error = check(
+            32,
+            &[
+                attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+                attribute(1, vk::Format::R32G32B32A32_UINT, 12),
+                attribute(2, vk::Format::R32G32_SFLOAT, 28),
+            ],
+        );
+        assert!(error.contains("vertex stride is 32"), "{}", error);
+
+        // the joints start inside of the position
+        let error = check(
+            36,
+            &[
+                attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+                attribute(1, vk::Format::R32G32B32A32_UINT, 8),
+                attribute(2, vk::Format::R32G32_SFLOAT, 28),
+            ],
+        );
+        assert!(error.contains("overlap"), "{}", error);
+    }
+
+    #[test]
+    fn format_sizes() {
+        assert_eq!(format_size(vk::Format::R32_SFLOAT).unwrap(), 4);
+        assert_eq!(format_size(vk::Format::R32G32_SINT).unwrap(), 8);
+        assert_eq!(format_size(vk::Format::R32G32B32_SFLOAT).unwrap(), 12);
+        assert_eq!(format_size(vk::Format::R32G32B32A32_UINT).unwrap(), 16);
+        assert_eq!(format_size(vk::Format::R8G8B8A8_UNORM).unwrap(), 4);
+        assert!(format_size(vk::Format::D32_SFLOAT).is_err());
+    }
+}
@@ -1,13 +1,18 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+
+use std::sync::Arc;
 
-use log::info;
 use vulkanalia::prelude::v1_0::*;
 
 use crate::app::AppData;
 
+use super::handle::{OwnedDevice, RenderPass};
+use super::image;
+use super::tonemap::HDR_FORMAT;
+
 pub unsafe fn create_render_pass(
-    insntance: &Instance,
-    device: &Device,
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
     // specify render pass: which framebuffer attachements are used while rendering
@@ -16,19 +21,60 @@ pub unsafe fn create_render_pass(
     // - how many samples for each of them?
     // - how to handle buffer contents?
 
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+
+    // the scene is rendered into the HDR image, which is sampled by the tone
+    // mapping pass afterwards (see tonemap.rs)
+    let hdr_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+
+    // multisampled images can't be sampled like regular images, they need to be
+    // resolved into a regular image first; the multisampled contents are not
+    // needed after resolving, so they don't need to be stored
+    let (color_store_op, color_final_layout) = if multisampled {
+        (
+            vk::AttachmentStoreOp::DONT_CARE,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+    } else {
+        (vk::AttachmentStoreOp::STORE, hdr_layout)
+    };
+
     let color_attachment = vk::AttachmentDescription::builder()
-        .format(data.swapchain_format)
-        .samples(vk::SampleCountFlags::_1) // no multisampling yet
+        .format(HDR_FORMAT)
+        .samples(data.msaa_samples)
         // load op and store op apply to color and depth
         .load_op(vk::AttachmentLoadOp::CLEAR) // what to do before rendering
-        .store_op(vk::AttachmentStoreOp::STORE) // what to do after rendering
+        .store_op(color_store_op) // what to do after rendering
         // stencil specific ops
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE) // ignore for now
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE) // ignore for now
         // images need to be transitioned to a layout, which is suitable for
         // the operation that they're going to be involved in next
         .initial_layout(vk::ImageLayout::UNDEFINED) // don't care, what layout the image is before this
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR); // present image to swapchain next
+        .final_layout(color_final_layout);
+
+    // the depth attachment uses the same format as the depth image; its contents
+    // are not needed after drawing, so they don't have to be stored
+    let depth_stencil_attachment = vk::AttachmentDescription::builder()
+        .format(image::get_depth_format(instance, data)?)
+        .samples(data.msaa_samples)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED) // previous depth contents don't matter
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    // the HDR image, which the multisampled color image is resolved into
+    let color_resolve_attachment = vk::AttachmentDescription::builder()
+        .format(HDR_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE) // everything is overwritten by the resolve
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(hdr_layout);
 
     // --- define subpasses ---
 
@@ -41,9 +87,28 @@ pub unsafe fn create_render_pass(
     // fragment shader by the `layout(location = 0) out vec4 outColor`
     let attachment_references = &[color_attachment_ref];
 
-    let subpass = vk::SubpassDescription::builder()
+    let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_resolve_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(2)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    // the resolve attachment at index i is the target of the color attachment
+    // at index i
+    let resolve_attachment_references = &[color_resolve_attachment_ref];
+
+    // unlike color attachments, a subpass can only use a single depth (+stencil)
+    // attachment
+    let mut subpass = vk::SubpassDescription::builder()
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-        .color_attachments(attachment_references); // there are other attachment-types!
+        .color_attachments(attachment_references) // there are other attachment-types!
+        .depth_stencil_attachment(&depth_stencil_attachment_ref);
+
+    if multisampled {
+        subpass = subpass.resolve_attachments(resolve_attachment_references);
+    }
 
     // --- define render pass ---
 
@@ -69,24 +134,64 @@ pub unsafe fn create_render_pass(
         // define, operations to wait on and stage(s) in which these operations
         // occur -> we need to wait for swapchain to read from image -> wait
         // for color attachment output itself
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .src_access_mask(vk::AccessFlags::empty())
+        // the depth image is first accessed in the early fragment test stage,
+        // where it is cleared (which is a write operation); it is shared by all
+        // frames, so the depth writes of the previous frame (in the early and
+        // late fragment tests) need to be finished and made available first
+        // the multisampled color image and the HDR image are shared by all
+        // frames as well, so the color writes of the previous frame need to be
+        // finished, and the HDR image must not be overwritten, while the tone
+        // mapping pass of the previous frame still samples it
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        )
+        .src_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
         // operations, that should wait on this are in color attachment stage
         // and involve writing of color attachment -> this will prevent
         // transition from happening until it's actually necessary (and allowed):
         // when we want to start writing colors to it..
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        );
+
+    let attachements: &[vk::AttachmentDescription] = if multisampled {
+        &[
+            *color_attachment,
+            *depth_stencil_attachment,
+            *color_resolve_attachment,
+        ]
+    } else {
+        &[*color_attachment, *depth_stencil_attachment]
+    };
+    // the tone mapping pass samples the HDR image, after it was written completely
+    let hdr_dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
 
-    let attachements = &[color_attachment];
     let subpasses = &[subpass];
-    let dependencies = &[dependency];
+    let dependencies = &[dependency, hdr_dependency];
     let info = vk::RenderPassCreateInfo::builder()
         .attachments(attachements)
         .subpasses(subpasses)
         .dependencies(dependencies);
 
-    data.render_pass = device.create_render_pass(&info, None)?;
+    data.render_pass = RenderPass::new(device, device.create_render_pass(&info, None)?);
 
     Ok(())
 }
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use naga::back::spv;
+use naga::front::glsl;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::{ShaderStage, SourceLocation};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+// the shader sources are compiled at runtime, so they are read relative to the
+// working directory (like the textures)
+pub const SHADER_DIR: &str = "src/shader";
+pub const VERTEX_SHADER: &str = "src/shader/shader.vert";
+pub const FRAGMENT_SHADER: &str = "src/shader/shader.frag";
+// renders the shadow map
+pub const SHADOW_VERTEX_SHADER: &str = "src/shader/shadow.vert";
+// draws the skybox; only compiled, if there is one
+pub const SKYBOX_VERTEX_SHADER: &str = "src/shader/skybox.vert";
+pub const SKYBOX_FRAGMENT_SHADER: &str = "src/shader/skybox.frag";
+// maps the HDR image of the scene into the swapchain image
+pub const TONEMAP_VERTEX_SHADER: &str = "src/shader/tonemap.vert";
+pub const TONEMAP_FRAGMENT_SHADER: &str = "src/shader/tonemap.frag";
+
+/// compiles a GLSL shader to SPIR-V; the stage is derived from the file extension
+/// (.vert or .frag)
+///
+/// errors contain the file and line of each problem, like the output of glslc
+pub fn compile_shader(path: &Path) -> Result<Vec<u32>> {
+    let stage = match path.extension().and_then(|e| e.to_str()) {
+        Some("vert") => ShaderStage::Vertex,
+        Some("frag") => ShaderStage::Fragment,
+        _ => return Err(anyhow!("Unknown shader stage of {}.", path.display())),
+    };
+
+    let source = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+    let module = glsl::Frontend::default()
+        .parse(&glsl::Options::from(stage), &source)
+        .map_err(|e| {
+            let messages = e
+                .errors
+                .iter()
+                .map(|error| format_error(path, error.location(&source), &error.kind))
+                .collect::<Vec<_>>();
+            anyhow!("{}", messages.join("\n"))
+        })?;
+
+    // push constants are called "immediates" by naga
+    let info = Validator::new(ValidationFlags::all(), Capabilities::IMMEDIATES)
+        .validate(&module)
+        .map_err(|e| anyhow!("{}", format_error(path, e.location(&source), e.as_inner())))?;
+
+    // the shaders are written for vulkan, so the y-axis of the clip space must
+    // not be flipped (naga does that by default, to match the WebGPU conventions)
+    let mut options = spv::Options::default();
+    options
+        .flags
+        .remove(spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+
+    let code = spv::write_vec(&module, &info, &options, None)
+        .map_err(|e| anyhow!("Failed to write SPIR-V for {}: {}", path.display(), e))?;
+
+    log::debug!("Compiled {} ({} bytes)", path.display(), code.len() * 4);
+
+    Ok(code)
+}
+
+/// whether `path` (as reported by the ShaderWatcher) is one of the given shaders
+pub fn is_any_of(path: &Path, shaders: &[&str]) -> bool {
+    shaders
+        .iter()
+        .filter_map(|s| Path::new(s).canonicalize().ok())
+        .any(|s| s == path)
+}
+
+fn format_error(path: &Path, location: Option<SourceLocation>, error: &dyn fmt::Display) -> String {
+    match location {
+        Some(location) => format!(
+            "{}:{}:{}: error: {}",
+            path.display(),
+            location.line_number,
+            location.line_position,
+            error
+        ),
+        None => format!("{}: error: {}", path.display(), error),
+    }
+}
+
+/// watches the shader directory and collects the paths of modified shaders
+pub struct ShaderWatcher {
+    // stops watching, when dropped
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<PathBuf>,
+}
+
+impl fmt::Debug for ShaderWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShaderWatcher").finish_non_exhaustive()
+    }
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &Path) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+
+        // the callback is called from a thread of the watcher
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                match event {
+                    Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                        for path in event.paths {
+                            // fails only, if the watcher outlives the app
+                            let _ = sender.send(path);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Shader watcher error: {}", e),
+                }
+            })?;
+
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        log::info!("Watching {} for shader changes", dir.display());
+
+        Ok(Self {
+            _watcher: watcher,
+            receiver,
+        })
+    }
+
+    /// the shaders, which changed since the last call; editors usually emit
+    /// several events for a single save, so the paths are deduplicated
+    pub fn changed(&self) -> HashSet<PathBuf> {
+        self.receiver
+            .try_iter()
+            .filter_map(|p| p.canonicalize().ok())
+            .collect()
+    }
+}
@@ -0,0 +1,484 @@
+use anyhow::{anyhow, Result};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+use crate::config::ShadowConfig;
+
+use super::descriptor_set;
+use super::handle::{
+    Framebuffer, Image, ImageView, OwnedDevice, Pipeline, PipelineLayout, RenderPass, Sampler,
+};
+use super::image;
+use super::pipeline::{self, Vertex};
+use super::push_constants::PushConstants;
+use super::reflection::ShaderReflection;
+use super::shader;
+use super::vertex::VertexLayout;
+
+/// depth of the scene as seen from the light, which casts shadows
+///
+/// the shadow map is rendered in its own render pass before the main pass of
+/// each frame; the fragment shader compares the depth of each fragment with the
+/// stored depth through a comparison sampler. a single shadow map is shared by
+/// all frames in flight, as the render passes are ordered on the graphics queue
+#[derive(Debug, Default)]
+pub struct ShadowMap {
+    // the framebuffer and the view are destroyed before the image
+    pub framebuffer: Framebuffer,
+    pub view: ImageView,
+    // only accessed through the view
+    #[allow(dead_code)]
+    pub image: Image,
+    pub sampler: Sampler,
+    pub pipeline: Pipeline,
+    pub pipeline_layout: PipelineLayout,
+    pub render_pass: RenderPass,
+    // width and height of the shadow map
+    pub resolution: u32,
+    // radius of a sphere around the origin, which contains the whole scene; the
+    // projection of the light covers this sphere
+    pub scene_radius: f32,
+    // see ShadowConfig; kept to create the pipeline again, when the shader changes
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    vertex_shader_code: Vec<u32>,
+}
+
+/// creates the shadow map and the depth-only pipeline, which renders into it;
+/// the descriptor set layout and the scene have to exist already
+pub unsafe fn create_shadow_map(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    config: &ShadowConfig,
+) -> Result<()> {
+    let limit = instance
+        .get_physical_device_properties(data.physical_device)
+        .limits
+        .max_image_dimension_2d;
+    if config.resolution > limit {
+        return Err(anyhow!(
+            "Shadow map resolution {} exceeds the maximum image size {} of the device.",
+            config.resolution,
+            limit
+        ));
+    }
+
+    // the depth is sampled by the fragment shader, so the format has to support both
+    let format = image::get_supported_format(
+        instance,
+        data,
+        &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM],
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+    )?;
+
+    let image = image::create_image(
+        instance,
+        device,
+        data,
+        config.resolution,
+        config.resolution,
+        1,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let view = image::create_image_view(device, *image, format, vk::ImageAspectFlags::DEPTH, 1)?;
+
+    // the comparison returns 1.0, if the depth of the fragment is less than or
+    // equal to the stored depth (the fragment is lit); outside of the shadow map,
+    // the border is at the far plane, so nothing is in shadow there
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .compare_enable(true)
+        .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .min_lod(0.0)
+        .max_lod(0.0);
+    let sampler = Sampler::new(device, device.create_sampler(&info, None)?);
+
+    let render_pass = create_shadow_render_pass(device, format)?;
+
+    let attachments = &[*view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(*render_pass)
+        .attachments(attachments)
+        .width(config.resolution)
+        .height(config.resolution)
+        .layers(1);
+    let framebuffer = Framebuffer::new(device, device.create_framebuffer(&info, None)?);
+
+    data.shadow = ShadowMap {
+        framebuffer,
+        view,
+        image,
+        sampler,
+        render_pass,
+        resolution: config.resolution,
+        scene_radius: scene_radius(data),
+        depth_bias: config.depth_bias,
+        slope_bias: config.slope_bias,
+        vertex_shader_code: compile_shadow_shader(data)?,
+        ..Default::default()
+    };
+
+    create_shadow_pipeline(device, data)?;
+
+    log::debug!(
+        "Created {}x{} shadow map with format {:?}",
+        config.resolution,
+        config.resolution,
+        format
+    );
+
+    Ok(())
+}
+
+// a single depth attachment, which is sampled after the pass
+unsafe fn create_shadow_render_pass(
+    device: &Arc<OwnedDevice>,
+    format: vk::Format,
+) -> Result<RenderPass> {
+    let depth_attachment = vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        // the previous contents are cleared anyway
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .depth_stencil_attachment(&depth_attachment_ref);
+
+    // the shadow map must not be overwritten, while the fragment shader of the
+    // previous frame still samples it, and must be written completely, before the
+    // fragment shader of this frame samples it
+    let dependencies = &[
+        vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+
+    let attachments = &[depth_attachment];
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    Ok(RenderPass::new(
+        device,
+        device.create_render_pass(&info, None)?,
+    ))
+}
+
+// the shadow pass binds the descriptor sets of the main pass, so the shader
+// can't use any other descriptors
+fn compile_shadow_shader(data: &AppData) -> Result<Vec<u32>> {
+    let code = shader::compile_shader(Path::new(shader::SHADOW_VERTEX_SHADER))?;
+
+    let reflection = ShaderReflection::new(&[(&code, vk::ShaderStageFlags::VERTEX)])?;
+    reflection.check_vertex_layout(
+        &Vertex::binding_description(),
+        &Vertex::attribute_descriptions(),
+    )?;
+    if let Some(binding) = reflection
+        .bindings
+        .iter()
+        .find(|b| !data.shader_reflection.bindings.contains(b))
+    {
+        return Err(anyhow!(
+            "Shadow shader uses descriptor {:?}, which is not used by the main shaders.",
+            binding
+        ));
+    }
+
+    Ok(code)
+}
+
+/// whether the shadow pipeline is built from the shader at `path`
+pub fn uses_shader(path: &Path) -> bool {
+    shader::is_any_of(path, &[shader::SHADOW_VERTEX_SHADER])
+}
+
+/// compiles the shadow shader again and replaces the shadow pipeline; like
+/// pipeline::reload_pipeline, errors are logged and the current pipeline stays
+/// in use
+///
+/// the pipeline must not be in use by the device
+pub unsafe fn reload_shadow_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) {
+    match try_reload_shadow_pipeline(device, data) {
+        Ok(()) => log::info!("Reloaded shadow pipeline"),
+        Err(e) => log::error!(
+            "Failed to reload the shadow pipeline, keeping the last working one:\n{}",
+            e
+        ),
+    }
+}
+
+// `data` is only changed, once everything succeeded
+unsafe fn try_reload_shadow_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    let code = compile_shadow_shader(data)?;
+    let (pipeline_layout, pipeline) = build_shadow_pipeline(device, data, &code)?;
+
+    data.shadow.pipeline = pipeline;
+    data.shadow.pipeline_layout = pipeline_layout;
+    data.shadow.vertex_shader_code = code;
+
+    Ok(())
+}
+
+unsafe fn create_shadow_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    let (pipeline_layout, pipeline) =
+        build_shadow_pipeline(device, data, &data.shadow.vertex_shader_code)?;
+    data.shadow.pipeline = pipeline;
+    data.shadow.pipeline_layout = pipeline_layout;
+
+    Ok(())
+}
+
+// only has a vertex stage, the depth is written by the fixed function stages
+unsafe fn build_shadow_pipeline(
+    device: &Arc<OwnedDevice>,
+    data: &AppData,
+    vertex_shader_code: &[u32],
+) -> Result<(PipelineLayout, Pipeline)> {
+    let module = pipeline::create_shader_module(device, vertex_shader_code)?;
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(*module)
+        .name(b"main\0");
+
+    let binding_descriptions = &[Vertex::binding_description()];
+    let attribute_descriptions = Vertex::attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let extent = vk::Extent2D {
+        width: data.shadow.resolution,
+        height: data.shadow.resolution,
+    };
+    let viewports = &[vk::Viewport::builder()
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)];
+    let scissors = &[vk::Rect2D::builder().extent(extent)];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // the depth bias pushes the stored depth away from the light, so surfaces
+    // don't shadow themselves because of the limited resolution (shadow acne);
+    // both sides are drawn, so open geometry (like the quads) casts shadows as well
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(true)
+        .depth_bias_constant_factor(data.shadow.depth_bias)
+        .depth_bias_slope_factor(data.shadow.slope_bias)
+        .depth_bias_clamp(0.0);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // there are no color attachments
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder();
+
+    // identical to the layout of the main pipeline, so the descriptor sets of
+    // the main pass can be bound
+    let set_layouts = &[*data.descriptor_set_layout];
+    let push_constant_ranges = &[PushConstants::range()];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    let pipeline_layout =
+        PipelineLayout::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    let stages = &[vert_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*pipeline_layout)
+        .render_pass(*data.shadow.render_pass)
+        .subpass(0);
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    Ok((pipeline_layout, Pipeline::new(device, pipeline)))
+}
+
+/// records the shadow pass, which draws the visible nodes of the scene into the
+/// shadow map; has to be recorded before the main render pass
+pub unsafe fn record_shadow_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    let extent = vk::Extent2D {
+        width: data.shadow.resolution,
+        height: data.shadow.resolution,
+    };
+    let render_area = vk::Rect2D::builder().extent(extent);
+
+    // everything, which is not covered by geometry, is at the far plane
+    let clear_values = &[vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    }];
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*data.shadow.render_pass)
+        .framebuffer(*data.shadow.framebuffer)
+        .render_area(render_area)
+        .clear_values(clear_values);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.shadow.pipeline,
+    );
+
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[*data.vertex_buffer], &[0]);
+    device.cmd_bind_index_buffer(command_buffer, *data.index_buffer, 0, vk::IndexType::UINT32);
+
+    // only the uniform buffer of the descriptor set is used, which is the same
+    // for all textures
+    let set = descriptor_set::descriptor_set_index(data, image_index, 0);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.shadow.pipeline_layout,
+        0,
+        &[data.descriptor_sets[set]],
+        &[],
+    );
+
+    for draw in data.scene.draws() {
+        let push_constants = PushConstants {
+            model: draw.transform,
+            base_color: draw.material.base_color_factor,
+        };
+        push_constants.push(device, command_buffer, *data.shadow.pipeline_layout);
+
+        device.cmd_draw_indexed(
+            command_buffer,
+            draw.mesh.index_count,
+            1,
+            draw.mesh.first_index,
+            draw.mesh.vertex_offset,
+            0,
+        );
+    }
+
+    device.cmd_end_render_pass(command_buffer);
+}
+
+/// the view and projection of a directional light, which shines into the given
+/// direction; the orthographic projection covers a sphere with the given radius
+/// around the origin
+pub fn light_view_proj(direction: &glm::Vec3, radius: f32) -> glm::Mat4 {
+    let direction = direction.normalize();
+
+    // the light looks at the origin from outside of the sphere; the up vector
+    // must not be parallel to the direction
+    let eye = -direction * 2.0 * radius;
+    let up = if direction.z.abs() > 0.99 {
+        glm::vec3(1.0, 0.0, 0.0)
+    } else {
+        glm::vec3(0.0, 0.0, 1.0)
+    };
+    let view = glm::look_at_rh(&eye, &glm::Vec3::zeros(), &up);
+
+    // the depth range of vulkan is 0.0 to 1.0 (zo = zero-to-one); unlike the
+    // camera, the y-axis is not flipped, as the shadow map is only sampled with
+    // the same matrix
+    let proj = glm::ortho_rh_zo(-radius, radius, -radius, radius, radius, 3.0 * radius);
+
+    proj * view
+}
+
+// the distance of the farthest vertex of the scene from the origin; the nodes
+// are only rotated around the origin (see App::update_scene), so the sphere
+// contains the scene in every frame
+fn scene_radius(data: &AppData) -> f32 {
+    let radius = data
+        .scene
+        .draws()
+        .flat_map(|draw| {
+            let first = draw.mesh.first_index as usize;
+            let indices = &data.indices[first..first + draw.mesh.index_count as usize];
+            indices.iter().filter_map(move |i| {
+                let vertex = data
+                    .vertices
+                    .get((*i as i64 + draw.mesh.vertex_offset as i64) as usize)?;
+                Some(glm::length(
+                    &(draw.transform * vertex.pos().push(1.0)).xyz(),
+                ))
+            })
+        })
+        .fold(0.0, f32::max);
+
+    // an empty scene would lead to an invalid projection
+    radius.max(0.1)
+}
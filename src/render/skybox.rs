@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Result};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+
+use super::cubemap;
+use super::descriptor_set;
+use super::handle::{DescriptorPool, DescriptorSetLayout, OwnedDevice, Pipeline, PipelineLayout};
+use super::image::{self, Texture};
+use super::pipeline;
+use super::reflection::ShaderReflection;
+use super::shader;
+
+/// a cube map, which is drawn behind the scene
+///
+/// the skybox is drawn at the start of the main render pass without depth test
+/// and depth writes, so the scene covers it. set 0 is the descriptor set of the
+/// main pass (for the camera matrices), set 1 holds the cube map
+#[derive(Debug, Default)]
+pub struct Skybox {
+    // the pipeline is destroyed before its layout and the layout before the
+    // descriptor set layouts
+    pub pipeline: Pipeline,
+    pub pipeline_layout: PipelineLayout,
+    // freed along with the pool
+    pub descriptor_set: vk::DescriptorSet,
+    // only kept alive for the descriptor set
+    #[allow(dead_code)]
+    pub descriptor_pool: DescriptorPool,
+    #[allow(dead_code)]
+    pub descriptor_set_layout: DescriptorSetLayout,
+    #[allow(dead_code)]
+    pub texture: Texture,
+    vertex_shader_code: Vec<u32>,
+    fragment_shader_code: Vec<u32>,
+    // the descriptors of set 1 can't change, when the shaders are reloaded
+    shader_reflection: ShaderReflection,
+}
+
+// the compiled shaders along with their reflection
+type SkyboxShaders = (Vec<u32>, Vec<u32>, ShaderReflection);
+
+// set 0 is shared with the main pass, so it can't contain other descriptors;
+// once the skybox exists, the shaders also have to use the same descriptors in
+// set 1 as before
+fn compile_skybox_shaders(data: &AppData) -> Result<SkyboxShaders> {
+    let vertex_shader_code = shader::compile_shader(Path::new(shader::SKYBOX_VERTEX_SHADER))?;
+    let fragment_shader_code = shader::compile_shader(Path::new(shader::SKYBOX_FRAGMENT_SHADER))?;
+    let reflection = ShaderReflection::new(&[
+        (&vertex_shader_code, vk::ShaderStageFlags::VERTEX),
+        (&fragment_shader_code, vk::ShaderStageFlags::FRAGMENT),
+    ])?;
+
+    if let Some(binding) = reflection
+        .bindings
+        .iter()
+        .filter(|b| b.set == 0)
+        .find(|b| !data.shader_reflection.bindings.contains(b))
+    {
+        return Err(anyhow!(
+            "Skybox shader uses descriptor {:?}, which is not used by the main shaders.",
+            binding
+        ));
+    }
+
+    if let Some(skybox) = &data.skybox {
+        let set_1 = |r: &ShaderReflection| {
+            r.bindings
+                .iter()
+                .filter(|b| b.set == 1)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        if set_1(&reflection) != set_1(&skybox.shader_reflection) {
+            return Err(anyhow!(
+                "Skybox shaders use different descriptors than the descriptor set layout: {:?}",
+                reflection.bindings
+            ));
+        }
+    }
+
+    Ok((vertex_shader_code, fragment_shader_code, reflection))
+}
+
+/// loads the cube map (see cubemap::load_cube_faces) and creates the descriptor
+/// set, which binds it; the descriptor set layout of the main pass has to exist
+/// already. the pipeline is created by create_skybox_pipeline
+pub unsafe fn create_skybox(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Skybox> {
+    let faces = cubemap::load_cube_faces(path)?;
+    let texture = image::create_cube_texture(instance, device, data, faces.pixels, faces.size)?;
+
+    let (vertex_shader_code, fragment_shader_code, reflection) = compile_skybox_shaders(data)?;
+
+    // binding 0: cube map (fragment shader)
+    // binding 1: sampler of the cube map (fragment shader)
+    let bindings = reflection
+        .bindings
+        .iter()
+        .filter(|b| b.set == 1)
+        .map(|b| b.layout_binding())
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let descriptor_set_layout =
+        DescriptorSetLayout::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // a single set, as the cube map is the same for all frames
+    let pool_sizes = bindings
+        .iter()
+        .map(|b| {
+            vk::DescriptorPoolSize::builder()
+                .type_(b.descriptor_type)
+                .descriptor_count(b.descriptor_count)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(1);
+    let descriptor_pool = DescriptorPool::new(device, device.create_descriptor_pool(&info, None)?);
+
+    let layouts = &[*descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*descriptor_pool)
+        .set_layouts(layouts);
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(*texture.view);
+    let image_info = &[info];
+    let texture_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(image_info);
+
+    let info = vk::DescriptorImageInfo::builder().sampler(*texture.sampler);
+    let sampler_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info);
+
+    device.update_descriptor_sets(
+        &[texture_write, sampler_write],
+        &[] as &[vk::CopyDescriptorSet],
+    );
+
+    // set 0 has the layout of the main pass, so its descriptor sets can be bound
+    let set_layouts = &[*data.descriptor_set_layout, *descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    let pipeline_layout =
+        PipelineLayout::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    log::debug!(
+        "Created skybox with faces of size {}x{}",
+        faces.size,
+        faces.size
+    );
+
+    Ok(Skybox {
+        pipeline: Pipeline::default(),
+        pipeline_layout,
+        descriptor_set,
+        descriptor_pool,
+        descriptor_set_layout,
+        texture,
+        vertex_shader_code,
+        fragment_shader_code,
+        shader_reflection: reflection,
+    })
+}
+
+/// whether the skybox pipeline is built from the shader at `path`
+pub fn uses_shader(path: &Path) -> bool {
+    shader::is_any_of(
+        path,
+        &[shader::SKYBOX_VERTEX_SHADER, shader::SKYBOX_FRAGMENT_SHADER],
+    )
+}
+
+/// compiles the skybox shaders again and replaces the skybox pipeline, if there
+/// is a skybox; like pipeline::reload_pipeline, errors are logged and the
+/// current pipeline stays in use
+///
+/// the pipeline must not be in use by the device
+pub unsafe fn reload_skybox_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) {
+    if data.skybox.is_none() {
+        return;
+    }
+
+    match try_reload_skybox_pipeline(device, data) {
+        Ok(()) => log::info!("Reloaded skybox pipeline"),
+        Err(e) => log::error!(
+            "Failed to reload the skybox pipeline, keeping the last working one:\n{}",
+            e
+        ),
+    }
+}
+
+// `data` is only changed, once everything succeeded
+unsafe fn try_reload_skybox_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    let (vertex_shader_code, fragment_shader_code, reflection) = compile_skybox_shaders(data)?;
+    let Some(skybox) = &data.skybox else {
+        return Ok(());
+    };
+    let pipeline = build_skybox_pipeline(
+        device,
+        data,
+        skybox,
+        &vertex_shader_code,
+        &fragment_shader_code,
+    )?;
+
+    if let Some(skybox) = &mut data.skybox {
+        skybox.pipeline = pipeline;
+        skybox.vertex_shader_code = vertex_shader_code;
+        skybox.fragment_shader_code = fragment_shader_code;
+        skybox.shader_reflection = reflection;
+    }
+
+    Ok(())
+}
+
+/// creates the pipeline of the skybox, if there is one; like the main pipeline,
+/// it depends on the render pass and the size of the swapchain
+pub unsafe fn create_skybox_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    let Some(skybox) = &data.skybox else {
+        return Ok(());
+    };
+
+    let pipeline = build_skybox_pipeline(
+        device,
+        data,
+        skybox,
+        &skybox.vertex_shader_code,
+        &skybox.fragment_shader_code,
+    )?;
+
+    if let Some(skybox) = &mut data.skybox {
+        skybox.pipeline = pipeline;
+    }
+
+    Ok(())
+}
+
+unsafe fn build_skybox_pipeline(
+    device: &Arc<OwnedDevice>,
+    data: &AppData,
+    skybox: &Skybox,
+    vertex_shader_code: &[u32],
+    fragment_shader_code: &[u32],
+) -> Result<Pipeline> {
+    let vert_shader_module = pipeline::create_shader_module(device, vertex_shader_code)?;
+    let frag_shader_module = pipeline::create_shader_module(device, fragment_shader_code)?;
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(*vert_shader_module)
+        .name(b"main\0");
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(*frag_shader_module)
+        .name(b"main\0");
+
+    // the corners of the triangle are computed from the vertex index
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewports = &[vk::Viewport::builder()
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)];
+    let scissors = &[vk::Rect2D::builder().extent(data.swapchain_extent)];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // the skybox is drawn first and leaves the depth buffer untouched, so
+    // everything, which is drawn afterwards, is in front of it
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // overwrites the clear color
+    let attachments = &[vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false)];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    let stages = &[vert_stage, frag_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*skybox.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    Ok(Pipeline::new(device, pipeline))
+}
+
+/// records the draw of the skybox, if there is one; has to be recorded at the
+/// start of the main render pass
+pub unsafe fn record_skybox(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    let Some(skybox) = &data.skybox else {
+        return;
+    };
+
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *skybox.pipeline,
+    );
+
+    // only the uniform buffer of the main descriptor set is used, which is the
+    // same for all textures
+    let set = descriptor_set::descriptor_set_index(data, image_index, 0);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *skybox.pipeline_layout,
+        0,
+        &[data.descriptor_sets[set], skybox.descriptor_set],
+        &[],
+    );
+
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+}
@@ -7,11 +7,13 @@ use vulkanalia::prelude::v1_0::*;
 use vulkanalia::vk::KhrSurfaceExtension;
 use vulkanalia::vk::KhrSwapchainExtension;
 
-use crate::app::App;
+use std::sync::Arc;
+
 use crate::app::AppData;
 use crate::render::queue::QueueFamilyIndices;
 use winit::window::Window;
 
+use super::handle::{OwnedDevice, Swapchain};
 use super::image;
 
 #[derive(Clone, Debug)]
@@ -56,13 +58,16 @@ fn get_swapchain_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::Surface
         .unwrap_or_else(|| formats[0]) // use selected or first one, if sRGB is not available
 }
 
-fn get_swapchain_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
-    // check, if Mailbox mode is supported, otherwise select FIFO (guaranteed
-    // to be supported)
+fn get_swapchain_present_mode(
+    present_modes: &[vk::PresentModeKHR],
+    preferred: vk::PresentModeKHR,
+) -> vk::PresentModeKHR {
+    // check, if the preferred mode (Mailbox by default) is supported, otherwise
+    // select FIFO (guaranteed to be supported)
     present_modes
         .iter()
         .cloned()
-        .find(|m| *m == vk::PresentModeKHR::MAILBOX)
+        .find(|m| *m == preferred)
         .unwrap_or(vk::PresentModeKHR::FIFO)
 }
 
@@ -70,7 +75,7 @@ fn get_swapchain_extent(window: &Window, capabilities: vk::SurfaceCapabilitiesKH
     // if current_extent is set to u32::max, then we need to set the extent
     // specifically to the inner_size of the window
 
-    if capabilities.current_extent.width != u32::max_value() {
+    if capabilities.current_extent.width != u32::MAX {
         capabilities.current_extent
     } else {
         let size = window.inner_size();
@@ -98,14 +103,15 @@ fn get_swapchain_extent(window: &Window, capabilities: vk::SurfaceCapabilitiesKH
 pub unsafe fn create_swapchain(
     window: &Window,
     instance: &Instance,
-    device: &Device,
+    device: &Arc<OwnedDevice>,
     data: &mut AppData,
 ) -> Result<()> {
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
     let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
     let surface_format = get_swapchain_surface_format(&support.formats);
-    let present_mode = get_swapchain_present_mode(&support.present_modes);
+    let present_mode = get_swapchain_present_mode(&support.present_modes, data.present_mode);
+    log::debug!("Present mode: {:?}", present_mode);
     let extent = get_swapchain_extent(window, support.capabilities);
 
     // strictly sticking to the minimum image count would mean, that we
@@ -163,7 +169,7 @@ pub unsafe fn create_swapchain(
                                                   // to recreate it and pass the old one, but we
                                                   // don't do that here
 
-    data.swapchain = device.create_swapchain_khr(&info, None)?;
+    data.swapchain = Swapchain::new(device, device.create_swapchain_khr(&info, None)?);
     info!("Created swapchain");
     log::debug!(
         "Swapchain extent: w: {}, h: {}",
@@ -171,7 +177,7 @@ pub unsafe fn create_swapchain(
         extent.height
     );
 
-    data.swapchain_images = device.get_swapchain_images_khr(data.swapchain)?;
+    data.swapchain_images = device.get_swapchain_images_khr(*data.swapchain)?;
     log::debug!("Created {} swapchain images", data.swapchain_images.len());
 
     data.swapchain_format = surface_format.format;
@@ -180,7 +186,10 @@ pub unsafe fn create_swapchain(
     Ok(())
 }
 
-pub unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData) -> Result<()> {
+pub unsafe fn create_swapchain_image_views(
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
     // iterate over swapchain images
     data.swapchain_image_views = data
         .swapchain_images
@@ -200,6 +209,9 @@ pub unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData)
                 data.swapchain_format,
                 components.build(),
                 vk::ImageAspectFlags::COLOR,
+                1,
+                vk::ImageViewType::_2D,
+                1,
             )
         })
         .collect::<Result<Vec<_>, _>>()?;
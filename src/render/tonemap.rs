@@ -0,0 +1,525 @@
+use anyhow::{anyhow, Result};
+
+use std::mem::size_of;
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+
+use vulkanalia::prelude::v1_0::*;
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+use crate::app::AppData;
+use crate::config::{ToneMapOperator, ToneMappingConfig};
+
+use super::handle::{
+    DescriptorPool, DescriptorSetLayout, Framebuffer, OwnedDevice, Pipeline, PipelineLayout,
+    RenderPass, Sampler,
+};
+use super::image::{self, SamplerOptions};
+use super::pipeline;
+use super::reflection::ShaderReflection;
+use super::shader;
+
+// format of the image, which the scene is rendered into; colors may exceed 1.0,
+// until they are tone mapped. the format is required to support color
+// attachments and sampling, so there is no need to check for support
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// the push constants of the tone mapping shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ToneMapConstants {
+    exposure: f32,
+    operator: u32,
+}
+
+/// maps the HDR image of the scene into the swapchain image
+///
+/// the tone mapping pass runs after the main render pass and draws a single
+/// triangle, which covers the whole swapchain image. the HDR image is shared by
+/// all swapchain images, like the depth image
+#[derive(Debug, Default)]
+pub struct ToneMapper {
+    pub operator: ToneMapOperator,
+    pub exposure: f32,
+    // one framebuffer per swapchain image; destroyed before the render pass
+    pub framebuffers: Vec<Framebuffer>,
+    pub pipeline: Pipeline,
+    pub render_pass: RenderPass,
+    pub pipeline_layout: PipelineLayout,
+    // freed along with the pool
+    pub descriptor_set: vk::DescriptorSet,
+    // only kept alive for the descriptor set
+    #[allow(dead_code)]
+    pub descriptor_pool: DescriptorPool,
+    pub descriptor_set_layout: DescriptorSetLayout,
+    pub sampler: Sampler,
+    vertex_shader_code: Vec<u32>,
+    fragment_shader_code: Vec<u32>,
+    // the descriptors can't change, when the shaders are reloaded
+    shader_reflection: ShaderReflection,
+}
+
+impl ToneMapper {
+    /// page up and page down change the exposure, T switches to the next operator
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    virtual_keycode: Some(key),
+                    state: ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return;
+        };
+
+        match key {
+            VirtualKeyCode::PageUp => {
+                self.exposure *= 1.25;
+                log::info!("Exposure: {}", self.exposure);
+            }
+            VirtualKeyCode::PageDown => {
+                self.exposure /= 1.25;
+                log::info!("Exposure: {}", self.exposure);
+            }
+            VirtualKeyCode::T => {
+                self.operator = match self.operator {
+                    ToneMapOperator::Clamp => ToneMapOperator::Reinhard,
+                    ToneMapOperator::Reinhard => ToneMapOperator::Aces,
+                    ToneMapOperator::Aces => ToneMapOperator::Clamp,
+                };
+                log::info!("Tone mapping operator: {:?}", self.operator);
+            }
+            _ => {}
+        }
+    }
+}
+
+// the value of the operator in the shader (see tonemap.frag)
+fn operator_index(operator: ToneMapOperator) -> u32 {
+    match operator {
+        ToneMapOperator::Clamp => 0,
+        ToneMapOperator::Reinhard => 1,
+        ToneMapOperator::Aces => 2,
+    }
+}
+
+fn push_constant_range() -> vk::PushConstantRange {
+    vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<ToneMapConstants>() as u32)
+        .build()
+}
+
+// the compiled shaders along with their reflection
+type ToneMapShaders = (Vec<u32>, Vec<u32>, ShaderReflection);
+
+// once the descriptor set layout exists, the shaders have to use the same
+// descriptors as before
+fn compile_tone_map_shaders(data: &AppData) -> Result<ToneMapShaders> {
+    let vertex_shader_code = shader::compile_shader(Path::new(shader::TONEMAP_VERTEX_SHADER))?;
+    let fragment_shader_code = shader::compile_shader(Path::new(shader::TONEMAP_FRAGMENT_SHADER))?;
+    let reflection = ShaderReflection::new(&[
+        (&vertex_shader_code, vk::ShaderStageFlags::VERTEX),
+        (&fragment_shader_code, vk::ShaderStageFlags::FRAGMENT),
+    ])?;
+
+    if reflection.push_constants_size > push_constant_range().size {
+        return Err(anyhow!(
+            "Tone mapping shader uses {} bytes of push constants, but only {} are pushed.",
+            reflection.push_constants_size,
+            push_constant_range().size
+        ));
+    }
+
+    if !data.tone_mapper.descriptor_set_layout.is_null()
+        && reflection.bindings != data.tone_mapper.shader_reflection.bindings
+    {
+        return Err(anyhow!(
+            "Tone mapping shaders use different descriptors than the descriptor set layout: {:?}",
+            reflection.bindings
+        ));
+    }
+
+    Ok((vertex_shader_code, fragment_shader_code, reflection))
+}
+
+/// compiles the tone mapping shaders and creates the objects, which don't depend
+/// on the swapchain; the rest is created by create_tone_map_pass
+pub unsafe fn create_tone_mapper(
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+    config: &ToneMappingConfig,
+) -> Result<()> {
+    let (vertex_shader_code, fragment_shader_code, reflection) = compile_tone_map_shaders(data)?;
+
+    // binding 0: HDR image (fragment shader)
+    // binding 1: sampler of the HDR image (fragment shader)
+    let bindings = reflection
+        .bindings
+        .iter()
+        .map(|b| b.layout_binding())
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let descriptor_set_layout =
+        DescriptorSetLayout::new(device, device.create_descriptor_set_layout(&info, None)?);
+
+    // a single set, as there is only one HDR image; it is updated, whenever the
+    // image is created again (see create_tone_map_pass)
+    let pool_sizes = bindings
+        .iter()
+        .map(|b| {
+            vk::DescriptorPoolSize::builder()
+                .type_(b.descriptor_type)
+                .descriptor_count(b.descriptor_count)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(1);
+    let descriptor_pool = DescriptorPool::new(device, device.create_descriptor_pool(&info, None)?);
+
+    let layouts = &[*descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*descriptor_pool)
+        .set_layouts(layouts);
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    // the HDR image has the size of the swapchain image, so every texel is read
+    // exactly once
+    let options = SamplerOptions {
+        mag_filter: vk::Filter::NEAREST,
+        min_filter: vk::Filter::NEAREST,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+    };
+    let sampler = image::create_sampler(device, &options, 1)?;
+
+    let set_layouts = &[*descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range()];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    let pipeline_layout =
+        PipelineLayout::new(device, device.create_pipeline_layout(&layout_info, None)?);
+
+    data.tone_mapper = ToneMapper {
+        operator: config.operator,
+        exposure: config.exposure,
+        pipeline_layout,
+        descriptor_set,
+        descriptor_pool,
+        descriptor_set_layout,
+        sampler,
+        vertex_shader_code,
+        fragment_shader_code,
+        shader_reflection: reflection,
+        ..Default::default()
+    };
+
+    Ok(())
+}
+
+/// whether the tone mapping pipeline is built from the shader at `path`
+pub fn uses_shader(path: &Path) -> bool {
+    shader::is_any_of(
+        path,
+        &[
+            shader::TONEMAP_VERTEX_SHADER,
+            shader::TONEMAP_FRAGMENT_SHADER,
+        ],
+    )
+}
+
+/// compiles the tone mapping shaders again and replaces the tone mapping
+/// pipeline; like pipeline::reload_pipeline, errors are logged and the current
+/// pipeline stays in use
+///
+/// the pipeline must not be in use by the device
+pub unsafe fn reload_tone_map_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) {
+    match try_reload_tone_map_pipeline(device, data) {
+        Ok(()) => log::info!("Reloaded tone mapping pipeline"),
+        Err(e) => log::error!(
+            "Failed to reload the tone mapping pipeline, keeping the last working one:\n{}",
+            e
+        ),
+    }
+}
+
+// `data` is only changed, once everything succeeded
+unsafe fn try_reload_tone_map_pipeline(
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
+    let (vertex_shader_code, fragment_shader_code, reflection) = compile_tone_map_shaders(data)?;
+    let pipeline =
+        build_tone_map_pipeline(device, data, &vertex_shader_code, &fragment_shader_code)?;
+
+    let tone_mapper = &mut data.tone_mapper;
+    tone_mapper.pipeline = pipeline;
+    tone_mapper.vertex_shader_code = vertex_shader_code;
+    tone_mapper.fragment_shader_code = fragment_shader_code;
+    tone_mapper.shader_reflection = reflection;
+
+    Ok(())
+}
+
+/// creates the render pass, the framebuffers and the pipeline of the tone
+/// mapping pass and binds the HDR image; like the main pass, these depend on
+/// the swapchain, so they are created again along with it
+pub unsafe fn create_tone_map_pass(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    log::debug!("Creating tone mapping pass");
+
+    data.tone_mapper.render_pass = create_tone_map_render_pass(device, data)?;
+
+    data.tone_mapper.framebuffers = data
+        .swapchain_image_views
+        .iter()
+        .map(|i| {
+            let attachments = &[**i];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(*data.tone_mapper.render_pass)
+                .attachments(attachments)
+                .width(data.swapchain_extent.width)
+                .height(data.swapchain_extent.height)
+                .layers(1);
+            Ok(Framebuffer::new(
+                device,
+                device.create_framebuffer(&info, None)?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    create_tone_map_pipeline(device, data)?;
+
+    // the main render pass leaves the HDR image in this layout
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(*data.hdr_image_view);
+    let image_info = &[info];
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tone_mapper.descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(image_info);
+
+    let info = vk::DescriptorImageInfo::builder().sampler(*data.tone_mapper.sampler);
+    let sampler_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tone_mapper.descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info);
+
+    device.update_descriptor_sets(
+        &[image_write, sampler_write],
+        &[] as &[vk::CopyDescriptorSet],
+    );
+
+    Ok(())
+}
+
+// a single color attachment: the swapchain image
+unsafe fn create_tone_map_render_pass(
+    device: &Arc<OwnedDevice>,
+    data: &AppData,
+) -> Result<RenderPass> {
+    // present image to swapchain next; in headless mode, the image is copied
+    // into a host visible buffer instead
+    let present_layout = if data.headless {
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+    } else {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    };
+
+    // every pixel is overwritten, so the previous contents don't matter
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(data.swapchain_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(present_layout);
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    let color_attachments = &[color_attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    // the swapchain image may only be written, after it was acquired (the
+    // semaphore is waited on in the color attachment output stage); the HDR
+    // image is made available by the main render pass
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+    let attachments = &[color_attachment];
+    let subpasses = &[subpass];
+    let dependencies = &[dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    Ok(RenderPass::new(
+        device,
+        device.create_render_pass(&info, None)?,
+    ))
+}
+
+unsafe fn create_tone_map_pipeline(device: &Arc<OwnedDevice>, data: &mut AppData) -> Result<()> {
+    let pipeline = build_tone_map_pipeline(
+        device,
+        data,
+        &data.tone_mapper.vertex_shader_code,
+        &data.tone_mapper.fragment_shader_code,
+    )?;
+    data.tone_mapper.pipeline = pipeline;
+
+    Ok(())
+}
+
+unsafe fn build_tone_map_pipeline(
+    device: &Arc<OwnedDevice>,
+    data: &AppData,
+    vertex_shader_code: &[u32],
+    fragment_shader_code: &[u32],
+) -> Result<Pipeline> {
+    let tone_mapper = &data.tone_mapper;
+
+    let vert_shader_module = pipeline::create_shader_module(device, vertex_shader_code)?;
+    let frag_shader_module = pipeline::create_shader_module(device, fragment_shader_code)?;
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(*vert_shader_module)
+        .name(b"main\0");
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(*frag_shader_module)
+        .name(b"main\0");
+
+    // the corners of the triangle are computed from the vertex index
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewports = &[vk::Viewport::builder()
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)];
+    let scissors = &[vk::Rect2D::builder().extent(data.swapchain_extent)];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // the HDR image is already resolved
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let attachments = &[vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false)];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(attachments);
+
+    let stages = &[vert_stage, frag_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(*tone_mapper.pipeline_layout)
+        .render_pass(*tone_mapper.render_pass)
+        .subpass(0);
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0;
+
+    Ok(Pipeline::new(device, pipeline))
+}
+
+/// records the tone mapping pass into the swapchain image; has to be recorded
+/// after the main render pass
+pub unsafe fn record_tone_map_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    let tone_mapper = &data.tone_mapper;
+
+    let render_area = vk::Rect2D::builder().extent(data.swapchain_extent);
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*tone_mapper.render_pass)
+        .framebuffer(*tone_mapper.framebuffers[image_index])
+        .render_area(render_area);
+
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *tone_mapper.pipeline,
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *tone_mapper.pipeline_layout,
+        0,
+        &[tone_mapper.descriptor_set],
+        &[],
+    );
+
+    let constants = ToneMapConstants {
+        exposure: tone_mapper.exposure,
+        operator: operator_index(tone_mapper.operator),
+    };
+    let bytes = slice::from_raw_parts(
+        (&constants as *const ToneMapConstants).cast::<u8>(),
+        size_of::<ToneMapConstants>(),
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        *tone_mapper.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        0,
+        bytes,
+    );
+
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    device.cmd_end_render_pass(command_buffer);
+}
@@ -0,0 +1,454 @@
+use anyhow::Result;
+
+use std::sync::Arc;
+
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrMaintenance1Extension;
+
+use crate::app::AppData;
+
+use super::handle::{Buffer, CommandPool, Fence, OwnedDevice, Semaphore};
+use super::queue::QueueFamilyIndices;
+
+// uploads are recorded into two command buffers: the copies are executed on the
+// transfer queue, and everything, which requires the graphics queue (e.g. the
+// blits, which generate the mip levels), on the graphics queue afterwards
+//
+// resources are created with vk::SharingMode::EXCLUSIVE, so if the transfer
+// queue belongs to another queue family, the ownership of each resource needs to
+// be transferred to the graphics queue family: the transfer queue releases it
+// with a barrier, and the graphics queue acquires it with a matching barrier,
+// after waiting for a semaphore, which is signaled by the transfer submission
+
+/// the command buffers of the batch, which is currently recorded
+#[derive(Copy, Clone, Debug)]
+pub struct UploadCommands {
+    // submitted to the transfer queue
+    pub transfer: vk::CommandBuffer,
+    // submitted to the graphics queue, after the transfer commands completed
+    pub graphics: vk::CommandBuffer,
+}
+
+/// the usage of an uploaded resource on the graphics queue, which has to wait
+/// for the upload
+#[derive(Copy, Clone, Debug)]
+pub struct Destination {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+// a submitted batch; the command buffers and staging buffers are released, when
+// its fence is signaled
+#[derive(Debug)]
+struct PendingUpload {
+    commands: UploadCommands,
+    // signaled, when the graphics commands completed
+    fence: Fence,
+    // signaled by the transfer queue and waited for by the graphics queue; only
+    // created, if the queue families differ
+    _semaphore: Semaphore,
+    _staging_buffers: Vec<Buffer>,
+}
+
+/// records uploads of buffers and images into batches, which are executed on
+/// the transfer queue without blocking the cpu
+///
+/// rendering does not need to wait for uploads explicitly: the graphics
+/// commands of a batch are submitted before any later frame, and the barriers
+/// in them order the following reads after the uploads
+#[derive(Debug, Default)]
+pub struct UploadManager {
+    device: Option<Arc<OwnedDevice>>,
+    transfer_pool: CommandPool,
+    graphics_pool: CommandPool,
+    transfer_queue: vk::Queue,
+    graphics_queue: vk::Queue,
+    transfer_family: u32,
+    graphics_family: u32,
+    // the batch, which is currently recorded, and the staging buffers it reads from
+    current: Option<UploadCommands>,
+    staging_buffers: Vec<Buffer>,
+    pending: Vec<PendingUpload>,
+    // set, if VK_KHR_maintenance1 is enabled; the pools are trimmed, whenever
+    // all batches are completed
+    trim_pools: bool,
+}
+
+impl UploadManager {
+    /// whether ownership of the uploaded resources needs to be transferred
+    /// between the queue families
+    pub fn separate_queues(&self) -> bool {
+        self.transfer_family != self.graphics_family
+    }
+
+    /// returns the command buffers of the current batch; a new batch is begun,
+    /// if there is none
+    pub unsafe fn commands(&mut self) -> Result<UploadCommands> {
+        if let Some(commands) = self.current {
+            return Ok(commands);
+        }
+
+        let device = self.device();
+        let transfer = begin_command_buffer(device, *self.transfer_pool)?;
+        let graphics = begin_command_buffer(device, *self.graphics_pool)?;
+
+        let commands = UploadCommands { transfer, graphics };
+        self.current = Some(commands);
+        Ok(commands)
+    }
+
+    /// keeps the staging buffer alive, until the current batch is completed
+    pub fn keep_alive(&mut self, staging_buffer: Buffer) {
+        self.staging_buffers.push(staging_buffer);
+    }
+
+    /// copies the staging buffer into the destination buffer on the transfer queue
+    /// and hands the destination buffer over to the graphics queue
+    pub unsafe fn upload_buffer(
+        &mut self,
+        staging_buffer: Buffer,
+        destination: vk::Buffer,
+        size: vk::DeviceSize,
+        usage: Destination,
+    ) -> Result<()> {
+        let commands = self.commands()?;
+
+        let region = vk::BufferCopy::builder().size(size);
+        self.device()
+            .cmd_copy_buffer(commands.transfer, *staging_buffer, destination, &[region]);
+
+        self.keep_alive(staging_buffer);
+
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .buffer(destination)
+            .offset(0)
+            .size(size)
+            .build();
+
+        self.hand_over(commands, usage, Barrier::Buffer(barrier));
+
+        Ok(())
+    }
+
+    /// hands an image, which was written by the transfer commands, over to the
+    /// graphics queue; the layout is changed from `old_layout` to `new_layout`
+    /// along the way
+    pub unsafe fn hand_over_image(
+        &mut self,
+        image: vk::Image,
+        subresource: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        usage: Destination,
+    ) -> Result<()> {
+        let commands = self.commands()?;
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .subresource_range(subresource)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .build();
+
+        self.hand_over(commands, usage, Barrier::Image(barrier));
+
+        Ok(())
+    }
+
+    // records the barriers, which make the transfer writes visible to the usage
+    // on the graphics queue; the queue families and access masks of the barrier
+    // are filled in here
+    unsafe fn hand_over(&self, commands: UploadCommands, usage: Destination, barrier: Barrier) {
+        let device = self.device();
+
+        if !self.separate_queues() {
+            // a single barrier on the graphics queue suffices, which waits for
+            // the transfer commands submitted before
+            barrier
+                .with(
+                    (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+                    (vk::AccessFlags::TRANSFER_WRITE, usage.access),
+                )
+                .record(
+                    device,
+                    commands.graphics,
+                    vk::PipelineStageFlags::TRANSFER,
+                    usage.stage,
+                );
+            return;
+        }
+
+        // the release barrier only makes the writes available; the destination
+        // stage and access are ignored, so the transfer queue does not need to
+        // support them
+        let families = (self.transfer_family, self.graphics_family);
+        barrier
+            .with(
+                families,
+                (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty()),
+            )
+            .record(
+                device,
+                commands.transfer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            );
+
+        // the acquire barrier makes the writes visible to the usage; the
+        // semaphore wait already orders it after the release, so the source
+        // stage is the same as the destination stage
+        barrier
+            .with(families, (vk::AccessFlags::empty(), usage.access))
+            .record(device, commands.graphics, usage.stage, usage.stage);
+    }
+
+    /// submits the current batch; returns immediately, the batch is released by
+    /// `poll` (or `wait`), after it completed
+    pub unsafe fn flush(&mut self) -> Result<()> {
+        let Some(commands) = self.current.take() else {
+            return Ok(());
+        };
+
+        let device = self.device().clone();
+        device.end_command_buffer(commands.transfer)?;
+        device.end_command_buffer(commands.graphics)?;
+
+        // a null handle, if the queue families are the same
+        let mut semaphore = Semaphore::default();
+        let fence = Fence::new(
+            &device,
+            device.create_fence(&vk::FenceCreateInfo::builder(), None)?,
+        );
+
+        if self.separate_queues() {
+            semaphore = Semaphore::new(
+                &device,
+                device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)?,
+            );
+
+            let transfer_buffers = &[commands.transfer];
+            let signal_semaphores = &[*semaphore];
+            let transfer_info = vk::SubmitInfo::builder()
+                .command_buffers(transfer_buffers)
+                .signal_semaphores(signal_semaphores);
+            device.queue_submit(self.transfer_queue, &[transfer_info], vk::Fence::null())?;
+
+            let graphics_buffers = &[commands.graphics];
+            let wait_semaphores = &[*semaphore];
+            let wait_stages = &[vk::PipelineStageFlags::ALL_COMMANDS];
+            let graphics_info = vk::SubmitInfo::builder()
+                .command_buffers(graphics_buffers)
+                .wait_semaphores(wait_semaphores)
+                .wait_dst_stage_mask(wait_stages);
+            device.queue_submit(self.graphics_queue, &[graphics_info], *fence)?;
+        } else {
+            // both command buffers are executed in order by the same queue
+            let command_buffers = &[commands.transfer, commands.graphics];
+            let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+            device.queue_submit(self.graphics_queue, &[info], *fence)?;
+        }
+
+        log::debug!(
+            "Submitted upload batch with {} staging buffers",
+            self.staging_buffers.len()
+        );
+
+        self.pending.push(PendingUpload {
+            commands,
+            fence,
+            _semaphore: semaphore,
+            _staging_buffers: std::mem::take(&mut self.staging_buffers),
+        });
+
+        Ok(())
+    }
+
+    /// releases the batches, which are completed; called once per frame
+    pub unsafe fn poll(&mut self) -> Result<()> {
+        let device = self.device().clone();
+
+        let mut completed = Vec::new();
+        for (index, upload) in self.pending.iter().enumerate() {
+            if device.get_fence_status(*upload.fence)? == vk::SuccessCode::SUCCESS {
+                completed.push(index);
+            }
+        }
+
+        if completed.is_empty() {
+            return Ok(());
+        }
+
+        for index in completed.into_iter().rev() {
+            let upload = self.pending.remove(index);
+            self.release(upload);
+        }
+        self.trim_pools();
+
+        Ok(())
+    }
+
+    /// blocks, until all submitted batches are completed, and releases them
+    pub unsafe fn wait(&mut self) -> Result<()> {
+        let device = self.device().clone();
+        for upload in std::mem::take(&mut self.pending) {
+            device.wait_for_fences(&[*upload.fence], true, u64::MAX)?;
+            self.release(upload);
+        }
+        self.trim_pools();
+        Ok(())
+    }
+
+    // the pools keep the memory of freed command buffers for later allocations;
+    // most uploads happen, while the scene is loaded, so the memory is returned
+    // to the system, once there is nothing left to upload
+    unsafe fn trim_pools(&self) {
+        if !self.trim_pools || !self.pending.is_empty() || self.current.is_some() {
+            return;
+        }
+
+        let device = self.device();
+        for pool in [&self.transfer_pool, &self.graphics_pool] {
+            device.trim_command_pool_khr(**pool, vk::CommandPoolTrimFlags::empty());
+        }
+        log::trace!("Trimmed upload command pools");
+    }
+
+    // the fence of the upload needs to be signaled
+    unsafe fn release(&self, upload: PendingUpload) {
+        let device = self.device();
+        device.free_command_buffers(*self.transfer_pool, &[upload.commands.transfer]);
+        device.free_command_buffers(*self.graphics_pool, &[upload.commands.graphics]);
+        // the fence, the semaphore and the staging buffers are dropped here
+    }
+
+    fn device(&self) -> &Arc<OwnedDevice> {
+        self.device
+            .as_ref()
+            .expect("upload manager was not created")
+    }
+}
+
+impl Drop for UploadManager {
+    fn drop(&mut self) {
+        if self.device.is_none() {
+            return;
+        }
+
+        // the staging buffers may not be destroyed, while they are still read from
+        if let Err(e) = unsafe { self.wait() } {
+            log::error!("Failed to wait for uploads: {}", e);
+        }
+    }
+}
+
+// a barrier, which is recorded into the transfer or the graphics command buffer
+#[derive(Copy, Clone)]
+enum Barrier {
+    Buffer(vk::BufferMemoryBarrier),
+    Image(vk::ImageMemoryBarrier),
+}
+
+impl Barrier {
+    // returns a copy with the given (source, destination) queue families and
+    // access masks
+    fn with(mut self, families: (u32, u32), access: (vk::AccessFlags, vk::AccessFlags)) -> Self {
+        match &mut self {
+            Self::Buffer(b) => {
+                (b.src_queue_family_index, b.dst_queue_family_index) = families;
+                (b.src_access_mask, b.dst_access_mask) = access;
+            }
+            Self::Image(i) => {
+                (i.src_queue_family_index, i.dst_queue_family_index) = families;
+                (i.src_access_mask, i.dst_access_mask) = access;
+            }
+        }
+        self
+    }
+
+    unsafe fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) {
+        let (buffer_barriers, image_barriers) = match self {
+            Self::Buffer(b) => (std::slice::from_ref(b), &[] as &[vk::ImageMemoryBarrier]),
+            Self::Image(i) => (&[] as &[vk::BufferMemoryBarrier], std::slice::from_ref(i)),
+        };
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            buffer_barriers,
+            image_barriers,
+        );
+    }
+}
+
+unsafe fn begin_command_buffer(
+    device: &Device,
+    pool: vk::CommandPool,
+) -> Result<vk::CommandBuffer> {
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    Ok(command_buffer)
+}
+
+/// creates the upload manager along with a command pool for the transfer queue
+/// and one for the graphics side of the uploads
+pub unsafe fn create_upload_manager(
+    instance: &Instance,
+    device: &Arc<OwnedDevice>,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let create_pool = |family| -> Result<CommandPool> {
+        // the command buffers are freed, as soon as their batch is completed
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(family);
+        Ok(CommandPool::new(
+            device,
+            device.create_command_pool(&info, None)?,
+        ))
+    };
+
+    if indices.transfer != indices.graphics {
+        log::info!(
+            "Uploading on dedicated transfer queue family {}",
+            indices.transfer
+        );
+    }
+
+    data.uploads = UploadManager {
+        device: Some(device.clone()),
+        transfer_pool: create_pool(indices.transfer)?,
+        graphics_pool: create_pool(indices.graphics)?,
+        transfer_queue: data.transfer_queue,
+        graphics_queue: data.graphics_queue,
+        transfer_family: indices.transfer,
+        graphics_family: indices.graphics,
+        current: None,
+        staging_buffers: Vec::new(),
+        pending: Vec::new(),
+        trim_pools: data
+            .enabled_extensions
+            .contains(&vk::KHR_MAINTENANCE1_EXTENSION.name),
+    };
+
+    Ok(())
+}
@@ -7,8 +7,6 @@ use std::ffi::CStr;
 use std::os::raw::c_void;
 use vulkanalia::vk;
 
-pub const ENABLED: bool = cfg!(debug_assertions);
-
 pub const LAYER: vk::ExtensionName = vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
 
 /// register a callback function, which will be called from the vulkan library,
@@ -0,0 +1,135 @@
+use vulkanalia::prelude::v1_0::*;
+
+pub use vertex_layout_derive::VertexLayout;
+
+/// describes, how the vertex data of a buffer is passed to the vertex shader
+///
+/// this should not be implemented by hand, but derived with
+/// `#[derive(VertexLayout)]`, which takes the offsets and formats from the
+/// struct definition
+pub trait VertexLayout {
+    // needed to tell vulkan, how to pass vertex data to the shader
+    // Vertex binding describes, at which rate to laod data from memory
+    // specifies number of bytes between data entries whether to move to next entry
+    // after each vertex or instance
+    fn binding_description() -> vk::VertexInputBindingDescription;
+
+    // used to specify how to handle vertex input; one description per location
+    fn attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+    use std::mem::{offset_of, size_of};
+
+    #[repr(C)]
+    #[derive(VertexLayout)]
+    struct PositionVertex {
+        pos: glm::Vec3,
+    }
+
+    #[repr(C)]
+    #[derive(VertexLayout)]
+    struct SkinnedVertex {
+        pos: glm::Vec3,
+        normal: glm::Vec3,
+        joints: [u32; 4],
+        weights: glm::Vec4,
+    }
+
+    // per instance data in a second vertex buffer, after the locations of the
+    // skinned vertex
+    #[repr(C)]
+    #[derive(VertexLayout)]
+    #[vertex_layout(binding = 1, rate = instance, first_location = 4)]
+    struct Instance {
+        model: glm::Mat4,
+        #[vertex_layout(format = R8G8B8A8_UNORM)]
+        tint: [u8; 4],
+        #[vertex_layout(skip)]
+        _id: u64,
+        scale: f32,
+    }
+
+    fn attribute(
+        location: u32,
+        binding: u32,
+        format: vk::Format,
+        offset: usize,
+    ) -> vk::VertexInputAttributeDescription {
+        vk::VertexInputAttributeDescription {
+            location,
+            binding,
+            format,
+            offset: offset as u32,
+        }
+    }
+
+    #[test]
+    fn position_only_vertex() {
+        let binding = PositionVertex::binding_description();
+        assert_eq!(binding.binding, 0);
+        assert_eq!(binding.stride, 12);
+        assert_eq!(binding.input_rate, vk::VertexInputRate::VERTEX);
+
+        assert_eq!(
+            PositionVertex::attribute_descriptions(),
+            [attribute(0, 0, vk::Format::R32G32B32_SFLOAT, 0)]
+        );
+    }
+
+    #[test]
+    fn skinned_vertex_uses_one_location_per_field() {
+        let binding = SkinnedVertex::binding_description();
+        assert_eq!(binding.stride as usize, size_of::<SkinnedVertex>());
+
+        assert_eq!(
+            SkinnedVertex::attribute_descriptions(),
+            [
+                attribute(0, 0, vk::Format::R32G32B32_SFLOAT, 0),
+                attribute(
+                    1,
+                    0,
+                    vk::Format::R32G32B32_SFLOAT,
+                    offset_of!(SkinnedVertex, normal)
+                ),
+                attribute(
+                    2,
+                    0,
+                    vk::Format::R32G32B32A32_UINT,
+                    offset_of!(SkinnedVertex, joints)
+                ),
+                attribute(
+                    3,
+                    0,
+                    vk::Format::R32G32B32A32_SFLOAT,
+                    offset_of!(SkinnedVertex, weights)
+                ),
+            ]
+        );
+    }
+
+    // the matrix takes one location per column, the skipped field none
+    #[test]
+    fn instance_data_starts_at_first_location() {
+        let binding = Instance::binding_description();
+        assert_eq!(binding.binding, 1);
+        assert_eq!(binding.stride as usize, size_of::<Instance>());
+        assert_eq!(binding.input_rate, vk::VertexInputRate::INSTANCE);
+
+        let model = offset_of!(Instance, model);
+        assert_eq!(
+            Instance::attribute_descriptions(),
+            [
+                attribute(4, 1, vk::Format::R32G32B32A32_SFLOAT, model),
+                attribute(5, 1, vk::Format::R32G32B32A32_SFLOAT, model + 16),
+                attribute(6, 1, vk::Format::R32G32B32A32_SFLOAT, model + 32),
+                attribute(7, 1, vk::Format::R32G32B32A32_SFLOAT, model + 48),
+                attribute(8, 1, vk::Format::R8G8B8A8_UNORM, offset_of!(Instance, tint)),
+                attribute(9, 1, vk::Format::R32_SFLOAT, offset_of!(Instance, scale)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use nalgebra_glm as glm;
+
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+/// the number of lights, which fit into the lights uniform block of the
+/// fragment shader; additional lights are ignored
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// infinitely far away (like the sun), so all rays are parallel
+    Directional { direction: glm::Vec3 },
+    /// emits in all directions; the intensity falls off to 0 at `range`
+    Point { position: glm::Vec3, range: f32 },
+    /// a point light, which only emits into a cone around `direction`; the
+    /// intensity falls off between the inner and the outer angle (in radians)
+    Spot {
+        position: glm::Vec3,
+        direction: glm::Vec3,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    pub enabled: bool,
+}
+
+impl Light {
+    pub fn new(kind: LightKind, color: glm::Vec3, intensity: f32) -> Self {
+        Self {
+            kind,
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+}
+
+/// the lights of the scene and the material parameters of the Blinn-Phong
+/// model; uploaded to the shaders every frame, so they can be changed at any time
+#[derive(Clone, Debug)]
+pub struct Lighting {
+    // added to every fragment, regardless of the lights
+    pub ambient: glm::Vec3,
+    // exponent of the specular highlight; higher values give smaller highlights
+    pub shininess: f32,
+    // weight of the specular term (the specular color is the light color)
+    pub specular_strength: f32,
+    pub lights: Vec<Light>,
+}
+
+impl Default for Lighting {
+    /// a sun from above, a reddish point light beside the model and a spot
+    /// light pointing down at it (the scene uses the z-axis as up direction)
+    fn default() -> Self {
+        Self {
+            ambient: glm::vec3(0.1, 0.1, 0.1),
+            shininess: 32.0,
+            specular_strength: 0.5,
+            lights: vec![
+                Light::new(
+                    LightKind::Directional {
+                        direction: glm::vec3(-1.0, -0.5, -2.0),
+                    },
+                    glm::vec3(1.0, 0.95, 0.9),
+                    0.8,
+                ),
+                Light::new(
+                    LightKind::Point {
+                        position: glm::vec3(1.5, 0.0, 1.0),
+                        range: 5.0,
+                    },
+                    glm::vec3(1.0, 0.4, 0.3),
+                    2.0,
+                ),
+                Light::new(
+                    LightKind::Spot {
+                        position: glm::vec3(0.0, 0.0, 2.0),
+                        direction: glm::vec3(0.0, 0.0, -1.0),
+                        range: 6.0,
+                        inner_angle: 15.0_f32.to_radians(),
+                        outer_angle: 25.0_f32.to_radians(),
+                    },
+                    glm::vec3(0.6, 0.8, 1.0),
+                    4.0,
+                ),
+            ],
+        }
+    }
+}
+
+impl Lighting {
+    /// the enabled lights, as many as fit into the uniform block
+    pub fn active_lights(&self) -> impl Iterator<Item = &Light> {
+        self.lights.iter().filter(|l| l.enabled).take(MAX_LIGHTS)
+    }
+
+    /// the first active directional light casts shadows; returns its index among
+    /// the active lights and its direction
+    pub fn shadow_caster(&self) -> Option<(usize, glm::Vec3)> {
+        self.active_lights()
+            .enumerate()
+            .find_map(|(i, light)| match light.kind {
+                LightKind::Directional { direction } => Some((i, direction)),
+                _ => None,
+            })
+    }
+
+    /// the number keys toggle the lights (1 is the first light), plus and minus
+    /// change the ambient light; should be called for every event of the window
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    virtual_keycode: Some(key),
+                    state: ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return;
+        };
+
+        let number_keys = [
+            VirtualKeyCode::Key1,
+            VirtualKeyCode::Key2,
+            VirtualKeyCode::Key3,
+            VirtualKeyCode::Key4,
+            VirtualKeyCode::Key5,
+            VirtualKeyCode::Key6,
+            VirtualKeyCode::Key7,
+            VirtualKeyCode::Key8,
+        ];
+
+        match key {
+            VirtualKeyCode::Plus | VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd => {
+                self.ambient = (self.ambient * 1.25).map(|c| c.min(1.0));
+                log::info!("Ambient light: {:?}", self.ambient);
+            }
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                self.ambient /= 1.25;
+                log::info!("Ambient light: {:?}", self.ambient);
+            }
+            _ => {
+                let Some(index) = number_keys.iter().position(|k| k == key) else {
+                    return;
+                };
+                if let Some(light) = self.lights.get_mut(index) {
+                    light.enabled = !light.enabled;
+                    log::info!("Light {} enabled: {}", index + 1, light.enabled);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+mod light;
+
+use anyhow::{anyhow, Result};
+
+use nalgebra_glm as glm;
+
+pub use light::{LightKind, Lighting, MAX_LIGHTS};
+
+/// a range of the shared index buffer, which is drawn with a single draw call
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Mesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    // added to each index before the vertex is fetched, so meshes can be appended
+    // to the vertex buffer without rewriting their indices
+    pub vertex_offset: i32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Material {
+    // index into the textures of the renderer
+    pub texture: usize,
+    // multiplied with the color of the texture
+    pub base_color_factor: glm::Vec4,
+    // imported along with the base color, but not used by the blinn-phong shading
+    #[allow(dead_code)]
+    pub metallic_factor: f32,
+    #[allow(dead_code)]
+    pub roughness_factor: f32,
+}
+
+impl Material {
+    /// a material, which shows the texture unchanged
+    pub fn textured(texture: usize) -> Self {
+        Self {
+            texture,
+            base_color_factor: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            metallic_factor: 0.0,
+            roughness_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    // only used to identify nodes while debugging
+    #[allow(dead_code)]
+    pub name: String,
+    // parents are always stored before their children
+    pub parent: Option<usize>,
+    // transformation relative to the parent (or the world, if there is no parent)
+    pub transform: glm::Mat4,
+    // nodes without mesh are only used to group other nodes
+    pub mesh: Option<usize>,
+    pub material: usize,
+    pub visible: bool,
+    world_transform: glm::Mat4,
+}
+
+/// a single draw of a mesh, as produced by Scene::draws
+#[derive(Copy, Clone, Debug)]
+pub struct Draw {
+    pub mesh: Mesh,
+    pub material: Material,
+    pub transform: glm::Mat4,
+}
+
+/// hierarchy of nodes, which reference meshes and materials by index
+///
+/// nodes can only be added after their parent, so iterating the nodes in order
+/// visits every parent before its children; this allows the world transforms
+/// to be computed in a single pass
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub nodes: Vec<Node>,
+    pub lighting: Lighting,
+}
+
+impl Scene {
+    pub fn add_mesh(&mut self, mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// adds a node and returns its index; the parent, mesh and material need to
+    /// exist already
+    pub fn add_node(
+        &mut self,
+        name: &str,
+        parent: Option<usize>,
+        transform: glm::Mat4,
+        mesh: Option<usize>,
+        material: usize,
+    ) -> Result<usize> {
+        if let Some(parent) = parent.filter(|p| *p >= self.nodes.len()) {
+            return Err(anyhow!(
+                "Parent node {} of '{}' does not exist.",
+                parent,
+                name
+            ));
+        }
+
+        if let Some(mesh) = mesh.filter(|m| *m >= self.meshes.len()) {
+            return Err(anyhow!("Mesh {} of '{}' does not exist.", mesh, name));
+        }
+
+        if mesh.is_some() && material >= self.materials.len() {
+            return Err(anyhow!(
+                "Material {} of '{}' does not exist.",
+                material,
+                name
+            ));
+        }
+
+        let world_transform = match parent {
+            Some(parent) => self.nodes[parent].world_transform * transform,
+            None => transform,
+        };
+
+        self.nodes.push(Node {
+            name: name.to_string(),
+            parent,
+            transform,
+            mesh,
+            material,
+            visible: true,
+            world_transform,
+        });
+
+        Ok(self.nodes.len() - 1)
+    }
+
+    /// propagates the local transforms down the hierarchy; needs to be called,
+    /// after the transform of a node was changed
+    pub fn update_world_transforms(&mut self) {
+        for i in 0..self.nodes.len() {
+            let world_transform = match self.nodes[i].parent {
+                Some(parent) => self.nodes[parent].world_transform * self.nodes[i].transform,
+                None => self.nodes[i].transform,
+            };
+            self.nodes[i].world_transform = world_transform;
+        }
+    }
+
+    /// whether the node or one of its ancestors is hidden
+    pub fn is_hidden(&self, node: usize) -> bool {
+        let mut current = Some(node);
+        while let Some(i) = current {
+            if !self.nodes[i].visible {
+                return true;
+            }
+            current = self.nodes[i].parent;
+        }
+        false
+    }
+
+    /// the draws of all visible nodes with a mesh, in node order; the transforms
+    /// are only up to date after update_world_transforms
+    pub fn draws(&self) -> impl Iterator<Item = Draw> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.is_hidden(*i))
+            .filter_map(|(_, n)| {
+                n.mesh.map(|mesh| Draw {
+                    mesh: self.meshes[mesh],
+                    material: self.materials[n.material],
+                    transform: n.world_transform,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(x: f32, y: f32, z: f32) -> glm::Mat4 {
+        glm::translation(&glm::vec3(x, y, z))
+    }
+
+    // a parent with two children, which all reference the same mesh
+    fn scene() -> Scene {
+        let mut scene = Scene::default();
+        let mesh = scene.add_mesh(Mesh {
+            first_index: 0,
+            index_count: 3,
+            vertex_offset: 0,
+        });
+        let material = scene.add_material(Material::textured(0));
+
+        let parent = scene
+            .add_node(
+                "parent",
+                None,
+                translation(1.0, 0.0, 0.0),
+                Some(mesh),
+                material,
+            )
+            .unwrap();
+        let child = scene
+            .add_node(
+                "child",
+                Some(parent),
+                translation(0.0, 2.0, 0.0),
+                Some(mesh),
+                material,
+            )
+            .unwrap();
+        scene
+            .add_node(
+                "grandchild",
+                Some(child),
+                glm::scaling(&glm::vec3(2.0, 2.0, 2.0)),
+                Some(mesh),
+                material,
+            )
+            .unwrap();
+        scene
+    }
+
+    #[test]
+    fn world_transforms_are_propagated() {
+        let mut scene = scene();
+        let transforms = |scene: &Scene| scene.draws().map(|d| d.transform).collect::<Vec<_>>();
+
+        assert_eq!(
+            transforms(&scene)[2],
+            translation(1.0, 2.0, 0.0) * glm::scaling(&glm::vec3(2.0, 2.0, 2.0))
+        );
+
+        // only the local transform changes, until the world transforms are updated
+        scene.nodes[0].transform = translation(0.0, 0.0, 3.0);
+        assert_eq!(transforms(&scene)[1], translation(1.0, 2.0, 0.0));
+
+        scene.update_world_transforms();
+        let transforms = transforms(&scene);
+        assert_eq!(transforms[0], translation(0.0, 0.0, 3.0));
+        assert_eq!(transforms[1], translation(0.0, 2.0, 3.0));
+        assert_eq!(
+            transforms[2],
+            translation(0.0, 2.0, 3.0) * glm::scaling(&glm::vec3(2.0, 2.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn hidden_nodes_hide_their_descendants() {
+        let mut scene = scene();
+        assert_eq!(scene.draws().count(), 3);
+
+        scene.nodes[1].visible = false;
+        assert!(!scene.is_hidden(0));
+        assert!(scene.is_hidden(1));
+        assert!(scene.is_hidden(2));
+        assert_eq!(scene.draws().count(), 1);
+
+        scene.nodes[1].visible = true;
+        scene.nodes[0].visible = false;
+        assert_eq!(scene.draws().count(), 0);
+    }
+
+    #[test]
+    fn nodes_need_existing_references() {
+        let mut scene = scene();
+        let identity = glm::Mat4::identity();
+
+        assert!(scene.add_node("a", Some(3), identity, None, 0).is_err());
+        assert!(scene.add_node("b", None, identity, Some(1), 0).is_err());
+        assert!(scene.add_node("c", None, identity, Some(0), 1).is_err());
+        // the material of a node without mesh is never used
+        assert!(scene.add_node("d", None, identity, None, 1).is_ok());
+        assert_eq!(scene.nodes.len(), 4);
+    }
+}
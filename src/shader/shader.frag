@@ -9,10 +9,132 @@ layout(location = 0) out vec4 outColor;
 // vertex shader, will be linked together exclusively by the location parameter
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+layout(location = 4) in vec4 fragLightSpace;
 
-layout(binding = 1) uniform sampler2D texSampler;
+// the texture and the sampler are bound separately (the shaders are compiled
+// with naga, which does not support combined image samplers); the sampler is
+// shared by all textures
+layout(binding = 1) uniform texture2D tex;
+layout(binding = 2) uniform sampler texSampler;
+
+// depth of the scene as seen from the light, which casts shadows; the sampler
+// compares the depth of the fragment with the stored depth
+layout(binding = 4) uniform texture2D shadowMap;
+layout(binding = 5) uniform samplerShadow shadowSampler;
+
+// must match the block in the vertex shader; the base color factor of the
+// material is multiplied with the texture color
+layout(push_constant) uniform PushConstants {
+	mat4 model;
+	vec4 baseColorFactor;
+} node;
+
+const uint MAX_LIGHTS = 8;
+
+// kinds of light, stored in position.w
+const float DIRECTIONAL_LIGHT = 0.0;
+const float POINT_LIGHT = 1.0;
+const float SPOT_LIGHT = 2.0;
+
+// must match descriptor_set::LightData; only vec4 members, so there is no
+// padding in the std140 layout
+struct Light {
+	vec4 position;  // xyz: position, w: kind
+	vec4 direction; // xyz: direction, w: range
+	vec4 color;     // rgb: color, a: intensity
+	vec4 cone;      // x: cos(inner angle), y: cos(outer angle)
+};
+
+// must match descriptor_set::LightsBufferObject; updated every frame
+layout(binding = 3) uniform Lights {
+	vec4 ambient;         // rgb: ambient light, a: shininess
+	vec4 cameraPosition;  // xyz: camera position, w: specular strength
+	uvec4 count;          // x: number of lights, y: index of the shadow caster + 1
+	Light lights[MAX_LIGHTS];
+} lighting;
+
+// the fraction of the light, which reaches the fragment; the shadow map is
+// sampled around the fragment and the results are averaged (percentage closer
+// filtering), which softens the edges of the shadows
+float shadowFactor() {
+	vec3 coords = fragLightSpace.xyz / fragLightSpace.w;
+
+	// the projection of the light already maps the depth to [0, 1]; fragments
+	// beyond the far plane are never in shadow
+	if (coords.z > 1.0) {
+		return 1.0;
+	}
+	vec2 uv = coords.xy * 0.5 + 0.5;
+
+	vec2 texelSize = 1.0 / vec2(textureSize(sampler2DShadow(shadowMap, shadowSampler), 0));
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			vec2 offset = vec2(x, y) * texelSize;
+			lit += texture(sampler2DShadow(shadowMap, shadowSampler), vec3(uv + offset, coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+
+// diffuse and specular contribution of a single light (blinn-phong)
+vec3 shade(Light light, vec3 normal, vec3 viewDir) {
+	float kind = light.position.w;
+
+	vec3 lightDir;
+	float attenuation = 1.0;
+	if (kind == DIRECTIONAL_LIGHT) {
+		lightDir = -light.direction.xyz;
+	} else {
+		vec3 toLight = light.position.xyz - fragPosition;
+		float distance = length(toLight);
+		lightDir = toLight / distance;
+
+		// falls off smoothly to 0 at the range of the light
+		float range = light.direction.w;
+		float falloff = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+		attenuation = falloff * falloff / (distance * distance + 1.0);
+
+		if (kind == SPOT_LIGHT) {
+			float cosAngle = dot(-lightDir, light.direction.xyz);
+			attenuation *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+		}
+	}
+
+	float diffuse = max(dot(normal, lightDir), 0.0);
+
+	// the half vector replaces the reflection vector of the phong model
+	vec3 halfDir = normalize(lightDir + viewDir);
+	float specular = diffuse > 0.0
+		? pow(max(dot(normal, halfDir), 0.0), lighting.ambient.a) * lighting.cameraPosition.w
+		: 0.0;
+
+	return light.color.rgb * light.color.a * attenuation * (diffuse + specular);
+}
 
 // called for every fragment
 void main() {
-	outColor = texture(texSampler, fragTexCoord);
+	vec4 albedo = texture(sampler2D(tex, texSampler), fragTexCoord) * node.baseColorFactor;
+
+	vec3 normal = normalize(fragNormal);
+	vec3 viewDir = normalize(lighting.cameraPosition.xyz - fragPosition);
+
+	// the quads of the built-in geometry are visible from both sides
+	if (!gl_FrontFacing) {
+		normal = -normal;
+	}
+
+	// only a single light casts shadows
+	uint shadowCaster = lighting.count.y;
+	float shadow = shadowCaster > 0 ? shadowFactor() : 1.0;
+
+	vec3 light = lighting.ambient.rgb;
+	for (uint i = 0; i < min(lighting.count.x, MAX_LIGHTS); i++) {
+		vec3 contribution = shade(lighting.lights[i], normal, viewDir);
+		light += i + 1 == shadowCaster ? contribution * shadow : contribution;
+	}
+
+	outColor = vec4(albedo.rgb * light, albedo.a);
 }
@@ -1,6 +1,6 @@
 #version 450
 
-// will be updated every frame to make things spin
+// camera matrices, which are updated every frame
 // similar to the location directive for attributes
 // 
 // it is possible to bind multiple descriptor sets simultaneously;
@@ -11,11 +11,19 @@
 // stay the same for every object in different sets (more efficient, than 
 // rebinding all descriptor sets across all draw calls)
 layout(binding = 0) uniform UniformBufferObject {
-	mat4 model;
 	mat4 view;
 	mat4 proj;
+	// projects world space into the shadow map (see shadow.vert)
+	mat4 lightViewProj;
 } ubo;
 
+// transformation of the scene node, which is currently drawn; pushed before
+// each draw call
+layout(push_constant) uniform PushConstants {
+	mat4 model;
+	vec4 baseColorFactor;
+} node;
+
 // these are vertex attributes, they are defined for each vertex
 // the location = x notation assigns indices to the inputs, so we can 
 // reference them
@@ -25,9 +33,15 @@ layout(binding = 0) uniform UniformBufferObject {
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+// position and normal in world space, where the lights are defined
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+// position in the clip space of the light, which casts shadows
+layout(location = 4) out vec4 fragLightSpace;
 
 
 // invoked on every vertex
@@ -36,7 +50,13 @@ void main() {
 
 	// add dummy z and w coordinates
 	// gl_Position is the builtin output of this vertex shader
-	gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
+	vec4 worldPosition = node.model * vec4(inPosition, 1.0);
+	gl_Position = ubo.proj * ubo.view * worldPosition;
 	fragColor = inColor;
 	fragTexCoord = inTexCoord;
+	fragPosition = worldPosition.xyz;
+	fragLightSpace = ubo.lightViewProj * worldPosition;
+	// the nodes can be scaled non-uniformly, so the normals need the inverse
+	// transpose of the model matrix to stay perpendicular to the surface
+	fragNormal = normalize(transpose(inverse(mat3(node.model))) * inNormal);
 }
@@ -0,0 +1,24 @@
+#version 450
+
+// renders the depth of the scene from the view of the light, which casts
+// shadows, into the shadow map; there is no fragment shader, as only the depth
+// is needed
+
+// must match the block in shader.vert
+layout(binding = 0) uniform UniformBufferObject {
+	mat4 view;
+	mat4 proj;
+	mat4 lightViewProj;
+} ubo;
+
+// must match the block in shader.vert
+layout(push_constant) uniform PushConstants {
+	mat4 model;
+	vec4 baseColorFactor;
+} node;
+
+layout(location = 0) in vec3 inPosition;
+
+void main() {
+	gl_Position = ubo.lightViewProj * node.model * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+
+// the cube map of the skybox with its own sampler
+layout(set = 1, binding = 0) uniform textureCube skybox;
+layout(set = 1, binding = 1) uniform sampler skyboxSampler;
+
+void main() {
+	// the scene uses the z-axis as up direction, cube maps the y-axis
+	vec3 direction = vec3(fragDirection.x, fragDirection.z, -fragDirection.y);
+	outColor = texture(samplerCube(skybox, skyboxSampler), direction);
+}
@@ -0,0 +1,27 @@
+#version 450
+
+// draws a triangle, which covers the whole screen, and passes the direction of
+// the camera ray through each corner to the fragment shader; only the rotation
+// of the camera is used, so the sky is infinitely far away
+
+// must match the block in shader.vert
+layout(binding = 0) uniform UniformBufferObject {
+	mat4 view;
+	mat4 proj;
+	mat4 lightViewProj;
+} ubo;
+
+layout(location = 0) out vec3 fragDirection;
+
+void main() {
+	// the corners (-1, -1), (3, -1) and (-1, 3) in clip space
+	vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+	// at the far plane, behind everything else
+	gl_Position = vec4(position, 1.0, 1.0);
+
+	// the ray in view space; the projection is symmetric, so only its scale
+	// has to be undone (this includes the flipped y-axis)
+	vec3 viewDirection = vec3(position.x / ubo.proj[0][0], position.y / ubo.proj[1][1], -1.0);
+	// the inverse of the rotation is its transpose
+	fragDirection = transpose(mat3(ubo.view)) * viewDirection;
+}
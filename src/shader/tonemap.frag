@@ -0,0 +1,44 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// the scene in linear HDR colors
+layout(binding = 0) uniform texture2D hdrImage;
+layout(binding = 1) uniform sampler hdrSampler;
+
+// must match tonemap::ToneMapConstants
+layout(push_constant) uniform ToneMapping {
+	float exposure;
+	uint operator;
+} toneMapping;
+
+// must match tonemap::operator_index
+const uint CLAMP = 0u;
+const uint REINHARD = 1u;
+const uint ACES = 2u;
+
+// the fit of the ACES filmic curve by Krzysztof Narkowicz
+vec3 aces(vec3 x) {
+	const float a = 2.51;
+	const float b = 0.03;
+	const float c = 2.43;
+	const float d = 0.59;
+	const float e = 0.14;
+	return (x * (a * x + b)) / (x * (c * x + d) + e);
+}
+
+void main() {
+	vec4 hdr = texture(sampler2D(hdrImage, hdrSampler), fragTexCoord);
+	vec3 color = hdr.rgb * toneMapping.exposure;
+
+	if (toneMapping.operator == REINHARD) {
+		color = color / (1.0 + color);
+	} else if (toneMapping.operator == ACES) {
+		color = aces(color);
+	}
+
+	// the swapchain image has an sRGB format, so the colors stay linear here
+	outColor = vec4(clamp(color, 0.0, 1.0), clamp(hdr.a, 0.0, 1.0));
+}
@@ -0,0 +1,14 @@
+#version 450
+
+// draws a triangle, which covers the whole screen, for the tone mapping pass
+
+layout(location = 0) out vec2 fragTexCoord;
+
+void main() {
+	// the corners (-1, -1), (3, -1) and (-1, 3) in clip space
+	vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+	gl_Position = vec4(position, 0.0, 1.0);
+	// the HDR image has the size of the swapchain image, so the texture
+	// coordinates are the position in the range of 0.0 to 1.0
+	fragTexCoord = position * 0.5 + 0.5;
+}
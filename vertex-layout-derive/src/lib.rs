@@ -0,0 +1,287 @@
+//! `#[derive(VertexLayout)]` generates the vertex binding and attribute
+//! descriptions of a `#[repr(C)]` struct, so they can't get out of sync with the
+//! struct definition
+//!
+//! the offsets are taken from `offset_of!` and the formats are derived from the
+//! field types (f32, i32, u32, arrays of them and the glm vector and matrix
+//! types); matrices use one location per column
+//!
+//! ```ignore
+//! #[repr(C)]
+//! #[derive(VertexLayout)]
+//! struct Vertex {
+//!     pos: glm::Vec3,       // location 0
+//!     color: glm::Vec3,     // location 1
+//!     tex_coord: glm::Vec2, // location 2
+//! }
+//!
+//! // per instance data in a second vertex buffer
+//! #[repr(C)]
+//! #[derive(VertexLayout)]
+//! #[vertex_layout(binding = 1, rate = instance, first_location = 3)]
+//! struct Instance {
+//!     model: glm::Mat4, // locations 3 to 6
+//!     #[vertex_layout(format = R8G8B8A8_UNORM)]
+//!     tint: [u8; 4],    // location 7
+//!     #[vertex_layout(skip)]
+//!     id: u64,
+//! }
+//! ```
+//!
+//! the generated code implements `crate::render::vertex::VertexLayout`, so the
+//! derive can only be used inside of the renderer crate
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Expr, ExprLit, Fields, Ident, Lit, Result,
+    Type,
+};
+
+#[proc_macro_derive(VertexLayout, attributes(vertex_layout))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+// options of the struct
+struct Layout {
+    binding: u32,
+    instance: bool,
+    first_location: u32,
+}
+
+// options of a field
+#[derive(Default)]
+struct FieldOptions {
+    skip: bool,
+    format: Option<Ident>,
+}
+
+// a shader input type: `columns` consecutive locations of the given format,
+// each `column_size` bytes after the previous one
+struct InputType {
+    format: Ident,
+    columns: u32,
+    column_size: u32,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    if !has_repr_c(&input.attrs) {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "VertexLayout requires #[repr(C)], otherwise the field order is not guaranteed",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "VertexLayout requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "VertexLayout can only be derived for structs",
+            ))
+        }
+    };
+
+    let layout = parse_layout(&input.attrs)?;
+    let binding = layout.binding;
+
+    let mut location = layout.first_location;
+    let mut attributes = Vec::new();
+
+    for field in fields {
+        let options = parse_field_options(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+
+        let name = field.ident.as_ref().unwrap();
+        let input = match options.format {
+            Some(format) => InputType {
+                format,
+                columns: 1,
+                column_size: 0,
+            },
+            None => input_type(&field.ty)?,
+        };
+
+        for column in 0..input.columns {
+            let format = &input.format;
+            let column_offset = column * input.column_size;
+            attributes.push(quote! {
+                ::vulkanalia::vk::VertexInputAttributeDescription {
+                    location: #location,
+                    binding: #binding,
+                    format: ::vulkanalia::vk::Format::#format,
+                    offset: (::std::mem::offset_of!(Self, #name) as u32) + #column_offset,
+                }
+            });
+            location += 1;
+        }
+    }
+
+    let input_rate = if layout.instance {
+        quote!(::vulkanalia::vk::VertexInputRate::INSTANCE)
+    } else {
+        quote!(::vulkanalia::vk::VertexInputRate::VERTEX)
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics crate::render::vertex::VertexLayout for #ident #ty_generics #where_clause {
+            fn binding_description() -> ::vulkanalia::vk::VertexInputBindingDescription {
+                ::vulkanalia::vk::VertexInputBindingDescription {
+                    binding: #binding,
+                    stride: ::std::mem::size_of::<Self>() as u32,
+                    input_rate: #input_rate,
+                }
+            }
+
+            fn attribute_descriptions() -> ::std::vec::Vec<::vulkanalia::vk::VertexInputAttributeDescription> {
+                ::std::vec![#(#attributes),*]
+            }
+        }
+    })
+}
+
+fn has_repr_c(attrs: &[Attribute]) -> bool {
+    attrs.iter().filter(|a| a.path().is_ident("repr")).any(|a| {
+        let mut c = false;
+        let _ = a.parse_nested_meta(|meta| {
+            c |= meta.path.is_ident("C");
+            Ok(())
+        });
+        c
+    })
+}
+
+fn parse_layout(attrs: &[Attribute]) -> Result<Layout> {
+    let mut layout = Layout {
+        binding: 0,
+        instance: false,
+        first_location: 0,
+    };
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("vertex_layout")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("binding") {
+                layout.binding = meta.value()?.parse::<syn::LitInt>()?.base10_parse()?;
+            } else if meta.path.is_ident("first_location") {
+                layout.first_location = meta.value()?.parse::<syn::LitInt>()?.base10_parse()?;
+            } else if meta.path.is_ident("rate") {
+                let rate = meta.value()?.parse::<Ident>()?;
+                layout.instance = match rate.to_string().as_str() {
+                    "vertex" => false,
+                    "instance" => true,
+                    _ => return Err(meta.error("expected `vertex` or `instance`")),
+                };
+            } else {
+                return Err(meta.error("unknown vertex_layout option"));
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(layout)
+}
+
+fn parse_field_options(attrs: &[Attribute]) -> Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("vertex_layout")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("format") {
+                options.format = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unknown vertex_layout option"));
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(options)
+}
+
+// maps the type of a field to the format of the shader input; glm types are
+// matched by their name, so `glm::Vec3` and `nalgebra_glm::Vec3` both work
+fn input_type(ty: &Type) -> Result<InputType> {
+    let unsupported = || {
+        syn::Error::new_spanned(
+            ty,
+            "unsupported vertex attribute type, use #[vertex_layout(format = ...)]",
+        )
+    };
+
+    let (scalar, size, columns) = match ty {
+        Type::Path(path) => {
+            let name = path
+                .path
+                .segments
+                .last()
+                .ok_or_else(unsupported)?
+                .ident
+                .to_string();
+            match name.as_str() {
+                "f32" => ("SFLOAT", 1, 1),
+                "i32" => ("SINT", 1, 1),
+                "u32" => ("UINT", 1, 1),
+                "Vec2" => ("SFLOAT", 2, 1),
+                "Vec3" => ("SFLOAT", 3, 1),
+                "Vec4" => ("SFLOAT", 4, 1),
+                "IVec2" => ("SINT", 2, 1),
+                "IVec3" => ("SINT", 3, 1),
+                "IVec4" => ("SINT", 4, 1),
+                "UVec2" => ("UINT", 2, 1),
+                "UVec3" => ("UINT", 3, 1),
+                "UVec4" => ("UINT", 4, 1),
+                "Mat2" => ("SFLOAT", 2, 2),
+                "Mat3" => ("SFLOAT", 3, 3),
+                "Mat4" => ("SFLOAT", 4, 4),
+                _ => return Err(unsupported()),
+            }
+        }
+        Type::Array(array) => {
+            let scalar = match &*array.elem {
+                Type::Path(p) if p.path.is_ident("f32") => "SFLOAT",
+                Type::Path(p) if p.path.is_ident("i32") => "SINT",
+                Type::Path(p) if p.path.is_ident("u32") => "UINT",
+                _ => return Err(unsupported()),
+            };
+            let size = match &array.len {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Int(len), ..
+                }) => len.base10_parse::<u32>()?,
+                _ => return Err(unsupported()),
+            };
+            if !(1..=4).contains(&size) {
+                return Err(unsupported());
+            }
+            (scalar, size, 1)
+        }
+        _ => return Err(unsupported()),
+    };
+
+    let channels = ["R32", "R32G32", "R32G32B32", "R32G32B32A32"][size as usize - 1];
+
+    Ok(InputType {
+        format: Ident::new(&format!("{}_{}", channels, scalar), Span::call_site()),
+        columns,
+        column_size: size * 4,
+    })
+}
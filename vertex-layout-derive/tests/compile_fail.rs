@@ -0,0 +1,8 @@
+// each case in tests/ui has to fail with the error in its .stderr file; the
+// layouts of valid structs are tested in the renderer (see render::vertex), as
+// the generated code implements a trait of the renderer
+#[test]
+fn compile_fail() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
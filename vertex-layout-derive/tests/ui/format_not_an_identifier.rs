@@ -0,0 +1,10 @@
+use vertex_layout_derive::VertexLayout;
+
+#[repr(C)]
+#[derive(VertexLayout)]
+struct Vertex {
+    #[vertex_layout(format = "R8G8B8A8_UNORM")]
+    tint: [u8; 4],
+}
+
+fn main() {}
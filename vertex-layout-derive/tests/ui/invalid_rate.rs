@@ -0,0 +1,10 @@
+use vertex_layout_derive::VertexLayout;
+
+#[repr(C)]
+#[derive(VertexLayout)]
+#[vertex_layout(rate = fragment)]
+struct Vertex {
+    pos: [f32; 3],
+}
+
+fn main() {}
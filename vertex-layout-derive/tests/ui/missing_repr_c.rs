@@ -0,0 +1,8 @@
+use vertex_layout_derive::VertexLayout;
+
+#[derive(VertexLayout)]
+struct Vertex {
+    pos: [f32; 3],
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use vertex_layout_derive::VertexLayout;
+
+#[repr(C)]
+#[derive(VertexLayout)]
+struct Vertex {
+    weights: [f32; 5],
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use vertex_layout_derive::VertexLayout;
+
+#[repr(C)]
+#[derive(VertexLayout)]
+struct Vertex {
+    #[vertex_layout(normalized)]
+    pos: [f32; 3],
+}
+
+fn main() {}
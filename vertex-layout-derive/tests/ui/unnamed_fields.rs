@@ -0,0 +1,7 @@
+use vertex_layout_derive::VertexLayout;
+
+#[repr(C)]
+#[derive(VertexLayout)]
+struct Vertex([f32; 3], [f32; 2]);
+
+fn main() {}
@@ -0,0 +1,11 @@
+use vertex_layout_derive::VertexLayout;
+
+#[repr(C)]
+#[derive(VertexLayout)]
+struct Vertex {
+    pos: [f32; 3],
+    // needs an explicit format
+    tint: [u8; 4],
+}
+
+fn main() {}